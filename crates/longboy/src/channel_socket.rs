@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    io::Result,
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+};
+
+use fnv::FnvHashMap;
+
+use crate::{supports_version, ChannelStats, Layout};
+
+// Every datagram and heartbeat carries its schema's channel right after the version, which is
+// all a socket shared by several schemas needs to hand each its own.
+pub(crate) const CHANNEL_OFFSET: usize = 1;
+
+// A channel that falls behind loses its oldest packets rather than holding on to everything.
+const MAX_QUEUED: usize = 256;
+
+// A byte over the largest datagram, so anything bigger still shows up as too big rather than
+// being cut down to size.
+const PACKET_SIZE: usize = Layout::MAX_DATAGRAM_SIZE + 1;
+
+// One socket several schemas send and receive on.  Whichever of their tasks reads first drains
+// the socket, queueing what it finds for each channel.
+pub(crate) struct SharedSocket
+{
+    socket: UdpSocket,
+    channels: Mutex<Channels>,
+}
+
+struct Channels
+{
+    owner: Option<u8>,
+    queues: FnvHashMap<u8, Queue>,
+    // Buffers move between the pool and the queues without copying what's in them.
+    #[allow(clippy::vec_box)]
+    buffers: Vec<Box<[u8; PACKET_SIZE]>>,
+}
+
+struct Queue
+{
+    packets: VecDeque<Packet>,
+    stats: Arc<Mutex<ChannelStats>>,
+}
+
+struct Packet
+{
+    buffer: Box<[u8; PACKET_SIZE]>,
+    len: usize,
+    socket_addr: SocketAddr,
+}
+
+pub(crate) struct ChannelSocket
+{
+    shared: Arc<SharedSocket>,
+    channel_id: u8,
+}
+
+impl SharedSocket
+{
+    pub(crate) fn new(socket: UdpSocket) -> Arc<Self>
+    {
+        Arc::new(Self {
+            socket,
+            channels: Mutex::new(Channels {
+                owner: None,
+                queues: FnvHashMap::default(),
+                buffers: Vec::new(),
+            }),
+        })
+    }
+
+    pub(crate) fn port(&self) -> u16
+    {
+        self.socket.local_addr().unwrap().port()
+    }
+
+    // Nothing when the channel is already taken on this socket.  Packets the channel loses for
+    // falling behind are counted in its stats.
+    pub(crate) fn channel(self: &Arc<Self>, channel_id: u8, stats: Arc<Mutex<ChannelStats>>) -> Option<ChannelSocket>
+    {
+        let mut channels = self.channels.lock().unwrap();
+        if channels.queues.contains_key(&channel_id)
+        {
+            return None;
+        }
+        channels.owner.get_or_insert(channel_id);
+        channels.queues.insert(
+            channel_id,
+            Queue {
+                packets: VecDeque::new(),
+                stats,
+            },
+        );
+
+        Some(ChannelSocket {
+            shared: self.clone(),
+            channel_id,
+        })
+    }
+}
+
+impl ChannelSocket
+{
+    pub(crate) fn socket(&self) -> &UdpSocket
+    {
+        &self.shared.socket
+    }
+
+    pub(crate) fn send_to(&self, buffer: &[u8], socket_addr: SocketAddr) -> Result<usize>
+    {
+        self.shared.socket.send_to(buffer, socket_addr)
+    }
+
+    pub(crate) fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddr)>
+    {
+        loop
+        {
+            // Take what's queued for us, or a buffer to read the next packet into.
+            let mut packet = {
+                let mut channels = self.shared.channels.lock().unwrap();
+
+                // Sockets only one channel uses are read straight from.
+                if channels.queues.len() == 1
+                {
+                    drop(channels);
+                    return self.shared.socket.recv_from(buffer);
+                }
+
+                if let Some(queued) = channels.queues.get_mut(&self.channel_id).unwrap().packets.pop_front()
+                {
+                    let len = std::cmp::min(queued.len, buffer.len());
+                    buffer[0..len].copy_from_slice(&queued.buffer[0..len]);
+                    channels.buffers.push(queued.buffer);
+                    return Ok((len, queued.socket_addr));
+                }
+                channels.buffers.pop().unwrap_or_else(|| Box::new([0; PACKET_SIZE]))
+            };
+
+            // Read without holding on to the channels, so the others can still take what's
+            // queued for them in the meantime.
+            let result = self.shared.socket.recv_from(packet.as_mut_slice());
+
+            let mut channels = self.shared.channels.lock().unwrap();
+            let channels = &mut *channels;
+            let (len, socket_addr) = match result
+            {
+                Ok(received) => received,
+                Err(error) =>
+                {
+                    channels.buffers.push(packet);
+                    return Err(error);
+                }
+            };
+
+            // Packets that don't name a channel on this socket go to the channel that opened it,
+            // so they still show up in its stats.  The channel is only known for versions we
+            // support.
+            let channel_id = match packet[0..len].get(CHANNEL_OFFSET)
+            {
+                Some(channel_id) if supports_version(packet[0]) && channels.queues.contains_key(channel_id) =>
+                {
+                    *channel_id
+                }
+                _ => channels.owner.unwrap(),
+            };
+            let queue = channels.queues.get_mut(&channel_id).unwrap();
+            if queue.packets.len() == MAX_QUEUED
+            {
+                let dropped = queue.packets.pop_front().unwrap();
+                channels.buffers.push(dropped.buffer);
+                queue.stats.lock().unwrap().packets_dropped += 1;
+            }
+            queue.packets.push_back(Packet {
+                buffer: packet,
+                len,
+                socket_addr,
+            });
+        }
+    }
+}
@@ -0,0 +1,5 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelStats
+{
+    pub packets_dropped: u64,
+}
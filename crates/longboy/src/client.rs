@@ -1,25 +1,48 @@
+mod client_messages;
 mod client_session;
+mod client_session_event;
 mod client_to_server_sender;
 mod server_to_client_receiver;
+mod server_to_client_snapshot_receiver;
 
 // API
 pub use self::client_session::*;
 
 // Internal
-pub(crate) use self::{client_to_server_sender::*, server_to_client_receiver::*};
+pub(crate) use self::{
+    client_messages::*, client_session_event::*, client_to_server_sender::*, server_to_client_receiver::*,
+    server_to_client_snapshot_receiver::*,
+};
 
-use crate::{ClientToServerSchema, Constants, Mirroring, Runtime, RuntimeTask, ServerToClientSchema, Sink, Source};
+use crate::{
+    check_baselines, check_max_queued_messages, check_path_mtu, datagram_size, layout, message_channel,
+    oversized_schemas, snapshot_layout, ChannelStats, Cipher, ClientToServerSchema, DynSink, DynSource, MessageRoute,
+    MessageSchema, MessageSink, MessageSource, MessageStats, MetadataSink, Mirroring, ReceiverStats, Runtime,
+    RuntimeTask, ServerToClientSchema, SharedSocket, SizedSink, SizedSource, SnapshotSchema, SnapshotSink,
+    SnapshotStats, VariableSource,
+};
 use anyhow::{anyhow, Context, Result};
 use enum_map::{enum_map, EnumMap};
-use fnv::FnvHashSet;
-use std::net::{SocketAddr, UdpSocket};
+use flume::Sender as FlumeSender;
+use fnv::{FnvHashMap, FnvHashSet};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+};
+use tokio::task::AbortHandle;
 
 pub struct Client
 {
-    #[allow(unused)]
     session: ClientSession,
+    key_rotations: AbortHandle,
     #[allow(unused)]
     runtime: Box<dyn Runtime>,
+
+    datagram_sizes: Box<[(&'static str, usize)]>,
+    channel_stats: FnvHashMap<&'static str, Arc<Mutex<ChannelStats>>>,
+    receiver_stats: FnvHashMap<&'static str, Arc<Mutex<ReceiverStats>>>,
+    snapshot_stats: FnvHashMap<&'static str, Arc<Mutex<SnapshotStats>>>,
+    message_stats: FnvHashMap<&'static str, Arc<Mutex<MessageStats>>>,
 }
 
 pub struct ClientBuilder
@@ -27,7 +50,16 @@ pub struct ClientBuilder
     session: ClientSession,
     runtime: Box<dyn Runtime>,
 
-    ports: FnvHashSet<u16>,
+    channels: FnvHashSet<u8>,
+    lane_sockets: Option<EnumMap<Mirroring, Arc<SharedSocket>>>,
+    receiver_socket: Option<Arc<SharedSocket>>,
+    datagram_sizes: Vec<(&'static str, usize)>,
+    channel_stats: FnvHashMap<&'static str, Arc<Mutex<ChannelStats>>>,
+    receiver_stats: FnvHashMap<&'static str, Arc<Mutex<ReceiverStats>>>,
+    snapshot_stats: FnvHashMap<&'static str, Arc<Mutex<SnapshotStats>>>,
+    message_stats: FnvHashMap<&'static str, Arc<Mutex<MessageStats>>>,
+    session_senders: Vec<FlumeSender<ClientSessionEvent>>,
+    message_routes: Vec<(u8, MessageRoute)>,
     tasks: Vec<Box<dyn RuntimeTask>>,
 }
 
@@ -38,123 +70,458 @@ impl Client
         ClientBuilder {
             session,
             runtime,
-            ports: FnvHashSet::default(),
+            channels: FnvHashSet::default(),
+            lane_sockets: None,
+            receiver_socket: None,
+            datagram_sizes: Vec::new(),
+            channel_stats: FnvHashMap::default(),
+            receiver_stats: FnvHashMap::default(),
+            snapshot_stats: FnvHashMap::default(),
+            message_stats: FnvHashMap::default(),
+            session_senders: Vec::new(),
+            message_routes: Vec::new(),
             tasks: Vec::new(),
         }
     }
+
+    pub fn channel_stats(&self, name: &str) -> Option<ChannelStats>
+    {
+        self.channel_stats.get(name).map(|stats| *stats.lock().unwrap())
+    }
+
+    pub fn receiver_stats(&self, name: &str) -> Option<ReceiverStats>
+    {
+        self.receiver_stats.get(name).map(|stats| *stats.lock().unwrap())
+    }
+
+    pub fn snapshot_stats(&self, name: &str) -> Option<SnapshotStats>
+    {
+        self.snapshot_stats.get(name).map(|stats| *stats.lock().unwrap())
+    }
+
+    pub fn message_stats(&self, name: &str) -> Option<MessageStats>
+    {
+        self.message_stats.get(name).map(|stats| *stats.lock().unwrap())
+    }
+
+    pub fn oversized_schemas(&self) -> Vec<&'static str>
+    {
+        oversized_schemas(&self.datagram_sizes, self.session.path_mtu())
+    }
+}
+
+impl Drop for Client
+{
+    fn drop(&mut self)
+    {
+        self.key_rotations.abort();
+    }
 }
 
 impl ClientBuilder
 {
-    pub fn sender<SourceType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn sender<SourceType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
         self,
         schema: &ClientToServerSchema,
         source: SourceType,
     ) -> Result<Self>
     where
-        SourceType: Source<SIZE>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+        SourceType: VariableSource<SIZE>,
+        CipherType: Cipher,
     {
-        let sockets = enum_map! {
-            Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?,
-            Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?,
-            Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?,
-        };
+        self.dyn_sender::<_, CipherType>(schema, SIZE, WINDOW_SIZE, SizedSource(source))
+    }
 
-        self.sender_with_sockets::<SourceType, SIZE, WINDOW_SIZE>(schema, sockets, source)
+    pub fn sender_with_sockets<SourceType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
+        self,
+        schema: &ClientToServerSchema,
+        sockets: EnumMap<Mirroring, UdpSocket>,
+        source: SourceType,
+    ) -> Result<Self>
+    where
+        SourceType: VariableSource<SIZE>,
+        CipherType: Cipher,
+    {
+        self.dyn_sender_with_sockets::<_, CipherType>(schema, SIZE, WINDOW_SIZE, sockets, SizedSource(source))
     }
 
-    pub fn sender_with_sockets<SourceType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn dyn_sender<SourceType, CipherType>(
         mut self,
         schema: &ClientToServerSchema,
+        size: usize,
+        window_size: usize,
+        source: SourceType,
+    ) -> Result<Self>
+    where
+        SourceType: DynSource,
+        CipherType: Cipher,
+    {
+        // Senders share one socket per lane, told apart by channel.
+        let sockets = match self.lane_sockets.as_ref()
+        {
+            Some(sockets) => sockets.clone(),
+            None =>
+            {
+                let sockets = enum_map! {
+                    Mirroring::AudioVideo => SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?),
+                    Mirroring::Background => SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?),
+                    Mirroring::Voice => SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?),
+                };
+                self.lane_sockets = Some(sockets.clone());
+                sockets
+            }
+        };
+
+        self.add_sender::<SourceType, CipherType>(schema, size, window_size, sockets, source)
+    }
+
+    pub fn dyn_sender_with_sockets<SourceType, CipherType>(
+        self,
+        schema: &ClientToServerSchema,
+        size: usize,
+        window_size: usize,
         sockets: EnumMap<Mirroring, UdpSocket>,
         source: SourceType,
     ) -> Result<Self>
     where
-        SourceType: Source<SIZE>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+        SourceType: DynSource,
+        CipherType: Cipher,
     {
-        if !self.ports.insert(schema.mapper_port)
+        let sockets = sockets.map(|_, socket| SharedSocket::new(socket));
+
+        self.add_sender::<SourceType, CipherType>(schema, size, window_size, sockets, source)
+    }
+
+    fn add_sender<SourceType, CipherType>(
+        mut self,
+        schema: &ClientToServerSchema,
+        size: usize,
+        window_size: usize,
+        sockets: EnumMap<Mirroring, Arc<SharedSocket>>,
+        source: SourceType,
+    ) -> Result<Self>
+    where
+        SourceType: DynSource,
+        CipherType: Cipher,
+    {
+        if schema.mapper_port == schema.port
         {
-            return Err(anyhow!("Reused port {}", schema.mapper_port)).context(schema.name);
+            return Err(anyhow!("Reused port {}", schema.port)).context(schema.name);
         }
-        if !self.ports.insert(schema.port)
+        if !self.channels.insert(schema.channel_id)
         {
-            return Err(anyhow!("Reused port {}", schema.port)).context(schema.name);
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
         }
+        let layout = layout::<CipherType>(size, window_size).context(schema.name)?;
+        let datagram_size = datagram_size(&layout, schema.max_datagram_size, schema.redundancy).context(schema.name)?;
+        check_path_mtu(datagram_size, self.session.path_mtu()).context(schema.name)?;
+
+        let (session_sender, session_receiver) = flume::unbounded();
+        let channel_stats = Arc::new(Mutex::new(ChannelStats::default()));
 
-        let client_to_server_sender = ClientToServerSender::<SourceType, SIZE, WINDOW_SIZE>::new(
+        let client_to_server_sender = ClientToServerSender::<SourceType, CipherType>::new(
             format!("ClientToServerSender: {}", schema.name),
+            schema.name,
+            layout,
+            schema.channel_id,
             SocketAddr::from((self.session.ip_addr(), schema.mapper_port)),
             schema.heartbeat_period,
             SocketAddr::from((self.session.ip_addr(), schema.port)),
+            schema.encoding,
+            schema.keepalive_period,
+            schema.redundancy,
+            schema.max_datagram_size,
+            schema.ack_period,
             self.session.session_id(),
             self.session.cipher_key(),
-            sockets,
+            sockets.map(|_, socket| {
+                socket
+                    .channel(schema.channel_id, channel_stats.clone())
+                    .expect("Reused channel")
+            }),
+            session_receiver,
             source,
         )
         .context(schema.name)?;
 
+        self.datagram_sizes.push((schema.name, datagram_size));
+        self.channel_stats.insert(schema.name, channel_stats);
         self.tasks.push(Box::new(client_to_server_sender));
+        self.session_senders.push(session_sender);
         Ok(self)
     }
 
-    pub fn receiver<SinkType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn receiver<SinkType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
         self,
         schema: &ServerToClientSchema,
         sink: SinkType,
     ) -> Result<Self>
     where
-        SinkType: Sink<SIZE>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+        SinkType: MetadataSink<SIZE>,
+        CipherType: Cipher,
     {
-        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?;
+        self.dyn_receiver::<_, CipherType>(schema, SIZE, WINDOW_SIZE, SizedSink(sink))
+    }
 
-        self.receiver_with_socket::<SinkType, SIZE, WINDOW_SIZE>(schema, socket, sink)
+    pub fn receiver_with_socket<SinkType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
+        self,
+        schema: &ServerToClientSchema,
+        socket: UdpSocket,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SinkType: MetadataSink<SIZE>,
+        CipherType: Cipher,
+    {
+        self.dyn_receiver_with_socket::<_, CipherType>(schema, SIZE, WINDOW_SIZE, socket, SizedSink(sink))
     }
 
-    pub fn receiver_with_socket<SinkType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn dyn_receiver<SinkType, CipherType>(
         mut self,
         schema: &ServerToClientSchema,
+        size: usize,
+        window_size: usize,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SinkType: DynSink,
+        CipherType: Cipher,
+    {
+        let socket = self.receiver_socket(schema.name)?;
+
+        self.add_receiver::<SinkType, CipherType>(schema, size, window_size, socket, sink)
+    }
+
+    pub fn dyn_receiver_with_socket<SinkType, CipherType>(
+        self,
+        schema: &ServerToClientSchema,
+        size: usize,
+        window_size: usize,
         socket: UdpSocket,
         sink: SinkType,
     ) -> Result<Self>
     where
-        SinkType: Sink<SIZE>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+        SinkType: DynSink,
+        CipherType: Cipher,
+    {
+        self.add_receiver::<SinkType, CipherType>(schema, size, window_size, SharedSocket::new(socket), sink)
+    }
+
+    fn add_receiver<SinkType, CipherType>(
+        mut self,
+        schema: &ServerToClientSchema,
+        size: usize,
+        window_size: usize,
+        socket: Arc<SharedSocket>,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SinkType: DynSink,
+        CipherType: Cipher,
     {
-        if !self.ports.insert(schema.mapper_port)
+        if !self.channels.insert(schema.channel_id)
         {
-            return Err(anyhow!("Reused port {}", schema.mapper_port)).context(schema.name);
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
         }
+        if self.receiver_stats.contains_key(schema.name) || self.snapshot_stats.contains_key(schema.name)
+        {
+            return Err(anyhow!("Reused receiver name {}", schema.name)).context(schema.name);
+        }
+        let layout = layout::<CipherType>(size, window_size).context(schema.name)?;
+        let datagram_size = datagram_size(&layout, schema.max_datagram_size, schema.redundancy).context(schema.name)?;
+        check_path_mtu(datagram_size, self.session.path_mtu()).context(schema.name)?;
 
-        let server_to_client_receiver = ServerToClientReceiver::<SinkType, SIZE, WINDOW_SIZE>::new(
+        let (session_sender, session_receiver) = flume::unbounded();
+        let channel_stats = Arc::new(Mutex::new(ChannelStats::default()));
+        let stats = Arc::new(Mutex::new(ReceiverStats::default()));
+        let server_to_client_receiver = ServerToClientReceiver::<SinkType, CipherType>::new(
             format!("ServerToClientReceiver: {}", schema.name),
+            schema.name,
+            layout,
+            schema.channel_id,
             SocketAddr::from((self.session.ip_addr(), schema.mapper_port)),
             schema.heartbeat_period,
+            schema.ack_period,
+            schema.delivery,
+            schema.key_grace_period,
             self.session.session_id(),
             self.session.cipher_key(),
-            socket,
+            socket
+                .channel(schema.channel_id, channel_stats.clone())
+                .expect("Reused channel"),
+            session_receiver,
             sink,
+            stats.clone(),
         )
         .context(schema.name)?;
 
+        self.datagram_sizes.push((schema.name, datagram_size));
+        self.channel_stats.insert(schema.name, channel_stats);
+        self.receiver_stats.insert(schema.name, stats);
         self.tasks.push(Box::new(server_to_client_receiver));
+        self.session_senders.push(session_sender);
+        Ok(self)
+    }
+
+    pub fn snapshot_receiver<SinkType, CipherType>(
+        mut self,
+        schema: &SnapshotSchema,
+        max_snapshot_size: usize,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SinkType: SnapshotSink,
+        CipherType: Cipher,
+    {
+        let socket = self.receiver_socket(schema.name)?;
+
+        self.add_snapshot_receiver::<SinkType, CipherType>(schema, max_snapshot_size, socket, sink)
+    }
+
+    pub fn snapshot_receiver_with_socket<SinkType, CipherType>(
+        self,
+        schema: &SnapshotSchema,
+        max_snapshot_size: usize,
+        socket: UdpSocket,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SinkType: SnapshotSink,
+        CipherType: Cipher,
+    {
+        self.add_snapshot_receiver::<SinkType, CipherType>(schema, max_snapshot_size, SharedSocket::new(socket), sink)
+    }
+
+    fn add_snapshot_receiver<SinkType, CipherType>(
+        mut self,
+        schema: &SnapshotSchema,
+        max_snapshot_size: usize,
+        socket: Arc<SharedSocket>,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SinkType: SnapshotSink,
+        CipherType: Cipher,
+    {
+        if !self.channels.insert(schema.channel_id)
+        {
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
+        }
+        if self.receiver_stats.contains_key(schema.name) || self.snapshot_stats.contains_key(schema.name)
+        {
+            return Err(anyhow!("Reused receiver name {}", schema.name)).context(schema.name);
+        }
+        let layout = snapshot_layout::<CipherType>(max_snapshot_size, schema.max_datagram_size).context(schema.name)?;
+        check_path_mtu(layout.datagram_size, self.session.path_mtu()).context(schema.name)?;
+        check_baselines(schema.baselines).context(schema.name)?;
+
+        let (session_sender, session_receiver) = flume::unbounded();
+        let channel_stats = Arc::new(Mutex::new(ChannelStats::default()));
+        let stats = Arc::new(Mutex::new(SnapshotStats::default()));
+        let server_to_client_snapshot_receiver = ServerToClientSnapshotReceiver::<SinkType, CipherType>::new(
+            format!("ServerToClientSnapshotReceiver: {}", schema.name),
+            schema.name,
+            max_snapshot_size,
+            schema.max_datagram_size,
+            schema.baselines,
+            schema.channel_id,
+            SocketAddr::from((self.session.ip_addr(), schema.mapper_port)),
+            schema.heartbeat_period,
+            schema.key_grace_period,
+            schema.ack_period,
+            self.session.session_id(),
+            self.session.cipher_key(),
+            socket
+                .channel(schema.channel_id, channel_stats.clone())
+                .expect("Reused channel"),
+            session_receiver,
+            sink,
+            stats.clone(),
+        )
+        .context(schema.name)?;
+
+        self.datagram_sizes.push((schema.name, layout.datagram_size));
+        self.channel_stats.insert(schema.name, channel_stats);
+        self.snapshot_stats.insert(schema.name, stats);
+        self.tasks.push(Box::new(server_to_client_snapshot_receiver));
+        self.session_senders.push(session_sender);
         Ok(self)
     }
 
+    pub fn messages<SourceType, SinkType>(
+        mut self,
+        schema: &MessageSchema,
+        source: SourceType,
+        sink: SinkType,
+    ) -> Result<Self>
+    where
+        SourceType: MessageSource,
+        SinkType: MessageSink,
+    {
+        if !self.channels.insert(schema.channel_id)
+        {
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
+        }
+
+        check_max_queued_messages(schema.max_queued_messages).context(schema.name)?;
+
+        let (endpoint, route) = message_channel(schema.max_message_size, schema.max_queued_messages);
+
+        let stats = Arc::new(Mutex::new(MessageStats::default()));
+        let client_messages = ClientMessages::new(
+            format!("ClientMessages: {}", schema.name),
+            schema.max_message_size,
+            endpoint,
+            source,
+            sink,
+            stats.clone(),
+        );
+
+        self.message_stats.insert(schema.name, stats);
+        self.message_routes.push((schema.channel_id, route));
+        self.tasks.push(Box::new(client_messages));
+        Ok(self)
+    }
+
+    // Receivers share one socket, told apart by channel.
+    fn receiver_socket(&mut self, name: &'static str) -> Result<Arc<SharedSocket>>
+    {
+        if let Some(socket) = self.receiver_socket.as_ref()
+        {
+            return Ok(socket.clone());
+        }
+
+        let socket = SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(name)?);
+        self.receiver_socket = Some(socket.clone());
+        Ok(socket)
+    }
+
     pub fn build(mut self) -> Client
     {
+        // Message streams are only opened once nothing else can fail.
+        for (channel_id, route) in self.message_routes.into_iter()
+        {
+            self.session.open_messages(channel_id, route);
+        }
+
         for task in self.tasks.into_iter()
         {
             self.runtime.spawn(task);
         }
 
+        // Keys the Server rotates to are taken as they're announced.
+        let key_rotations = self.session.accept_keys(self.session_senders.into_boxed_slice());
+
         Client {
-            runtime: self.runtime,
             session: self.session,
+            key_rotations,
+            runtime: self.runtime,
+
+            datagram_sizes: self.datagram_sizes.into_boxed_slice(),
+            channel_stats: self.channel_stats,
+            receiver_stats: self.receiver_stats,
+            snapshot_stats: self.snapshot_stats,
+            message_stats: self.message_stats,
         }
     }
 }
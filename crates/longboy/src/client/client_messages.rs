@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{Instant, MessageEndpoint, MessageSink, MessageSource, MessageStats, RuntimeTask};
+
+pub(crate) struct ClientMessages<SourceType, SinkType>
+where
+    SourceType: MessageSource,
+    SinkType: MessageSink,
+{
+    name: String,
+    max_message_size: usize,
+
+    endpoint: MessageEndpoint,
+    buffer: Vec<u8>,
+    source: SourceType,
+    sink: SinkType,
+
+    stats: MessageStats,
+    shared_stats: Arc<Mutex<MessageStats>>,
+}
+
+impl<SourceType, SinkType> ClientMessages<SourceType, SinkType>
+where
+    SourceType: MessageSource,
+    SinkType: MessageSink,
+{
+    pub(crate) fn new(
+        name: String,
+        max_message_size: usize,
+        endpoint: MessageEndpoint,
+        source: SourceType,
+        sink: SinkType,
+        stats: Arc<Mutex<MessageStats>>,
+    ) -> Self
+    {
+        Self {
+            name,
+            max_message_size,
+
+            endpoint,
+            buffer: Vec::with_capacity(max_message_size),
+            source,
+            sink,
+
+            stats: MessageStats::default(),
+            shared_stats: stats,
+        }
+    }
+}
+
+impl<SourceType, SinkType> RuntimeTask for ClientMessages<SourceType, SinkType>
+where
+    SourceType: MessageSource,
+    SinkType: MessageSink,
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn poll(&mut self, _now: Instant)
+    {
+        // Messages over the maximum would only get the stream torn down on the other end, so
+        // they're dropped here instead.
+        loop
+        {
+            self.buffer.clear();
+            if !self.source.poll(&mut self.buffer)
+            {
+                break;
+            }
+            if self.buffer.len() > self.max_message_size
+            {
+                self.stats.messages_oversized += 1;
+                continue;
+            }
+            self.endpoint.send(&self.buffer, &mut self.stats);
+        }
+
+        for message in self.endpoint.incoming.try_iter()
+        {
+            self.sink.handle(&message);
+            self.stats.messages_received += 1;
+        }
+
+        // Find out whether the stream is done with, and publish stats.
+        self.endpoint.poll_closed(&mut self.stats);
+        *self.shared_stats.lock().unwrap() = self.stats;
+    }
+}
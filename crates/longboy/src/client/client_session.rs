@@ -1,14 +1,22 @@
 use std::net::IpAddr;
 
 use anyhow::Result;
+use flume::Sender as FlumeSender;
 use quinn::Connection;
-use tokio::io::AsyncReadExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    runtime::Handle,
+    task::AbortHandle,
+};
+
+use crate::{open_message_stream, ClientSessionEvent, MessageRoute};
 
 pub struct ClientSession
 {
     connection: Connection,
     session_id: u64,
     cipher_key: u64,
+    runtime: Handle,
 }
 
 impl ClientSession
@@ -24,6 +32,7 @@ impl ClientSession
             connection,
             session_id,
             cipher_key,
+            runtime: Handle::current(),
         })
     }
 
@@ -41,4 +50,43 @@ impl ClientSession
     {
         self.cipher_key
     }
+
+    pub(crate) fn open_messages(&self, channel_id: u8, route: MessageRoute)
+    {
+        open_message_stream(&self.runtime, self.connection.clone(), channel_id, route);
+    }
+
+    pub(crate) fn path_mtu(&self) -> usize
+    {
+        // QUIC discovers the path MTU as the Session goes on, and our datagrams take the same path.
+        self.connection.stats().path.current_mtu as usize
+    }
+
+    pub(crate) fn accept_keys(&self, session_senders: Box<[FlumeSender<ClientSessionEvent>]>) -> AbortHandle
+    {
+        // The Server announces each key on a stream of its own, for as long as the Session lasts.
+        let connection = self.connection.clone();
+        self.runtime
+            .spawn(async move {
+                while let Ok((mut send, mut recv)) = connection.accept_bi().await
+                {
+                    let _: Result<()> = async {
+                        let epoch = recv.read_u8().await?;
+                        let cipher_key = recv.read_u64_le().await?;
+
+                        // Hand the key to tasks before acknowledging, the Server switches its
+                        // Senders over as soon as we do.  A failed acknowledgement surfaces on the
+                        // Server's end.
+                        session_senders.iter().for_each(|session_sender| {
+                            let _ = session_sender.send(ClientSessionEvent::Rekeyed { epoch, cipher_key });
+                        });
+                        send.write_u8(epoch).await?;
+                        send.finish()?;
+                        Ok(())
+                    }
+                    .await;
+                }
+            })
+            .abort_handle()
+    }
 }
@@ -0,0 +1,7 @@
+pub(crate) enum ClientSessionEvent
+{
+    Rekeyed
+    {
+        epoch: u8, cipher_key: u64
+    },
+}
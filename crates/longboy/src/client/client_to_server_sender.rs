@@ -1,105 +1,182 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::net::SocketAddr;
 
 use anyhow::Result;
-use enum_map::{Enum, EnumMap};
+use enum_map::EnumMap;
+use flume::Receiver as FlumeReceiver;
 
-use crate::{Constants, Mirroring, RuntimeTask, Sender, Source, UdpSocketExt};
+use crate::{
+    derive_channel_key, ChannelSocket, Cipher, ClientSessionEvent, Direction, DynSender, DynSource, Encoding, Instant,
+    Layout, Mirroring, Redundancy, RuntimeTask, UdpSocketExt,
+};
 
-pub(crate) struct ClientToServerSender<SourceType, const SIZE: usize, const WINDOW_SIZE: usize>
+pub(crate) struct ClientToServerSender<SourceType, CipherType>
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    CipherType: Cipher,
 {
     name: String,
+    schema_name: &'static str,
 
     mapper_socket_addr: SocketAddr,
     heartbeat_period: u16,
     socket_addr: SocketAddr,
+    ack_period: Option<u16>,
 
-    sockets: EnumMap<Mirroring, UdpSocket>,
+    sockets: EnumMap<Mirroring, ChannelSocket>,
 
     session_id: u64,
-    next_heartbeat: u16,
-    sender: Sender<SourceType, SIZE, WINDOW_SIZE>,
+    session_receiver: FlumeReceiver<ClientSessionEvent>,
+    next_heartbeat: Instant,
+    sender: DynSender<SourceType, CipherType>,
 }
 
-impl<SourceType, const SIZE: usize, const WINDOW_SIZE: usize> ClientToServerSender<SourceType, SIZE, WINDOW_SIZE>
+impl<SourceType, CipherType> ClientToServerSender<SourceType, CipherType>
 where
-    SourceType: Source<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    SourceType: DynSource,
+    CipherType: Cipher,
 {
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         name: String,
+        schema_name: &'static str,
+        layout: Layout,
+        channel_id: u8,
         mapper_socket_addr: SocketAddr,
         heartbeat_period: u16,
         socket_addr: SocketAddr,
+        encoding: Encoding,
+        keepalive_period: Option<u16>,
+        redundancy: Redundancy,
+        max_datagram_size: usize,
+        ack_period: Option<u16>,
         session_id: u64,
         cipher_key: u64,
-        sockets: EnumMap<Mirroring, UdpSocket>,
+        sockets: EnumMap<Mirroring, ChannelSocket>,
+        session_receiver: FlumeReceiver<ClientSessionEvent>,
         source: SourceType,
     ) -> Result<Self>
     {
-        sockets[Mirroring::AudioVideo].set_nonblocking(true)?;
-        sockets[Mirroring::AudioVideo].set_qos_audio_video()?;
-
-        sockets[Mirroring::Background].set_nonblocking(true)?;
-        sockets[Mirroring::Background].set_qos_background()?;
-
-        sockets[Mirroring::Voice].set_nonblocking(true)?;
-        sockets[Mirroring::Voice].set_qos_voice()?;
-
+        sockets[Mirroring::AudioVideo].socket().set_nonblocking(true)?;
+        sockets[Mirroring::AudioVideo].socket().set_qos_audio_video()?;
+
+        sockets[Mirroring::Background].socket().set_nonblocking(true)?;
+        sockets[Mirroring::Background].socket().set_qos_background()?;
+
+        sockets[Mirroring::Voice].socket().set_nonblocking(true)?;
+        sockets[Mirroring::Voice].socket().set_qos_voice()?;
+
+        let sender = DynSender::new(
+            derive_channel_key(cipher_key, schema_name, Direction::ClientToServer),
+            layout.size,
+            layout.window_size,
+            source,
+        )
+        .with_channel(channel_id)
+        .with_encoding(encoding)
+        .with_redundancy(redundancy)
+        .with_max_datagram_size(max_datagram_size);
         Ok(Self {
             name,
+            schema_name,
 
             mapper_socket_addr,
             heartbeat_period,
             socket_addr,
+            ack_period,
 
             sockets,
 
             session_id,
-            next_heartbeat: 0,
-            sender: Sender::new(cipher_key, source),
+            session_receiver,
+            next_heartbeat: Instant::default(),
+            sender: match keepalive_period
+            {
+                Some(keepalive_period) => sender.with_keepalive(keepalive_period),
+                None => sender,
+            },
         })
     }
 }
 
-impl<SourceType, const SIZE: usize, const WINDOW_SIZE: usize> RuntimeTask
-    for ClientToServerSender<SourceType, SIZE, WINDOW_SIZE>
+impl<SourceType, CipherType> RuntimeTask for ClientToServerSender<SourceType, CipherType>
 where
-    SourceType: Source<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    SourceType: DynSource,
+    CipherType: Cipher,
 {
     fn name(&self) -> &str
     {
         &self.name
     }
 
-    fn poll(&mut self, timestamp: u16)
+    fn poll(&mut self, now: Instant)
     {
-        // Heartbeat to Server.
-        if timestamp >= self.next_heartbeat
+        let timestamp = now.timestamp();
+
+        // Handle Session changes.
+        for event in self.session_receiver.try_iter()
         {
-            let mut buffer = [0; std::mem::size_of::<u64>() + std::mem::size_of::<u8>()];
-            *<&mut [u8; 8]>::try_from(&mut buffer[0..8]).unwrap() = self.session_id.to_le_bytes();
+            match event
+            {
+                ClientSessionEvent::Rekeyed { epoch, cipher_key } => self.sender.rekey(
+                    epoch,
+                    derive_channel_key(cipher_key, self.schema_name, Direction::ClientToServer),
+                ),
+            }
+        }
 
+        // Heartbeat to Server from each lane, checked under the lane's key, since they tell it
+        // where to find us.
+        if now >= self.next_heartbeat
+        {
             for (mirroring, socket) in self.sockets.iter()
             {
-                buffer[8] = Mirroring::into_usize(mirroring) as u8;
                 socket
-                    .send_to(&buffer, self.mapper_socket_addr)
+                    .send_to(
+                        &self.sender.heartbeat_datagram(self.session_id, mirroring),
+                        self.mapper_socket_addr,
+                    )
                     .expect("send_to failure");
             }
 
-            self.next_heartbeat = timestamp + self.heartbeat_period;
+            self.next_heartbeat = now + (self.heartbeat_period as u64);
+        }
+
+        // Take acknowledgements from the Server's mapper on any lane, once they check out under
+        // our key.  Anything else arriving on them is dropped.
+        let mut buffer = [0; 64];
+        for (_, socket) in self.sockets.iter()
+        {
+            while let Ok((len, socket_addr)) = socket.recv_from(&mut buffer)
+            {
+                if self.ack_period.is_none() || socket_addr != self.mapper_socket_addr
+                {
+                    continue;
+                }
+
+                if let Some(sequence) = self.sender.check_acknowledgement(self.session_id, &buffer[0..len])
+                {
+                    self.sender.acknowledge(sequence);
+                }
+            }
         }
 
-        // Poll Session
-        if let Some(datagram) = self.sender.poll_datagram(timestamp)
+        // Poll Session, sealing a copy of each datagram and any parity following it for each
+        // lane.
+        if self.sender.poll_datagram(timestamp).is_some()
+        {
+            for (mirroring, socket) in self.sockets.iter()
+            {
+                socket
+                    .send_to(self.sender.mirror_datagram(mirroring), self.socket_addr)
+                    .expect("send_to failure");
+            }
+        }
+        if self.sender.poll_parity(timestamp).is_some()
         {
-            for socket in self.sockets.values()
+            for (mirroring, socket) in self.sockets.iter()
             {
-                socket.send_to(datagram, self.socket_addr).expect("send_to failure");
+                socket
+                    .send_to(self.sender.mirror_datagram(mirroring), self.socket_addr)
+                    .expect("send_to failure");
             }
         }
     }
@@ -1,103 +1,149 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
+use flume::Receiver as FlumeReceiver;
 
-use crate::{Constants, Receiver, RuntimeTask, Sink};
+use crate::{
+    derive_channel_key, ChannelSocket, Cipher, ClientSessionEvent, Delivery, Direction, DynReceiver, DynSink, Instant,
+    Layout, Mirroring, ReceiverStats, RuntimeTask,
+};
 
-pub(crate) struct ServerToClientReceiver<SinkType, const SIZE: usize, const WINDOW_SIZE: usize>
+pub(crate) struct ServerToClientReceiver<SinkType, CipherType>
 where
-    SinkType: Sink<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkType: DynSink,
+    CipherType: Cipher,
 {
     name: String,
+    schema_name: &'static str,
 
     mapper_socket_addr: SocketAddr,
     heartbeat_period: u16,
 
-    socket: UdpSocket,
+    socket: ChannelSocket,
+    buffer: Box<[u8]>,
 
     session_id: u64,
-    next_heartbeat: u16,
-    receiver: Receiver<SinkType, SIZE, WINDOW_SIZE>,
+    session_receiver: FlumeReceiver<ClientSessionEvent>,
+    next_heartbeat: Instant,
+    receiver: DynReceiver<SinkType, CipherType>,
+    stats: Arc<Mutex<ReceiverStats>>,
 }
 
-impl<SinkType, const SIZE: usize, const WINDOW_SIZE: usize> ServerToClientReceiver<SinkType, SIZE, WINDOW_SIZE>
+impl<SinkType, CipherType> ServerToClientReceiver<SinkType, CipherType>
 where
-    SinkType: Sink<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkType: DynSink,
+    CipherType: Cipher,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         name: String,
+        schema_name: &'static str,
+        layout: Layout,
+        channel_id: u8,
         mapper_socket_addr: SocketAddr,
         heartbeat_period: u16,
+        ack_period: Option<u16>,
+        delivery: Delivery,
+        key_grace_period: u16,
         session_id: u64,
         cipher_key: u64,
-        socket: UdpSocket,
+        socket: ChannelSocket,
+        session_receiver: FlumeReceiver<ClientSessionEvent>,
         sink: SinkType,
+        stats: Arc<Mutex<ReceiverStats>>,
     ) -> Result<Self>
     {
-        socket.set_nonblocking(true)?;
+        socket.socket().set_nonblocking(true)?;
 
         Ok(Self {
             name,
+            schema_name,
 
             mapper_socket_addr,
-            heartbeat_period,
+            // Acknowledgements ride along on heartbeats, so send them often enough for both.
+            heartbeat_period: match ack_period
+            {
+                Some(ack_period) => std::cmp::min(heartbeat_period, ack_period),
+                None => heartbeat_period,
+            },
 
             socket,
+            // One byte past the largest datagram, so oversized datagrams are left for the
+            // Receiver to count.
+            buffer: vec![0; layout.datagram_size + 1].into_boxed_slice(),
 
             session_id,
-            next_heartbeat: 0,
-            receiver: Receiver::new(cipher_key, sink),
+            session_receiver,
+            next_heartbeat: Instant::default(),
+            receiver: DynReceiver::new(
+                derive_channel_key(cipher_key, schema_name, Direction::ServerToClient),
+                layout.size,
+                layout.window_size,
+                sink,
+            )
+            .with_channel(channel_id)
+            .with_delivery(delivery)
+            .with_key_grace_period(key_grace_period),
+            stats,
         })
     }
 }
 
-impl<SinkType, const SIZE: usize, const WINDOW_SIZE: usize> RuntimeTask
-    for ServerToClientReceiver<SinkType, SIZE, WINDOW_SIZE>
+impl<SinkType, CipherType> RuntimeTask for ServerToClientReceiver<SinkType, CipherType>
 where
-    SinkType: Sink<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkType: DynSink,
+    CipherType: Cipher,
 {
     fn name(&self) -> &str
     {
         &self.name
     }
 
-    fn poll(&mut self, timestamp: u16)
+    fn poll(&mut self, now: Instant)
     {
-        // Alias constants so they're less painful to read.
-        #[allow(non_snake_case)]
-        let DATAGRAM_SIZE: usize = Constants::<SIZE, WINDOW_SIZE>::DATAGRAM_SIZE;
+        let timestamp = now.timestamp();
 
-        // Heartbeat to Server.
-        if timestamp >= self.next_heartbeat
+        // Handle Session changes.
+        for event in self.session_receiver.try_iter()
         {
-            let mut buffer = [0; std::mem::size_of::<u64>()];
-            *<&mut [u8; 8]>::try_from(&mut buffer[0..8]).unwrap() = self.session_id.to_le_bytes();
+            match event
+            {
+                ClientSessionEvent::Rekeyed { epoch, cipher_key } => self.receiver.rekey(
+                    epoch,
+                    derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient),
+                ),
+            }
+        }
 
+        // Heartbeat to Server, acknowledging what we've received.  Heartbeats are checked under
+        // our key, since they move the Server's Sender along.
+        if now >= self.next_heartbeat
+        {
             self.socket
-                .send_to(&buffer, self.mapper_socket_addr)
+                .send_to(
+                    &self
+                        .receiver
+                        .acknowledgement_datagram(self.session_id, Mirroring::AudioVideo),
+                    self.mapper_socket_addr,
+                )
                 .expect("send_to failure");
 
-            self.next_heartbeat = timestamp + self.heartbeat_period;
+            self.next_heartbeat = now + (self.heartbeat_period as u64);
         }
 
         // Process datagrams.
-        let mut buffer = [0; 512];
-        while let Ok((len, _)) = self.socket.recv_from(&mut buffer)
+        while let Ok((len, _)) = self.socket.recv_from(&mut self.buffer)
         {
-            if len != DATAGRAM_SIZE
-            {
-                continue;
-            }
-
-            let datagram = (&mut buffer[0..DATAGRAM_SIZE]).try_into().unwrap();
+            let datagram = &mut self.buffer[0..len];
 
             self.receiver.handle_datagram(timestamp, datagram);
         }
+
+        // Release held input and publish stats.
+        self.receiver.poll(timestamp);
+        *self.stats.lock().unwrap() = self.receiver.stats();
     }
 }
@@ -0,0 +1,152 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use flume::Receiver as FlumeReceiver;
+
+use crate::{
+    derive_channel_key, ChannelSocket, Cipher, ClientSessionEvent, Direction, Instant, Mirroring, RuntimeTask,
+    SnapshotReceiver, SnapshotSink, SnapshotStats,
+};
+
+pub(crate) struct ServerToClientSnapshotReceiver<SinkType, CipherType>
+where
+    SinkType: SnapshotSink,
+    CipherType: Cipher,
+{
+    name: String,
+    schema_name: &'static str,
+
+    mapper_socket_addr: SocketAddr,
+    heartbeat_period: u16,
+
+    socket: ChannelSocket,
+    buffer: Box<[u8]>,
+
+    session_id: u64,
+    session_receiver: FlumeReceiver<ClientSessionEvent>,
+    next_heartbeat: Instant,
+    receiver: SnapshotReceiver<SinkType, CipherType>,
+    stats: Arc<Mutex<SnapshotStats>>,
+}
+
+impl<SinkType, CipherType> ServerToClientSnapshotReceiver<SinkType, CipherType>
+where
+    SinkType: SnapshotSink,
+    CipherType: Cipher,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        name: String,
+        schema_name: &'static str,
+        max_snapshot_size: usize,
+        max_datagram_size: usize,
+        baselines: usize,
+        channel_id: u8,
+        mapper_socket_addr: SocketAddr,
+        heartbeat_period: u16,
+        key_grace_period: u16,
+        ack_period: Option<u16>,
+        session_id: u64,
+        cipher_key: u64,
+        socket: ChannelSocket,
+        session_receiver: FlumeReceiver<ClientSessionEvent>,
+        sink: SinkType,
+        stats: Arc<Mutex<SnapshotStats>>,
+    ) -> Result<Self>
+    {
+        socket.socket().set_nonblocking(true)?;
+
+        let receiver = SnapshotReceiver::new(
+            derive_channel_key(cipher_key, schema_name, Direction::ServerToClient),
+            max_snapshot_size,
+            sink,
+        )
+        .with_channel(channel_id)
+        .with_max_datagram_size(max_datagram_size)
+        .with_baselines(baselines)
+        .with_key_grace_period(key_grace_period);
+
+        Ok(Self {
+            name,
+            schema_name,
+
+            mapper_socket_addr,
+            // Acknowledgements ride along on heartbeats, so send them often enough for both.
+            heartbeat_period: match ack_period
+            {
+                Some(ack_period) => std::cmp::min(heartbeat_period, ack_period),
+                None => heartbeat_period,
+            },
+
+            socket,
+            // One byte past the largest datagram, so oversized datagrams are left for the
+            // Receiver to count.
+            buffer: vec![0; receiver.layout().datagram_size + 1].into_boxed_slice(),
+
+            session_id,
+            session_receiver,
+            next_heartbeat: Instant::default(),
+            receiver,
+            stats,
+        })
+    }
+}
+
+impl<SinkType, CipherType> RuntimeTask for ServerToClientSnapshotReceiver<SinkType, CipherType>
+where
+    SinkType: SnapshotSink,
+    CipherType: Cipher,
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn poll(&mut self, now: Instant)
+    {
+        let timestamp = now.timestamp();
+
+        // Handle Session changes.
+        for event in self.session_receiver.try_iter()
+        {
+            match event
+            {
+                ClientSessionEvent::Rekeyed { epoch, cipher_key } => self.receiver.rekey(
+                    epoch,
+                    derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient),
+                ),
+            }
+        }
+
+        // Heartbeat to Server, acknowledging what we've received.  Heartbeats are checked under
+        // our key, since they pick the baselines the Server's Sender works from.
+        if now >= self.next_heartbeat
+        {
+            self.socket
+                .send_to(
+                    &self
+                        .receiver
+                        .acknowledgement_datagram(self.session_id, Mirroring::AudioVideo),
+                    self.mapper_socket_addr,
+                )
+                .expect("send_to failure");
+
+            self.next_heartbeat = now + (self.heartbeat_period as u64);
+        }
+
+        // Process datagrams.
+        while let Ok((len, _)) = self.socket.recv_from(&mut self.buffer)
+        {
+            let datagram = &mut self.buffer[0..len];
+
+            self.receiver.handle_datagram(timestamp, datagram);
+        }
+
+        // Retire keys and publish stats.
+        self.receiver.poll(timestamp);
+        *self.stats.lock().unwrap() = self.receiver.stats();
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, AddAssign},
+    time::Instant as StdInstant,
+};
+
+// Shared by all tasks on a Runtime, so their Instants agree with each other.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock
+{
+    origin: StdInstant,
+}
+
+// Milliseconds since a Clock's origin.  Never wraps, only its low 16 bits go on the wire as a
+// timestamp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Clock
+{
+    pub fn new() -> Self
+    {
+        Self {
+            origin: StdInstant::now(),
+        }
+    }
+
+    pub fn now(&self) -> Instant
+    {
+        self.at(StdInstant::now())
+    }
+
+    pub fn at(&self, instant: StdInstant) -> Instant
+    {
+        Instant(instant.saturating_duration_since(self.origin).as_millis() as u64)
+    }
+}
+
+impl Default for Clock
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Instant
+{
+    pub const fn from_millis(millis: u64) -> Self
+    {
+        Self(millis)
+    }
+
+    pub const fn as_millis(self) -> u64
+    {
+        self.0
+    }
+
+    pub const fn timestamp(self) -> u16
+    {
+        self.0 as u16
+    }
+
+    pub const fn saturating_since(self, earlier: Instant) -> u64
+    {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<u64> for Instant
+{
+    type Output = Self;
+
+    fn add(self, millis: u64) -> Self
+    {
+        Self(self.0 + millis)
+    }
+}
+
+impl AddAssign<u64> for Instant
+{
+    fn add_assign(&mut self, millis: u64)
+    {
+        self.0 += millis;
+    }
+}
+
+// Wire timestamps wrap every 65.536 seconds, so they're compared as serial numbers instead: `a`
+// is ahead of `b` when it's less than half the range past it.  Only meaningful for values known
+// to be close together.
+pub const fn serial_diff(a: u16, b: u16) -> i16
+{
+    a.wrapping_sub(b) as i16
+}
+
+pub fn serial_cmp(a: u16, b: u16) -> Ordering
+{
+    serial_diff(a, b).cmp(&0)
+}
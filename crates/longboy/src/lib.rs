@@ -9,9 +9,21 @@
 #![feature(try_blocks)]
 
 // API
+mod channel_stats;
+pub use self::channel_stats::*;
+
 mod client;
 pub use self::client::*;
 
+mod clock;
+pub use self::clock::*;
+
+mod message;
+pub use self::message::*;
+
+mod message_stats;
+pub use self::message_stats::*;
+
 mod mirroring;
 pub use self::mirroring::*;
 
@@ -31,5 +43,11 @@ mod server;
 pub use self::server::*;
 
 // Internal
+mod channel_socket;
+pub(crate) use self::channel_socket::*;
+
+mod message_stream;
+pub(crate) use self::message_stream::*;
+
 mod udp_socket_ext;
 pub(crate) use self::udp_socket_ext::*;
@@ -0,0 +1,16 @@
+// Messages arrive exactly once and in order, carried on the Session's QUIC connection instead of
+// the datagram sockets.
+pub trait MessageSource
+where
+    Self: 'static + Send,
+{
+    // Polled until it has nothing more to send, each time with an empty buffer.
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool;
+}
+
+pub trait MessageSink
+where
+    Self: 'static + Send,
+{
+    fn handle(&mut self, message: &[u8]);
+}
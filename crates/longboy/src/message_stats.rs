@@ -0,0 +1,12 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageStats
+{
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub messages_oversized: u64,
+    pub messages_refused: u64,
+    pub messages_undelivered: u64,
+
+    pub stream_closed: bool,
+    pub stream_failed: bool,
+}
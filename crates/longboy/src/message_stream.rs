@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Result};
+use flume::{Receiver as FlumeReceiver, Sender as FlumeSender, TryRecvError, TrySendError};
+use fnv::FnvHashMap;
+use quinn::{Connection, RecvStream, SendStream, VarInt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    runtime::Handle,
+    task::{AbortHandle, JoinSet},
+};
+
+use crate::MessageStats;
+
+const UNKNOWN_CHANNEL: VarInt = VarInt::from_u32(1);
+const MESSAGE_TOO_LARGE: VarInt = VarInt::from_u32(2);
+
+// A message stream as seen from the task using it.
+pub(crate) struct MessageEndpoint
+{
+    pub(crate) outgoing: FlumeSender<Box<[u8]>>,
+    pub(crate) incoming: FlumeReceiver<Box<[u8]>>,
+    closed: FlumeReceiver<Result<()>>,
+}
+
+// The other end, pumped to and from the stream itself.
+pub(crate) struct MessageRoute
+{
+    outgoing: FlumeReceiver<Box<[u8]>>,
+    incoming: FlumeSender<Box<[u8]>>,
+    closed: FlumeSender<Result<()>>,
+    max_message_size: usize,
+}
+
+// Only so many messages are queued each way.  The task is refused past that, while the stream is
+// left to wait for the task to catch up.
+pub(crate) fn message_channel(max_message_size: usize, max_queued_messages: usize) -> (MessageEndpoint, MessageRoute)
+{
+    let (outgoing_sender, outgoing_receiver) = flume::bounded(max_queued_messages);
+    let (incoming_sender, incoming_receiver) = flume::bounded(max_queued_messages);
+    let (closed_sender, closed_receiver) = flume::bounded(1);
+
+    (
+        MessageEndpoint {
+            outgoing: outgoing_sender,
+            incoming: incoming_receiver,
+            closed: closed_receiver,
+        },
+        MessageRoute {
+            outgoing: outgoing_receiver,
+            incoming: incoming_sender,
+            closed: closed_sender,
+            max_message_size,
+        },
+    )
+}
+
+// Clients open a stream for each of their message schemas, leading with its channel.  Whatever
+// ends it, the task on the other end of the route hears how.
+pub(crate) fn open_message_stream(runtime: &Handle, connection: Connection, channel_id: u8, route: MessageRoute)
+{
+    runtime.spawn(async move {
+        let opened: Result<(SendStream, RecvStream)> = async {
+            let (mut send, recv) = connection.open_bi().await?;
+            send.write_u8(channel_id).await?;
+            Ok((send, recv))
+        }
+        .await;
+        match opened
+        {
+            Ok((send, recv)) => route.pump(send, recv).await,
+            Err(error) => route.close(Err(error)),
+        }
+    });
+}
+
+// Servers accept those streams, handing each to the schema on its channel.  Channels nobody
+// registered, or already taken on this Session, are turned away.  Channels are read off to the
+// side, so a stream that's slow to name one doesn't hold up the rest.
+pub(crate) fn accept_message_streams(
+    runtime: &Handle,
+    connection: Connection,
+    mut routes: FnvHashMap<u8, MessageRoute>,
+) -> AbortHandle
+{
+    let pumps = runtime.clone();
+    runtime
+        .spawn(async move {
+            let mut pending = JoinSet::new();
+            while !routes.is_empty()
+            {
+                let (send, mut recv, channel_id) = tokio::select! {
+                    accepted = connection.accept_bi() =>
+                    {
+                        let Ok((send, mut recv)) = accepted
+                        else
+                        {
+                            break;
+                        };
+                        pending.spawn(async move {
+                            let channel_id = recv.read_u8().await;
+                            (send, recv, channel_id)
+                        });
+                        continue;
+                    }
+                    Some(Ok((send, recv, Ok(channel_id)))) = pending.join_next() => (send, recv, channel_id),
+                };
+
+                match routes.remove(&channel_id)
+                {
+                    Some(route) =>
+                    {
+                        pumps.spawn(route.pump(send, recv));
+                    }
+                    None =>
+                    {
+                        // Only fails if the stream is already gone.
+                        recv.stop(UNKNOWN_CHANNEL).ok();
+                    }
+                }
+            }
+        })
+        .abort_handle()
+}
+
+impl MessageEndpoint
+{
+    pub(crate) fn send(&self, message: &[u8], stats: &mut MessageStats)
+    {
+        // Messages only fail to send once the stream is gone, or too many are waiting on it.
+        match self.outgoing.try_send(Box::from(message))
+        {
+            Ok(()) => stats.messages_sent += 1,
+            Err(TrySendError::Full(_)) => stats.messages_refused += 1,
+            Err(TrySendError::Disconnected(_)) => stats.messages_undelivered += 1,
+        }
+    }
+
+    // Picks up how the stream ended, once it has.  Routes dropped without ever being pumped,
+    // like those for streams the Client never opened, end it all the same.
+    pub(crate) fn poll_closed(&self, stats: &mut MessageStats)
+    {
+        match self.closed.try_recv()
+        {
+            Ok(result) =>
+            {
+                stats.stream_closed = true;
+                stats.stream_failed |= result.is_err();
+            }
+            Err(TryRecvError::Disconnected) => stats.stream_closed = true,
+            Err(TryRecvError::Empty) => (),
+        }
+    }
+}
+
+impl MessageRoute
+{
+    async fn pump(self, send: SendStream, recv: RecvStream)
+    {
+        let result = self.transfer(send, recv).await;
+        self.close(result);
+    }
+
+    fn close(self, result: Result<()>)
+    {
+        // Nobody is left to tell once the task on the other end is gone.
+        self.closed.send(result).ok();
+    }
+
+    // Each message goes on the stream behind its length.  Whatever is still queued when the task
+    // lets go of its end is written out before the stream is finished, and reading carries on
+    // until the other side finishes its own.
+    async fn transfer(&self, mut send: SendStream, mut recv: RecvStream) -> Result<()>
+    {
+        let write = async {
+            while let Ok(message) = self.outgoing.recv_async().await
+            {
+                send.write_u32_le(message.len() as u32).await?;
+                send.write_all(&message).await?;
+            }
+            send.finish()?;
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let read = async {
+            loop
+            {
+                let len = match recv.read_u32_le().await
+                {
+                    Ok(len) => len as usize,
+                    Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                    Err(error) => return Err(error.into()),
+                };
+                if len > self.max_message_size
+                {
+                    recv.stop(MESSAGE_TOO_LARGE)?;
+                    return Err(anyhow!(
+                        "Message of {} bytes over maximum of {}",
+                        len,
+                        self.max_message_size
+                    ));
+                }
+
+                let mut message = vec![0; len].into_boxed_slice();
+                recv.read_exact(&mut message).await?;
+                if self.incoming.send_async(message).await.is_err()
+                {
+                    return Ok(());
+                }
+            }
+        };
+
+        tokio::try_join!(write, read)?;
+        Ok(())
+    }
+}
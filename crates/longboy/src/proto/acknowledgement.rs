@@ -0,0 +1,63 @@
+use enum_map::Enum;
+
+use crate::{supports_version, HeaderCheck, Mirroring, PROTOCOL_VERSION};
+
+// Version, channel, Session ID, acknowledged sequence, key epoch and lane, then a check over all
+// of it under the lane's key.
+pub(crate) const ACKNOWLEDGEMENT_SIZE: usize = 20 + std::mem::size_of::<u64>();
+
+// Acknowledgements are sent in the clear, so the Sender they're for can only trust one once it
+// checks out under the key it shares with the Receiver that sent it.
+pub(crate) fn encode_acknowledgement(
+    header_check: &HeaderCheck,
+    channel_id: u8,
+    session_id: u64,
+    sequence: u64,
+    epoch: u8,
+    mirroring: Mirroring,
+) -> [u8; ACKNOWLEDGEMENT_SIZE]
+{
+    let mut buffer = [0; ACKNOWLEDGEMENT_SIZE];
+    buffer[0] = PROTOCOL_VERSION;
+    buffer[1] = channel_id;
+    *<&mut [u8; 8]>::try_from(&mut buffer[2..10]).unwrap() = session_id.to_le_bytes();
+    *<&mut [u8; 8]>::try_from(&mut buffer[10..18]).unwrap() = sequence.to_le_bytes();
+    buffer[18] = epoch;
+    buffer[19] = Mirroring::into_usize(mirroring) as u8;
+    let check = header_check.authenticate(&buffer[0..20]);
+    *<&mut [u8; 8]>::try_from(&mut buffer[20..28]).unwrap() = check.to_le_bytes();
+    buffer
+}
+
+// Everything an acknowledgement says about itself, before it's been checked.
+pub(crate) struct Acknowledgement
+{
+    pub(crate) session_id: u64,
+    pub(crate) sequence: u64,
+    pub(crate) epoch: u8,
+    pub(crate) mirroring: Mirroring,
+}
+
+pub(crate) fn decode_acknowledgement(channel_id: u8, buffer: &[u8]) -> Option<Acknowledgement>
+{
+    if buffer.len() != ACKNOWLEDGEMENT_SIZE
+        || !supports_version(buffer[0])
+        || buffer[1] != channel_id
+        || (buffer[19] as usize) >= Mirroring::LENGTH
+    {
+        return None;
+    }
+
+    Some(Acknowledgement {
+        session_id: u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[2..10]).unwrap()),
+        sequence: u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[10..18]).unwrap()),
+        epoch: buffer[18],
+        mirroring: Mirroring::from_usize(buffer[19] as usize),
+    })
+}
+
+pub(crate) fn check_acknowledgement(header_check: &HeaderCheck, buffer: &[u8]) -> bool
+{
+    let check = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[20..28]).unwrap());
+    check == header_check.authenticate(&buffer[0..20])
+}
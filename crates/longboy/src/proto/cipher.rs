@@ -1,45 +1,21 @@
-use cipher::{
-    array::Array,
-    typenum::{U20, U8},
-    BlockCipherDecrypt, BlockCipherEncrypt,
-};
-use rc5::RC5;
-
-pub(crate) struct Cipher<const SIZE: usize>
+pub trait Cipher
+where
+    Self: 'static + Send,
 {
-    header_cipher: RC5<u16, U20, U8>,
-    slot_cipher: RC5<u32, U20, U8>,
-}
+    const BLOCK_SIZE: usize;
+    const TAG_SIZE: usize;
 
-impl<const SIZE: usize> Cipher<SIZE>
-{
-    pub(crate) fn new(key: u64) -> Self
-    {
-        Self {
-            header_cipher: RC5::new(key.to_ne_bytes().as_ref()),
-            slot_cipher: RC5::new(key.to_ne_bytes().as_ref()),
-        }
-    }
+    fn new(key: u64) -> Self;
 
-    pub(crate) fn encrypt_header(&self, block: &mut [u8; 4])
+    fn encrypt_header(&self, _header: &mut [u8; 4])
     {
-        self.header_cipher.encrypt_block(block.as_mut())
     }
 
-    pub(crate) fn decrypt_header(&self, block: &mut [u8; 4])
+    fn decrypt_header(&self, _header: &mut [u8; 4])
     {
-        self.header_cipher.decrypt_block(block.as_mut())
     }
 
-    pub(crate) fn encrypt_slot(&self, block: &mut [u8; SIZE])
-    {
-        self.slot_cipher
-            .encrypt_blocks(Array::cast_slice_from_core_mut(block.as_chunks_mut().0))
-    }
+    fn seal(&self, sequence: u64, header: &[u8], payload: &mut [u8], tag: &mut [u8]);
 
-    pub(crate) fn decrypt_slot(&self, block: &mut [u8; SIZE])
-    {
-        self.slot_cipher
-            .decrypt_blocks(Array::cast_slice_from_core_mut(block.as_chunks_mut().0))
-    }
+    fn open(&self, sequence: u64, header: &[u8], payload: &mut [u8], tag: &[u8]) -> bool;
 }
@@ -0,0 +1,57 @@
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Key, Nonce, Tag,
+};
+
+use crate::Cipher;
+
+pub struct ChaCha20Poly1305Cipher
+{
+    aead: ChaCha20Poly1305,
+}
+
+impl ChaCha20Poly1305Cipher
+{
+    fn nonce(sequence: u64, header: &[u8]) -> Nonce
+    {
+        // Sequence is the cycle extended by the number of times it has wrapped, so it never
+        // repeats for the life of a key.  Channel and lane from the header go in as well, so
+        // nonces stay apart even for schemas and lanes that were somehow given the same key.
+        let mut nonce = Nonce::default();
+        *<&mut [u8; 8]>::try_from(&mut nonce[0..8]).unwrap() = sequence.to_le_bytes();
+        nonce[8] = header[1];
+        nonce[9] = header[8];
+        nonce
+    }
+}
+
+impl Cipher for ChaCha20Poly1305Cipher
+{
+    const BLOCK_SIZE: usize = 1;
+    const TAG_SIZE: usize = 16;
+
+    fn new(key: u64) -> Self
+    {
+        let key = blake3::derive_key("longboy 2024-07 datagram cipher", &key.to_le_bytes());
+
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    fn seal(&self, sequence: u64, header: &[u8], payload: &mut [u8], tag: &mut [u8])
+    {
+        *<&mut [u8; 16]>::try_from(tag).unwrap() = self
+            .aead
+            .encrypt_in_place_detached(&Self::nonce(sequence, header), header, payload)
+            .expect("encrypt failure")
+            .into();
+    }
+
+    fn open(&self, sequence: u64, header: &[u8], payload: &mut [u8], tag: &[u8]) -> bool
+    {
+        self.aead
+            .decrypt_in_place_detached(&Self::nonce(sequence, header), header, payload, Tag::from_slice(tag))
+            .is_ok()
+    }
+}
@@ -0,0 +1,8 @@
+mod chacha20_poly1305_cipher;
+pub use self::chacha20_poly1305_cipher::*;
+
+mod null_cipher;
+pub use self::null_cipher::*;
+
+mod rc5_cipher;
+pub use self::rc5_cipher::*;
@@ -0,0 +1,51 @@
+use cipher::{
+    array::Array,
+    typenum::{U20, U8},
+    BlockCipherDecrypt, BlockCipherEncrypt,
+};
+use rc5::RC5;
+
+use crate::Cipher;
+
+pub struct Rc5Cipher
+{
+    header_cipher: RC5<u16, U20, U8>,
+    slot_cipher: RC5<u32, U20, U8>,
+}
+
+impl Cipher for Rc5Cipher
+{
+    const BLOCK_SIZE: usize = 8;
+    const TAG_SIZE: usize = 0;
+
+    fn new(key: u64) -> Self
+    {
+        Self {
+            header_cipher: RC5::new(key.to_ne_bytes().as_ref()),
+            slot_cipher: RC5::new(key.to_ne_bytes().as_ref()),
+        }
+    }
+
+    fn encrypt_header(&self, header: &mut [u8; 4])
+    {
+        self.header_cipher.encrypt_block(header.as_mut())
+    }
+
+    fn decrypt_header(&self, header: &mut [u8; 4])
+    {
+        self.header_cipher.decrypt_block(header.as_mut())
+    }
+
+    fn seal(&self, _sequence: u64, _header: &[u8], payload: &mut [u8], _tag: &mut [u8])
+    {
+        self.slot_cipher
+            .encrypt_blocks(Array::cast_slice_from_core_mut(payload.as_chunks_mut().0))
+    }
+
+    fn open(&self, _sequence: u64, _header: &[u8], payload: &mut [u8], _tag: &[u8]) -> bool
+    {
+        self.slot_cipher
+            .decrypt_blocks(Array::cast_slice_from_core_mut(payload.as_chunks_mut().0));
+        true
+    }
+}
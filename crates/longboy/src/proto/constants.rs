@@ -1,18 +1,39 @@
-pub struct Constants<const SIZE: usize, const WINDOW_SIZE: usize>;
+use std::marker::PhantomData;
 
-impl<const SIZE: usize, const WINDOW_SIZE: usize> Constants<SIZE, WINDOW_SIZE>
+use crate::{Cipher, Layout, Rc5Cipher, Redundancy};
+
+pub struct Constants<const SIZE: usize, const WINDOW_SIZE: usize, CipherType = Rc5Cipher>(PhantomData<CipherType>);
+
+impl<const SIZE: usize, const WINDOW_SIZE: usize, CipherType> Constants<SIZE, WINDOW_SIZE, CipherType>
+where
+    CipherType: Cipher,
 {
-    pub const DATAGRAM_SIZE: usize = {
-        let datagram_size = (std::mem::size_of::<u16>() * 2) + (SIZE * WINDOW_SIZE);
-        assert!(datagram_size < 420);
-        datagram_size
+    pub const LAYOUT: Layout = match Layout::new::<CipherType>(SIZE, WINDOW_SIZE)
+    {
+        Some(layout) => layout,
+        None => panic!("Input and window don't fit in a datagram"),
     };
 
-    pub const MAX_CYCLE: usize = ((u16::MAX as usize) / WINDOW_SIZE) * WINDOW_SIZE;
+    pub const PRESENCE_SIZE: usize = Self::LAYOUT.presence_size;
+
+    pub const HEADER_SIZE: usize = Self::LAYOUT.header_size;
+
+    pub const LENGTH_SIZE: usize = Self::LAYOUT.length_size;
+
+    pub const MAX_DATAGRAM_SIZE: usize = Layout::MAX_DATAGRAM_SIZE;
 
-    pub const MAX_BUFFERED: usize = match WINDOW_SIZE < 4
+    pub const PARITY_SIZE: usize = Self::LAYOUT.parity_size;
+
+    pub const PAYLOAD_SIZE: usize = Self::LAYOUT.payload_size;
+
+    pub const DATAGRAM_SIZE: usize = Self::LAYOUT.datagram_size;
+
+    pub const MAX_CYCLE: usize = Self::LAYOUT.max_cycle;
+
+    pub const MAX_BUFFERED: usize = Self::LAYOUT.max_buffered;
+
+    pub const fn min_datagram_size(redundancy: Redundancy) -> usize
     {
-        true => 8,
-        false => WINDOW_SIZE * 2,
-    };
+        Self::LAYOUT.min_datagram_size(redundancy)
+    }
 }
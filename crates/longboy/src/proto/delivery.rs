@@ -0,0 +1,14 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery
+{
+    Unordered,
+    Ordered
+    {
+        deadline: u16,
+    },
+    Buffered
+    {
+        min_delay: u16,
+        max_delay: u16,
+    },
+}
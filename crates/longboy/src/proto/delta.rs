@@ -0,0 +1,82 @@
+// Deltas are the XOR of an input against its newer neighbour, run-length coded as a series of
+// tokens.  A token with the high bit set is a run of zero bytes, otherwise it's followed by
+// literal bytes, with the low bits holding the length minus one in both cases.
+const RUN_FLAG: u8 = 0x80;
+const MAX_TOKEN_LENGTH: usize = 128;
+
+pub(crate) fn encode_delta(input: &[u8], reference: &[u8], output: &mut [u8]) -> Option<usize>
+{
+    let delta = |index: usize| input[index] ^ reference[index];
+
+    let mut written = 0;
+    let mut index = 0;
+    while index < input.len()
+    {
+        // Runs of a single zero byte are cheaper to leave inside a literal.
+        let zeros = (index..input.len()).take_while(|index| delta(*index) == 0).count();
+        if zeros >= 2
+        {
+            let length = std::cmp::min(zeros, MAX_TOKEN_LENGTH);
+            *output.get_mut(written)? = RUN_FLAG | ((length - 1) as u8);
+            written += 1;
+            index += length;
+            continue;
+        }
+
+        let mut end = index + 1;
+        while end < input.len()
+            && end - index < MAX_TOKEN_LENGTH
+            && !(delta(end) == 0 && end + 1 < input.len() && delta(end + 1) == 0)
+        {
+            end += 1;
+        }
+
+        let literal = output.get_mut(written..(written + 1 + (end - index)))?;
+        literal[0] = (end - index - 1) as u8;
+        for (byte, offset) in literal[1..].iter_mut().zip(index..end)
+        {
+            *byte = delta(offset);
+        }
+        written += literal.len();
+        index = end;
+    }
+
+    Some(written)
+}
+
+pub(crate) fn decode_delta(input: &[u8], reference: &[u8], output: &mut [u8]) -> Option<usize>
+{
+    let mut read = 0;
+    let mut index = 0;
+    while index < output.len()
+    {
+        let token = *input.get(read)?;
+        read += 1;
+
+        let end = index + ((token & !RUN_FLAG) as usize) + 1;
+        if end > output.len()
+        {
+            return None;
+        }
+
+        match token & RUN_FLAG != 0
+        {
+            true =>
+            {
+                output[index..end].copy_from_slice(&reference[index..end]);
+            }
+            false =>
+            {
+                let literal = input.get(read..(read + (end - index)))?;
+                for ((byte, offset), delta) in output[index..end].iter_mut().zip(index..end).zip(literal)
+                {
+                    *byte = reference[offset] ^ delta;
+                }
+                read += literal.len();
+            }
+        }
+        index = end;
+    }
+
+    Some(read)
+}
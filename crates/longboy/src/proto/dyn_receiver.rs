@@ -0,0 +1,804 @@
+use enum_map::{Enum, EnumMap};
+
+use crate::{
+    check_heartbeat, decode_delta, decode_heartbeat, derive_lane_key, encode_acknowledgement, serial_diff,
+    supports_version, Cipher, Delivery, Encoding, HeaderCheck, JitterEstimate, Layout, Mirroring, ReceiverStats,
+    ReplayWindow, SinkMetadata, ACKNOWLEDGEMENT_SIZE, DEFAULT_KEY_GRACE_PERIOD, HEADER_CHECK_OFFSET, MAX_PARITY_GROUP,
+    PARITY_FLAG, PARITY_SEQUENCE, TRUNCATED_FLAG,
+};
+
+pub struct DynReceiver<SinkType, CipherType>
+where
+    SinkType: DynSink,
+    CipherType: Cipher,
+{
+    sink: SinkType,
+    ciphers: EnumMap<Mirroring, CipherType>,
+    header_checks: EnumMap<Mirroring, HeaderCheck>,
+    epoch: u8,
+    channel_id: u8,
+    previous_ciphers: Vec<EpochKeys<CipherType>>,
+    rekey_timestamp: Option<u16>,
+    rekey_elapsed: u16,
+    key_grace_period: u16,
+    delivery: Delivery,
+    jitter_estimate: JitterEstimate,
+    layout: Layout,
+
+    cycle: usize,
+    rollover: u64,
+    flags: Vec<bool>,
+    lengths: Vec<Option<usize>>,
+    metadata: Vec<SinkMetadata>,
+    slots: Vec<Vec<u8>>,
+    replay_windows: EnumMap<Mirroring, ReplayWindow>,
+    parity_replay_windows: EnumMap<Mirroring, ReplayWindow>,
+    history_sequences: Vec<Option<u64>>,
+    history_lengths: Vec<usize>,
+    history: Vec<Vec<u8>>,
+    scratch_lengths: Vec<Option<usize>>,
+    scratch_slots: Vec<Vec<u8>>,
+
+    stats: ReceiverStats,
+}
+
+// A key epoch along with its ciphers and header checks for each lane.
+pub(crate) type EpochKeys<CipherType> = (u8, EnumMap<Mirroring, CipherType>, EnumMap<Mirroring, HeaderCheck>);
+
+pub trait DynSink
+where
+    Self: 'static + Send,
+{
+    fn handle(&mut self, metadata: &SinkMetadata, buffer: &[u8]);
+
+    fn skipped(&mut self, _cycle: u64)
+    {
+    }
+
+    // Whether the sink can take input of the given length at all.
+    fn accepts(&self, _length: usize) -> bool
+    {
+        true
+    }
+}
+
+impl<SinkType, CipherType> DynReceiver<SinkType, CipherType>
+where
+    SinkType: DynSink,
+    CipherType: Cipher,
+{
+    pub fn new(cipher_key: u64, size: usize, window_size: usize, sink: SinkType) -> Self
+    {
+        let layout = Layout::new::<CipherType>(size, window_size).unwrap_or_else(|| {
+            panic!(
+                "Input of {} bytes and window of {} don't fit in a datagram",
+                size, window_size
+            )
+        });
+
+        Self {
+            sink,
+            ciphers: EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring))),
+            header_checks: EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+            epoch: 0,
+            channel_id: 0,
+            previous_ciphers: Vec::new(),
+            rekey_timestamp: None,
+            rekey_elapsed: 0,
+            key_grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            delivery: Delivery::Unordered,
+            jitter_estimate: JitterEstimate::new(),
+            layout,
+
+            cycle: 0,
+            rollover: 0,
+            flags: vec![false; layout.max_buffered],
+            lengths: vec![None; layout.max_buffered],
+            metadata: vec![SinkMetadata::default(); layout.max_buffered],
+            slots: vec![vec![0; size]; layout.max_buffered],
+            replay_windows: EnumMap::from_fn(|_| ReplayWindow::new()),
+            parity_replay_windows: EnumMap::from_fn(|_| ReplayWindow::new()),
+            history_sequences: vec![None; layout.max_buffered],
+            history_lengths: vec![0; layout.max_buffered],
+            history: vec![vec![0; size]; layout.max_buffered],
+            scratch_lengths: vec![None; window_size],
+            scratch_slots: vec![vec![0; size]; window_size],
+
+            stats: ReceiverStats::default(),
+        }
+    }
+
+    pub fn with_channel(mut self, channel_id: u8) -> Self
+    {
+        self.channel_id = channel_id;
+        self
+    }
+
+    pub fn with_delivery(mut self, delivery: Delivery) -> Self
+    {
+        self.delivery = delivery;
+        self
+    }
+
+    pub fn with_key_grace_period(mut self, period: u16) -> Self
+    {
+        self.key_grace_period = period;
+        self
+    }
+
+    pub fn layout(&self) -> &Layout
+    {
+        &self.layout
+    }
+
+    pub fn cycle(&self) -> usize
+    {
+        self.cycle
+    }
+
+    pub fn acknowledgement(&self) -> u64
+    {
+        // Every sequence before our own has been handed off or given up on.
+        self.sequence()
+    }
+
+    pub fn acknowledgement_datagram(&self, session_id: u64, mirroring: Mirroring) -> [u8; ACKNOWLEDGEMENT_SIZE]
+    {
+        encode_acknowledgement(
+            &self.header_checks[mirroring],
+            self.channel_id,
+            session_id,
+            self.acknowledgement(),
+            self.epoch,
+            mirroring,
+        )
+    }
+
+    pub fn check_heartbeat(&self, session_id: u64, datagram: &[u8]) -> Option<Mirroring>
+    {
+        // Only heartbeats for this Session that check out under our current key for their
+        // lane, or one the Sender has yet to switch over from, are trusted with where it is.
+        let heartbeat = decode_heartbeat(self.channel_id, datagram)?;
+        let header_checks = match heartbeat.epoch == self.epoch
+        {
+            true => &self.header_checks,
+            false =>
+            {
+                &self
+                    .previous_ciphers
+                    .iter()
+                    .find(|(epoch, _, _)| *epoch == heartbeat.epoch)?
+                    .2
+            }
+        };
+        (heartbeat.session_id == session_id && check_heartbeat(&header_checks[heartbeat.mirroring], datagram))
+            .then_some(heartbeat.mirroring)
+    }
+
+    pub fn stats(&self) -> ReceiverStats
+    {
+        ReceiverStats {
+            jitter: self.jitter_estimate.jitter(),
+            playout_delay: self.playout_delay(),
+            ..self.stats
+        }
+    }
+
+    pub fn rekey(&mut self, epoch: u8, cipher_key: u64)
+    {
+        // Keep outgoing keys around for datagrams sealed before the Sender switches over.  Keys
+        // can be replaced again before it does, so every one is kept until the Sender has been
+        // heard from under the newest.
+        let ciphers = std::mem::replace(
+            &mut self.ciphers,
+            EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring))),
+        );
+        let header_checks = std::mem::replace(
+            &mut self.header_checks,
+            EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+        );
+        self.previous_ciphers
+            .retain(|(previous_epoch, _, _)| *previous_epoch != epoch);
+        self.previous_ciphers.push((self.epoch, ciphers, header_checks));
+        self.epoch = epoch;
+        self.rekey_timestamp = None;
+        self.rekey_elapsed = 0;
+    }
+
+    pub fn poll(&mut self, timestamp: u16)
+    {
+        self.retire_key(timestamp);
+        self.release(timestamp);
+    }
+
+    pub fn handle_datagram(&mut self, timestamp: u16, datagram: &mut [u8])
+    {
+        // Decode into slots held apart from the rest of us, so input can be stored straight from
+        // them.
+        let mut lengths = std::mem::take(&mut self.scratch_lengths);
+        let mut slots = std::mem::take(&mut self.scratch_slots);
+        self.decode_datagram(timestamp, datagram, &mut lengths, &mut slots);
+        self.scratch_lengths = lengths;
+        self.scratch_slots = slots;
+    }
+
+    fn decode_datagram(
+        &mut self,
+        timestamp: u16,
+        datagram: &mut [u8],
+        lengths: &mut [Option<usize>],
+        slots: &mut [Vec<u8>],
+    )
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let SIZE: usize = self.layout.size;
+        #[allow(non_snake_case)]
+        let WINDOW_SIZE: usize = self.layout.window_size;
+        #[allow(non_snake_case)]
+        let MAX_CYCLE: usize = self.layout.max_cycle;
+        #[allow(non_snake_case)]
+        let MAX_BUFFERED: usize = self.layout.max_buffered;
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+        #[allow(non_snake_case)]
+        let LENGTH_SIZE: usize = self.layout.length_size;
+        #[allow(non_snake_case)]
+        let DATAGRAM_SIZE: usize = self.layout.datagram_size;
+
+        // Check version before anything else, the rest of the header is only known for versions
+        // we support.
+        if let Some(version) = datagram.first()
+            && !supports_version(*version)
+        {
+            self.stats.datagrams_unsupported_version += 1;
+            return;
+        }
+
+        // Check for datagrams that can't hold a header and a whole number of cipher blocks.
+        if datagram.len() < HEADER_SIZE + CipherType::TAG_SIZE
+            || datagram.len() > DATAGRAM_SIZE
+            || (datagram.len() - HEADER_SIZE - CipherType::TAG_SIZE) % CipherType::BLOCK_SIZE != 0
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        }
+        let payload_end = datagram.len() - CipherType::TAG_SIZE;
+
+        // Sockets shared by several schemas hand datagrams over by channel, anything else still
+        // arriving here was meant for someone else.
+        if datagram[1] != self.channel_id
+        {
+            self.stats.datagrams_unknown_channel += 1;
+            return;
+        }
+
+        // Pick the key the datagram was sealed with, each lane has its own.  Previous keys are
+        // only good until their grace period runs out.
+        if (datagram[8] as usize) >= Mirroring::LENGTH
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        }
+        let mirroring = Mirroring::from_usize(datagram[8] as usize);
+        self.retire_key(timestamp);
+        let current_key = datagram[7] == self.epoch;
+        let (cipher, header_check) = match self.previous_ciphers.iter().find(|(epoch, _, _)| datagram[7] == *epoch)
+        {
+            _ if current_key => (&self.ciphers[mirroring], &self.header_checks[mirroring]),
+            Some((_, ciphers, header_checks)) => (&ciphers[mirroring], &header_checks[mirroring]),
+            None =>
+            {
+                self.stats.datagrams_unknown_epoch += 1;
+                return;
+            }
+        };
+
+        // Grab cycle and timestamp, and calculate diff for the cycle.  Cycles well ahead of ours
+        // are taken to be from the cycle's last time around instead.
+        cipher.decrypt_header(<&mut [u8; 4]>::try_from(&mut datagram[2..6]).unwrap());
+        let datagram_cycle = u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[2..4]).unwrap()) as usize;
+        let datagram_timestamp = u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[4..6]).unwrap());
+        let cycle_diff = ((datagram_cycle + MAX_CYCLE) - self.cycle) % MAX_CYCLE;
+        let sequence = match cycle_diff > 256
+        {
+            true => (self.sequence() + (cycle_diff as u64)).wrapping_sub(MAX_CYCLE as u64),
+            false => self.sequence() + (cycle_diff as u64),
+        };
+
+        // Turn away anything whose header doesn't check out under the lane's key for that
+        // sequence before either is trusted.
+        let check = u16::from_le_bytes(
+            *<&[u8; 2]>::try_from(&datagram[HEADER_CHECK_OFFSET..(HEADER_CHECK_OFFSET + 2)]).unwrap(),
+        );
+        if check != header_check.compute(&datagram[0..HEADER_SIZE], sequence)
+        {
+            self.stats.header_check_failures += 1;
+            return;
+        }
+
+        // Check for bad datagrams or late datagrams that are already processed.  Because
+        // we ensure only a positive diff, this is done by checking for any values greater
+        // that a certain threshold.  The Sender's timestamps are on a clock of its own, so
+        // datagrams are only held to how long they took compared to the ones before them.
+        if cycle_diff > 256
+            || self
+                .jitter_estimate
+                .lag(datagram_timestamp, timestamp)
+                .is_some_and(|lag| lag > 2048)
+        {
+            // Bad datagram or already received.
+            self.stats.datagrams_stale += 1;
+            return;
+        }
+
+        // Reject datagrams already accepted under this sequence on this lane, then authenticate
+        // and open window.  Mirrored copies from other lanes go on to count as duplicates.  A
+        // datagram replayed from before the last cycle wrap already failed its header check.
+        // Parity shares its sequence with the next datagram, so it's tracked and sealed apart.
+        let parity = datagram[6] & PARITY_FLAG != 0;
+        let (replay_window, nonce) = match parity
+        {
+            true => (&mut self.parity_replay_windows[mirroring], sequence | PARITY_SEQUENCE),
+            false => (&mut self.replay_windows[mirroring], sequence),
+        };
+        if !replay_window.check(sequence)
+        {
+            self.stats.datagrams_replayed += 1;
+            return;
+        }
+        let (header, rest) = datagram.split_at_mut(HEADER_SIZE);
+        let (payload, tag) = rest.split_at_mut(payload_end - HEADER_SIZE);
+        if !cipher.open(nonce, header, payload, tag)
+        {
+            self.stats.authentication_failures += 1;
+            return;
+        }
+
+        // The Sender has switched over once it's heard from under the current key, which is
+        // when the grace period for the previous ones starts.
+        if current_key && !self.previous_ciphers.is_empty() && self.rekey_timestamp.is_none()
+        {
+            self.rekey_timestamp = Some(timestamp);
+        }
+
+        // Parity only ever rebuilds cycles, it never moves ours along.
+        if parity
+        {
+            if !self.recover(
+                timestamp,
+                sequence,
+                datagram_timestamp,
+                &datagram[HEADER_SIZE..payload_end],
+            )
+            {
+                self.stats.datagrams_malformed += 1;
+                return;
+            }
+            self.parity_replay_windows[mirroring].accept(sequence);
+            self.stats.datagrams_accepted += 1;
+            self.release(timestamp);
+            return;
+        }
+
+        // Decode present slots newest first, undoing delta encoding against the next newer slot
+        // when flagged, and rejecting the datagram if anything runs past the payload.
+        let Some(encoding) = Encoding::from_flags(datagram[6] & !TRUNCATED_FLAG)
+        else
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        };
+        let truncated = datagram[6] & TRUNCATED_FLAG != 0;
+        let presence_start = HEADER_SIZE - self.layout.presence_size;
+        slots.iter_mut().for_each(|slot| slot.fill(0));
+        lengths.fill(None);
+        let mut reference: Option<usize> = None;
+        let mut start = HEADER_SIZE;
+        for (age, decoded_length) in lengths.iter_mut().enumerate()
+        {
+            let index = ((datagram_cycle + WINDOW_SIZE) - age) % WINDOW_SIZE;
+            if datagram[presence_start + (index / 8)] & (1 << (index % 8)) == 0
+            {
+                continue;
+            }
+
+            if start + LENGTH_SIZE > payload_end
+            {
+                self.stats.datagrams_malformed += 1;
+                return;
+            }
+            let mut length = [0; 2];
+            length[0..LENGTH_SIZE].copy_from_slice(&datagram[start..(start + LENGTH_SIZE)]);
+            let length = u16::from_le_bytes(length) as usize;
+            start += LENGTH_SIZE;
+            if length > SIZE
+            {
+                self.stats.datagrams_malformed += 1;
+                return;
+            }
+
+            let (newer, older) = slots.split_at_mut(age);
+            let slot = &mut older[0][0..length];
+            match (encoding, reference)
+            {
+                (Encoding::Delta, Some(reference)) =>
+                {
+                    let Some(encoded_length) = decode_delta(&datagram[start..payload_end], &newer[reference], slot)
+                    else
+                    {
+                        self.stats.datagrams_malformed += 1;
+                        return;
+                    };
+                    start += encoded_length;
+                }
+                _ =>
+                {
+                    if start + length > payload_end
+                    {
+                        self.stats.datagrams_malformed += 1;
+                        return;
+                    }
+                    slot.copy_from_slice(&datagram[start..(start + length)]);
+                    start += length;
+                }
+            }
+            *decoded_length = Some(length);
+            reference = Some(age);
+        }
+
+        self.replay_windows[mirroring].accept(sequence);
+        self.stats.datagrams_accepted += 1;
+        self.stats.max_cycle_gap = std::cmp::max(self.stats.max_cycle_gap, cycle_diff);
+        self.jitter_estimate.observe(datagram_timestamp, timestamp);
+
+        // Remember the newest input for rebuilding lost cycles from parity, which is all that
+        // parity protected datagrams carry.
+        if let Some(length) = lengths[0]
+        {
+            self.remember(sequence, &slots[0][0..length]);
+        }
+
+        // Check for late or missing packets from between local cycle and the datagram
+        // cycle just received.
+        if cycle_diff > std::cmp::min(8, WINDOW_SIZE + 1)
+        {
+            // soft warning
+            self.stats.soft_warnings += 1;
+        }
+        if cycle_diff >= MAX_BUFFERED
+        {
+            // hard warning
+
+            for _ in 0..=(cycle_diff - MAX_BUFFERED)
+            {
+                if !self.pop()
+                {
+                    self.stats.cycles_skipped += 1;
+                }
+            }
+        }
+
+        // Sink input, oldest first.  Slots older than the oldest one carried by a truncated
+        // datagram are unknown rather than empty, so leave those to other datagrams.
+        for i in (0..WINDOW_SIZE).rev()
+        {
+            if truncated && i > reference.unwrap_or(0)
+            {
+                continue;
+            }
+
+            let cycle_i = ((datagram_cycle + MAX_CYCLE) - i) % MAX_CYCLE;
+
+            // If we're before local cycle, skip.  This is effectively checking for distance
+            // being out of the buffer's size, which is only possible if before because we've
+            // already adanced the local cycle to catch up, if applicable.
+            let distance = ((cycle_i + MAX_CYCLE) - self.cycle) % MAX_CYCLE;
+            if distance >= MAX_BUFFERED
+            {
+                if lengths[i].is_some()
+                {
+                    self.stats.duplicate_slots += 1;
+                }
+                continue;
+            }
+
+            let metadata = SinkMetadata {
+                cycle: self.sequence() + (distance as u64),
+                timestamp: datagram_timestamp,
+                receive_timestamp: timestamp,
+                age: i,
+            };
+            self.store(distance, metadata, lengths[i].map(|length| &slots[i][0..length]));
+        }
+
+        // Advance cycles.
+        self.release(timestamp);
+    }
+
+    fn recover(&mut self, timestamp: u16, sequence: u64, datagram_timestamp: u16, payload: &[u8]) -> bool
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let SIZE: usize = self.layout.size;
+        #[allow(non_snake_case)]
+        let MAX_BUFFERED: usize = self.layout.max_buffered;
+        #[allow(non_snake_case)]
+        let LENGTH_SIZE: usize = self.layout.length_size;
+        #[allow(non_snake_case)]
+        let PARITY_SIZE: usize = self.layout.parity_size;
+
+        // Grab the group's shape, which has to fit in our buffer for any of it to still be held.
+        if payload.len() < PARITY_SIZE
+        {
+            return false;
+        }
+        let (group, interleave) = (payload[0] as usize, payload[1] as usize);
+        if !(2..=MAX_PARITY_GROUP).contains(&group) || interleave == 0 || group * interleave >= MAX_BUFFERED
+        {
+            return false;
+        }
+        let Some(first) = sequence.checked_sub((((group - 1) * interleave) + 1) as u64)
+        else
+        {
+            return false;
+        };
+        let presence = u32::from_le_bytes(*<&[u8; 4]>::try_from(&payload[2..6]).unwrap());
+        let mut length = [0; 2];
+        length[0..LENGTH_SIZE].copy_from_slice(&payload[6..(6 + LENGTH_SIZE)]);
+        let mut length = u16::from_le_bytes(length) as usize;
+        let mut slot = payload[(6 + LENGTH_SIZE)..PARITY_SIZE].to_vec();
+
+        // XOR out every member we've received.  With exactly one missing, what's left is its
+        // input.  Members without input are known to be empty whether we saw them or not.
+        let mut missing = None;
+        for row in 0..group
+        {
+            let member = first + ((row * interleave) as u64);
+            if presence & (1 << row) == 0
+            {
+                self.recover_slot(member, timestamp, datagram_timestamp, sequence, None);
+                continue;
+            }
+
+            let index = (member % (MAX_BUFFERED as u64)) as usize;
+            match (self.history_sequences[index] == Some(member), missing)
+            {
+                (true, _) =>
+                {
+                    length ^= self.history_lengths[index];
+                    for (parity, input) in slot.iter_mut().zip(self.history[index].iter())
+                    {
+                        *parity ^= *input;
+                    }
+                }
+                (false, None) => missing = Some(member),
+                (false, Some(_)) => return true,
+            }
+        }
+        if let Some(member) = missing
+        {
+            if length > SIZE
+            {
+                return false;
+            }
+            self.remember(member, &slot[0..length]);
+            if self.recover_slot(member, timestamp, datagram_timestamp, sequence, Some(&slot[0..length]))
+            {
+                self.stats.slots_recovered += 1;
+            }
+        }
+
+        true
+    }
+
+    fn recover_slot(
+        &mut self,
+        member: u64,
+        timestamp: u16,
+        datagram_timestamp: u16,
+        sequence: u64,
+        slot: Option<&[u8]>,
+    ) -> bool
+    {
+        // Members we've already moved past are of no use anymore.
+        let Some(distance) = member.checked_sub(self.sequence())
+        else
+        {
+            return false;
+        };
+        if distance >= self.layout.max_buffered as u64 || self.flags[self.index(distance as usize)]
+        {
+            return false;
+        }
+
+        let metadata = SinkMetadata {
+            cycle: member,
+            timestamp: datagram_timestamp,
+            receive_timestamp: timestamp,
+            age: (sequence - member) as usize,
+        };
+        self.store(distance as usize, metadata, slot);
+        true
+    }
+
+    fn remember(&mut self, sequence: u64, slot: &[u8])
+    {
+        // Keep the newest input for each place in history.
+        let index = (sequence % (self.layout.max_buffered as u64)) as usize;
+        if self.history_sequences[index].is_some_and(|history_sequence| history_sequence >= sequence)
+        {
+            return;
+        }
+        self.history_sequences[index] = Some(sequence);
+        self.history_lengths[index] = slot.len();
+        self.history[index][0..slot.len()].copy_from_slice(slot);
+        self.history[index][slot.len()..].fill(0);
+    }
+
+    fn store(&mut self, distance: usize, metadata: SinkMetadata, slot: Option<&[u8]>)
+    {
+        // Ordered delivery holds on to input until every cycle before it is released.
+        let index = self.index(distance);
+        if self.flags[index]
+        {
+            if slot.is_some()
+            {
+                self.stats.duplicate_slots += 1;
+            }
+            return;
+        }
+
+        // Input the sink can't take is dropped, leaving the cycle as if nothing was sent for it.
+        let slot = match slot
+        {
+            Some(slot) if !self.sink.accepts(slot.len()) =>
+            {
+                self.stats.slots_wrong_length += 1;
+                None
+            }
+            slot => slot,
+        };
+
+        match (self.delivery, slot)
+        {
+            (Delivery::Unordered, Some(slot)) => self.sink.handle(&metadata, slot),
+            (Delivery::Ordered { .. } | Delivery::Buffered { .. }, Some(slot)) =>
+            {
+                self.slots[index][0..slot.len()].copy_from_slice(slot)
+            }
+            (_, None) => (),
+        }
+        self.lengths[index] = slot.map(|slot| slot.len());
+        self.metadata[index] = metadata;
+        self.flags[index] = true;
+    }
+
+    fn sequence(&self) -> u64
+    {
+        (self.rollover * (self.layout.max_cycle as u64)) + (self.cycle as u64)
+    }
+
+    fn index(&self, distance: usize) -> usize
+    {
+        ((self.sequence() + (distance as u64)) % (self.layout.max_buffered as u64)) as usize
+    }
+
+    fn retire_key(&mut self, timestamp: u16)
+    {
+        // Timestamps wrap, so one that's gone back on the last has been all the way around.
+        if let Some(rekey_timestamp) = self.rekey_timestamp
+        {
+            let elapsed = timestamp.wrapping_sub(rekey_timestamp);
+            if elapsed >= self.key_grace_period || elapsed < self.rekey_elapsed
+            {
+                self.previous_ciphers.clear();
+                self.rekey_timestamp = None;
+                self.rekey_elapsed = 0;
+            }
+            else
+            {
+                self.rekey_elapsed = elapsed;
+            }
+        }
+    }
+
+    fn release(&mut self, timestamp: u16)
+    {
+        loop
+        {
+            match (self.flags[self.index(0)], self.delivery)
+            {
+                // Buffered delivery holds on to input until its playout time.
+                (true, Delivery::Buffered { .. }) =>
+                {
+                    if !self.due(timestamp, self.index(0))
+                    {
+                        break;
+                    }
+                }
+                (true, _) => (),
+                (false, Delivery::Unordered) => break,
+                // Ordered delivery gives up on a missing cycle once the next cycle received after
+                // it has waited out the deadline.
+                (false, Delivery::Ordered { deadline }) =>
+                {
+                    let waited = self
+                        .next_received()
+                        .map(|index| timestamp.wrapping_sub(self.metadata[index].receive_timestamp));
+                    if !waited.is_some_and(|waited| waited >= deadline)
+                    {
+                        break;
+                    }
+                    self.stats.cycles_timed_out += 1;
+                }
+                // Buffered delivery gives up on a missing cycle once the next cycle received after
+                // it is due.
+                (false, Delivery::Buffered { .. }) =>
+                {
+                    if !self.next_received().is_some_and(|index| self.due(timestamp, index))
+                    {
+                        break;
+                    }
+                    self.stats.cycles_timed_out += 1;
+                }
+            }
+
+            self.pop();
+        }
+    }
+
+    fn next_received(&self) -> Option<usize>
+    {
+        (1..self.layout.max_buffered)
+            .map(|distance| self.index(distance))
+            .find(|index| self.flags[*index])
+    }
+
+    fn playout_delay(&self) -> u16
+    {
+        match self.delivery
+        {
+            Delivery::Buffered { min_delay, max_delay } => self.jitter_estimate.playout_delay(min_delay, max_delay),
+            _ => 0,
+        }
+    }
+
+    fn due(&self, timestamp: u16, index: usize) -> bool
+    {
+        // Playout times are by our clock, and only ever a little ahead of or behind it.
+        let playout = self
+            .jitter_estimate
+            .playout(self.metadata[index].timestamp, self.playout_delay());
+        serial_diff(timestamp, playout) >= 0
+    }
+
+    fn pop(&mut self) -> bool
+    {
+        // Release held input for the local cycle, if any, or let the sink know it's never coming,
+        // then advance past it.
+        let index = self.index(0);
+        let received = self.flags[index];
+        match received
+        {
+            true =>
+            {
+                if self.delivery != Delivery::Unordered
+                    && let Some(length) = self.lengths[index]
+                {
+                    self.sink.handle(&self.metadata[index], &self.slots[index][0..length]);
+                }
+            }
+            false => self.sink.skipped(self.sequence()),
+        }
+
+        self.flags[index] = false;
+        self.cycle = (self.cycle + 1) % self.layout.max_cycle;
+        if self.cycle == 0
+        {
+            self.rollover += 1;
+        }
+
+        received
+    }
+}
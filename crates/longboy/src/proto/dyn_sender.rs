@@ -0,0 +1,513 @@
+use enum_map::{Enum, EnumMap};
+
+use crate::{
+    check_acknowledgement, decode_acknowledgement, derive_lane_key, encode_delta, encode_heartbeat, Cipher, Encoding,
+    HeaderCheck, Layout, Mirroring, Redundancy, HEADER_CHECK_OFFSET, HEARTBEAT_SIZE, MAX_PARITY_GROUP, PARITY_FLAG,
+    PARITY_SEQUENCE, PROTOCOL_VERSION, TRUNCATED_FLAG,
+};
+
+pub struct DynSender<SourceType, CipherType>
+where
+    CipherType: Cipher,
+{
+    source: SourceType,
+    ciphers: EnumMap<Mirroring, CipherType>,
+    header_checks: EnumMap<Mirroring, HeaderCheck>,
+    epoch: u8,
+    announced_header_checks: Vec<(u8, EnumMap<Mirroring, HeaderCheck>)>,
+    channel_id: u8,
+    encoding: Encoding,
+    keepalive_period: Option<u16>,
+    redundancy: Redundancy,
+    layout: Layout,
+    payload_size: usize,
+
+    cycle: usize,
+    rollover: u64,
+    acknowledged: u64,
+    last_transmit: u16,
+    flags: Vec<bool>,
+    lengths: Vec<usize>,
+    slots: Vec<Vec<u8>>,
+    scratch: Vec<u8>,
+    parity_presence: Vec<u32>,
+    parity_lengths: Vec<usize>,
+    parity_slots: Vec<Vec<u8>>,
+    parity_pending: Option<usize>,
+    plaintext_end: usize,
+    plaintext_sequence: u64,
+    plaintext: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+pub trait DynSource
+where
+    Self: 'static + Send,
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool;
+}
+
+impl<SourceType, CipherType> DynSender<SourceType, CipherType>
+where
+    SourceType: DynSource,
+    CipherType: Cipher,
+{
+    pub fn new(cipher_key: u64, size: usize, window_size: usize, source: SourceType) -> Self
+    {
+        let layout = Layout::new::<CipherType>(size, window_size).unwrap_or_else(|| {
+            panic!(
+                "Input of {} bytes and window of {} don't fit in a datagram",
+                size, window_size
+            )
+        });
+
+        Self {
+            source,
+            ciphers: EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring))),
+            header_checks: EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+            epoch: 0,
+            announced_header_checks: Vec::new(),
+            channel_id: 0,
+            encoding: Encoding::Full,
+            keepalive_period: None,
+            redundancy: Redundancy::Repetition,
+            layout,
+            payload_size: layout.payload_size,
+
+            cycle: 0,
+            rollover: 0,
+            acknowledged: 0,
+            last_transmit: 0,
+            flags: vec![false; window_size],
+            lengths: vec![0; window_size],
+            slots: vec![vec![0; size]; window_size],
+            scratch: Vec::with_capacity(size),
+            parity_presence: Vec::new(),
+            parity_lengths: Vec::new(),
+            parity_slots: Vec::new(),
+            parity_pending: None,
+            plaintext_end: 0,
+            plaintext_sequence: 0,
+            plaintext: vec![0; layout.datagram_size],
+            buffer: vec![0; layout.datagram_size],
+        }
+    }
+
+    pub fn with_channel(mut self, channel_id: u8) -> Self
+    {
+        self.channel_id = channel_id;
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self
+    {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn with_keepalive(mut self, period: u16) -> Self
+    {
+        self.keepalive_period = Some(period);
+        self
+    }
+
+    pub fn with_redundancy(mut self, redundancy: Redundancy) -> Self
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let SIZE: usize = self.layout.size;
+        #[allow(non_snake_case)]
+        let MAX_BUFFERED: usize = self.layout.max_buffered;
+        #[allow(non_snake_case)]
+        let PARITY_SIZE: usize = self.layout.parity_size;
+
+        // Lost members are only rebuilt while the Receiver is still holding their cycle, so a
+        // whole block of groups has to fit in its buffer.
+        if let Redundancy::Parity { group, interleave } = redundancy
+        {
+            assert!(
+                (2..=MAX_PARITY_GROUP).contains(&group),
+                "Parity group of {} outside of 2 to {}",
+                group,
+                MAX_PARITY_GROUP
+            );
+            assert!(
+                interleave > 0 && group * interleave < MAX_BUFFERED,
+                "Parity block of {} cycles exceeds maximum of {}",
+                group * interleave,
+                MAX_BUFFERED - 1
+            );
+            assert!(
+                PARITY_SIZE <= self.payload_size,
+                "Parity of {} bytes exceeds maximum of {}",
+                PARITY_SIZE,
+                self.payload_size
+            );
+
+            self.parity_presence = vec![0; interleave];
+            self.parity_lengths = vec![0; interleave];
+            self.parity_slots = vec![vec![0; SIZE]; interleave];
+        }
+        self.redundancy = redundancy;
+        self
+    }
+
+    pub fn with_max_datagram_size(mut self, max_datagram_size: usize) -> Self
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+        #[allow(non_snake_case)]
+        let PAYLOAD_SIZE: usize = self.layout.payload_size;
+
+        // Budgets below the largest datagram only leave out more of the older slots.
+        let min_datagram_size = self.layout.min_datagram_size(self.redundancy);
+        assert!(
+            max_datagram_size >= min_datagram_size,
+            "Datagram budget of {} bytes below minimum of {}",
+            max_datagram_size,
+            min_datagram_size
+        );
+
+        let payload_size = ((max_datagram_size - HEADER_SIZE - CipherType::TAG_SIZE) / CipherType::BLOCK_SIZE)
+            * CipherType::BLOCK_SIZE;
+        self.payload_size = std::cmp::min(payload_size, PAYLOAD_SIZE);
+        self
+    }
+
+    pub fn layout(&self) -> &Layout
+    {
+        &self.layout
+    }
+
+    pub fn cycle(&self) -> usize
+    {
+        self.cycle
+    }
+
+    pub fn acknowledge(&mut self, sequence: u64)
+    {
+        // Acknowledgements arrive out of band and out of order, so only ever move forward, and
+        // never past what we've actually sent.
+        let next_sequence = (self.rollover * (self.layout.max_cycle as u64)) + (self.cycle as u64);
+        self.acknowledged = std::cmp::max(self.acknowledged, std::cmp::min(sequence, next_sequence));
+    }
+
+    pub fn check_acknowledgement(&self, session_id: u64, datagram: &[u8]) -> Option<u64>
+    {
+        // Only acknowledgements for this Session that check out under our current key for
+        // their lane, or one announced to the Receiver that we've yet to switch over to, are
+        // taken at their word.
+        let acknowledgement = decode_acknowledgement(self.channel_id, datagram)?;
+        let header_checks = self.header_checks(acknowledgement.epoch)?;
+        (acknowledgement.session_id == session_id
+            && check_acknowledgement(&header_checks[acknowledgement.mirroring], datagram))
+        .then_some(acknowledgement.sequence)
+    }
+
+    pub fn heartbeat_datagram(&self, session_id: u64, mirroring: Mirroring) -> [u8; HEARTBEAT_SIZE]
+    {
+        encode_heartbeat(
+            &self.header_checks[mirroring],
+            self.channel_id,
+            session_id,
+            self.epoch,
+            mirroring,
+        )
+    }
+
+    pub fn announce_key(&mut self, epoch: u8, cipher_key: u64)
+    {
+        // Receivers take keys as soon as they're announced, well before we switch over, and
+        // acknowledge under them in the meantime.
+        self.announced_header_checks.push((
+            epoch,
+            EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+        ));
+    }
+
+    pub fn rekey(&mut self, epoch: u8, cipher_key: u64)
+    {
+        // Sequences carry on across keys, so nonces never repeat under either of them.  Keys
+        // announced before this one are never switched over to.
+        self.ciphers = EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring)));
+        self.header_checks = EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring));
+        self.epoch = epoch;
+        if let Some(position) = self
+            .announced_header_checks
+            .iter()
+            .position(|(announced_epoch, _)| *announced_epoch == epoch)
+        {
+            self.announced_header_checks.drain(0..=position);
+        }
+    }
+
+    pub fn poll_datagram(&mut self, timestamp: u16) -> Option<&[u8]>
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let SIZE: usize = self.layout.size;
+        #[allow(non_snake_case)]
+        let WINDOW_SIZE: usize = self.layout.window_size;
+        #[allow(non_snake_case)]
+        let MAX_CYCLE: usize = self.layout.max_cycle;
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+        #[allow(non_snake_case)]
+        let LENGTH_SIZE: usize = self.layout.length_size;
+
+        // Poll source.
+        let sequence = (self.rollover * (MAX_CYCLE as u64)) + (self.cycle as u64);
+        let index = self.cycle % WINDOW_SIZE;
+        self.scratch.clear();
+        match self.source.poll(&mut self.scratch)
+        {
+            true =>
+            {
+                let length = self.scratch.len();
+                assert!(length <= SIZE, "Input of {} bytes exceeds maximum of {}", length, SIZE);
+
+                // Zero the rest of the slot so deltas against it see a consistent tail.
+                self.slots[index][0..length].copy_from_slice(&self.scratch);
+                self.slots[index][length..].fill(0);
+                self.lengths[index] = length;
+                self.flags[index] = true;
+            }
+            false =>
+            {
+                self.lengths[index] = 0;
+                self.flags[index] = false;
+            }
+        }
+
+        // Fold input into its parity group instead of repeating it.  Groups are interleaved
+        // across cycles, so a burst of lost datagrams no longer than the interleave takes out at
+        // most one member of each.  A cycle can be polled again if it didn't transmit, so the group
+        // is only handed to poll_parity once its last member goes out, or its parity would be
+        // sealed twice under the same sequence.
+        let mut completed = None;
+        if let Redundancy::Parity { group, interleave } = self.redundancy
+        {
+            let position = (sequence % ((group * interleave) as u64)) as usize;
+            let (column, row) = (position % interleave, position / interleave);
+            if row == 0
+            {
+                self.parity_presence[column] = 0;
+                self.parity_lengths[column] = 0;
+                self.parity_slots[column].fill(0);
+            }
+            if self.flags[index]
+            {
+                self.parity_presence[column] |= 1 << row;
+                self.parity_lengths[column] ^= self.lengths[index];
+                for (parity, input) in self.parity_slots[column].iter_mut().zip(self.slots[index].iter())
+                {
+                    *parity ^= *input;
+                }
+            }
+            if row == group - 1 && self.parity_presence[column] != 0
+            {
+                completed = Some(column);
+            }
+        }
+
+        // Slots older than the Receiver's acknowledgement have already been handed off, so
+        // there's no point in sending them again.  They're left out as if they had no input,
+        // which the Receiver won't look at since it's past them.  Parity protected datagrams
+        // only ever carry the newest slot.
+        let span = match self.redundancy
+        {
+            Redundancy::Repetition => std::cmp::min(sequence - self.acknowledged, (WINDOW_SIZE - 1) as u64) as usize,
+            Redundancy::Parity { .. } => 0,
+        };
+
+        // Check for transmit.  Idle senders still send an empty window every keepalive period,
+        // which takes up a cycle like any other datagram.
+        if !(0..=span).any(|age| self.flags[((self.cycle + WINDOW_SIZE) - age) % WINDOW_SIZE])
+            && !self
+                .keepalive_period
+                .is_some_and(|period| timestamp.wrapping_sub(self.last_transmit) >= period)
+        {
+            return None;
+        }
+        self.last_transmit = timestamp;
+        if completed.is_some()
+        {
+            self.parity_pending = completed;
+        }
+
+        // Encode present slots newest first as length and input.  With delta encoding, every
+        // slot but the newest holds its input XORed against the next newer slot instead, run
+        // length coded.  Older slots that no longer fit are left out, and the datagram flagged
+        // as truncated so the receiver doesn't take them for slots without input.  Parity
+        // protected datagrams leave them out on purpose, flagged the same way.
+        let payload_end = HEADER_SIZE + self.payload_size;
+        let presence_start = HEADER_SIZE - self.layout.presence_size;
+        self.plaintext[presence_start..HEADER_SIZE].fill(0);
+        let mut end = HEADER_SIZE;
+        let mut reference: Option<usize> = None;
+        let mut truncated = matches!(self.redundancy, Redundancy::Parity { .. }) && WINDOW_SIZE > 1;
+        for age in 0..=span
+        {
+            let index = ((self.cycle + WINDOW_SIZE) - age) % WINDOW_SIZE;
+            if !self.flags[index]
+            {
+                continue;
+            }
+
+            let length = self.lengths[index];
+            let start = end + LENGTH_SIZE;
+            if start > payload_end
+            {
+                truncated = true;
+                break;
+            }
+            let encoded_length = match (self.encoding, reference)
+            {
+                (Encoding::Delta, Some(reference)) => encode_delta(
+                    &self.slots[index][0..length],
+                    &self.slots[reference],
+                    &mut self.plaintext[start..payload_end],
+                ),
+                _ => match start + length <= payload_end
+                {
+                    true =>
+                    {
+                        self.plaintext[start..(start + length)].copy_from_slice(&self.slots[index][0..length]);
+                        Some(length)
+                    }
+                    false => None,
+                },
+            };
+            let Some(encoded_length) = encoded_length
+            else
+            {
+                truncated = true;
+                break;
+            };
+
+            self.plaintext[end..start].copy_from_slice(&(length as u16).to_le_bytes()[0..LENGTH_SIZE]);
+            self.plaintext[presence_start + (index / 8)] |= 1 << (index % 8);
+            end = start + encoded_length;
+            reference = Some(index);
+        }
+
+        // Record version, channel, cycle, timestamp, flags and key epoch, then pad out to the
+        // cipher's block size.
+        self.plaintext[0] = PROTOCOL_VERSION;
+        self.plaintext[1] = self.channel_id;
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[2..4]).unwrap() = (self.cycle as u16).to_le_bytes();
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[4..6]).unwrap() = timestamp.to_le_bytes();
+        self.plaintext[6] = match truncated
+        {
+            true => self.encoding.flags() | TRUNCATED_FLAG,
+            false => self.encoding.flags(),
+        };
+        self.plaintext[7] = self.epoch;
+        let padded_end = HEADER_SIZE + (end - HEADER_SIZE).next_multiple_of(CipherType::BLOCK_SIZE);
+        self.plaintext[end..padded_end].fill(0);
+
+        // Keep the plaintext so it can be sealed for each lane in turn, which records the lane
+        // after the key epoch and the header's check value after that.
+        self.plaintext_end = padded_end;
+        self.plaintext_sequence = sequence;
+
+        // Advance cycle.
+        self.cycle = (self.cycle + 1) % MAX_CYCLE;
+        if self.cycle == 0
+        {
+            self.rollover += 1;
+        }
+
+        Some(self.mirror_datagram(Mirroring::AudioVideo))
+    }
+
+    pub fn poll_parity(&mut self, timestamp: u16) -> Option<&[u8]>
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let MAX_CYCLE: usize = self.layout.max_cycle;
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+        #[allow(non_snake_case)]
+        let LENGTH_SIZE: usize = self.layout.length_size;
+        #[allow(non_snake_case)]
+        let PARITY_SIZE: usize = self.layout.parity_size;
+
+        let column = self.parity_pending.take()?;
+        let Redundancy::Parity { group, interleave } = self.redundancy
+        else
+        {
+            unreachable!();
+        };
+
+        // Record the group's shape and presence, then its XORed lengths and input.  Parity goes
+        // out on the cycle after its group's last member, which the Receiver counts back from.
+        let presence_start = HEADER_SIZE - self.layout.presence_size;
+        self.plaintext[presence_start..HEADER_SIZE].fill(0);
+        let start = HEADER_SIZE;
+        self.plaintext[start] = group as u8;
+        self.plaintext[start + 1] = interleave as u8;
+        *<&mut [u8; 4]>::try_from(&mut self.plaintext[(start + 2)..(start + 6)]).unwrap() =
+            self.parity_presence[column].to_le_bytes();
+        self.plaintext[(start + 6)..(start + 6 + LENGTH_SIZE)]
+            .copy_from_slice(&(self.parity_lengths[column] as u16).to_le_bytes()[0..LENGTH_SIZE]);
+        self.plaintext[(start + 6 + LENGTH_SIZE)..(start + PARITY_SIZE)].copy_from_slice(&self.parity_slots[column]);
+        let end = start + PARITY_SIZE;
+
+        // Record version, channel, cycle, timestamp, flags and key epoch, then pad out to the
+        // cipher's block size.
+        self.plaintext[0] = PROTOCOL_VERSION;
+        self.plaintext[1] = self.channel_id;
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[2..4]).unwrap() = (self.cycle as u16).to_le_bytes();
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[4..6]).unwrap() = timestamp.to_le_bytes();
+        self.plaintext[6] = PARITY_FLAG;
+        self.plaintext[7] = self.epoch;
+        let padded_end = HEADER_SIZE + (end - HEADER_SIZE).next_multiple_of(CipherType::BLOCK_SIZE);
+        self.plaintext[end..padded_end].fill(0);
+
+        self.plaintext_end = padded_end;
+        self.plaintext_sequence = ((self.rollover * (MAX_CYCLE as u64)) + (self.cycle as u64)) | PARITY_SEQUENCE;
+
+        Some(self.mirror_datagram(Mirroring::AudioVideo))
+    }
+
+    pub fn mirror_datagram(&mut self, mirroring: Mirroring) -> &[u8]
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+
+        assert!(self.plaintext_end > 0, "No datagram to mirror");
+        let padded_end = self.plaintext_end;
+        let datagram_size = padded_end + CipherType::TAG_SIZE;
+        self.buffer[0..padded_end].copy_from_slice(&self.plaintext[0..padded_end]);
+        self.buffer[8] = Mirroring::into_usize(mirroring) as u8;
+
+        // Check the header under the lane's key, then seal window, authenticating the header
+        // along with it, then protect the header.
+        let cipher = &self.ciphers[mirroring];
+        let (header, rest) = self.buffer[0..datagram_size].split_at_mut(HEADER_SIZE);
+        let check = self.header_checks[mirroring].compute(header, self.plaintext_sequence & !PARITY_SEQUENCE);
+        *<&mut [u8; 2]>::try_from(&mut header[HEADER_CHECK_OFFSET..(HEADER_CHECK_OFFSET + 2)]).unwrap() =
+            check.to_le_bytes();
+        let (payload, tag) = rest.split_at_mut(padded_end - HEADER_SIZE);
+        cipher.seal(self.plaintext_sequence, header, payload, tag);
+        cipher.encrypt_header(<&mut [u8; 4]>::try_from(&mut header[2..6]).unwrap());
+
+        &self.buffer[0..datagram_size]
+    }
+
+    fn header_checks(&self, epoch: u8) -> Option<&EnumMap<Mirroring, HeaderCheck>>
+    {
+        match epoch == self.epoch
+        {
+            true => Some(&self.header_checks),
+            false => self
+                .announced_header_checks
+                .iter()
+                .find(|(announced_epoch, _)| *announced_epoch == epoch)
+                .map(|(_, header_checks)| header_checks),
+        }
+    }
+}
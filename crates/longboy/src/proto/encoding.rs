@@ -0,0 +1,32 @@
+// Header flags, alongside the encoding.
+pub(crate) const TRUNCATED_FLAG: u8 = 0x02;
+pub(crate) const PARITY_FLAG: u8 = 0x04;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding
+{
+    Full,
+    Delta,
+}
+
+impl Encoding
+{
+    pub(crate) fn flags(self) -> u8
+    {
+        match self
+        {
+            Encoding::Full => 0x00,
+            Encoding::Delta => 0x01,
+        }
+    }
+
+    pub(crate) fn from_flags(flags: u8) -> Option<Self>
+    {
+        match flags
+        {
+            0x00 => Some(Encoding::Full),
+            0x01 => Some(Encoding::Delta),
+            _ => None,
+        }
+    }
+}
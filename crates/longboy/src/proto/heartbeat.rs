@@ -0,0 +1,59 @@
+use enum_map::Enum;
+
+use crate::{supports_version, HeaderCheck, Mirroring, PROTOCOL_VERSION};
+
+// Version, channel, Session ID, key epoch and lane, then a check over all of it under the lane's
+// key.
+pub(crate) const HEARTBEAT_SIZE: usize = 12 + std::mem::size_of::<u64>();
+
+// Heartbeats tell the Server where to find each of a Client's lanes, so like acknowledgements
+// they're sent in the clear and only trusted once they check out under the Session's key.
+pub(crate) fn encode_heartbeat(
+    header_check: &HeaderCheck,
+    channel_id: u8,
+    session_id: u64,
+    epoch: u8,
+    mirroring: Mirroring,
+) -> [u8; HEARTBEAT_SIZE]
+{
+    let mut buffer = [0; HEARTBEAT_SIZE];
+    buffer[0] = PROTOCOL_VERSION;
+    buffer[1] = channel_id;
+    *<&mut [u8; 8]>::try_from(&mut buffer[2..10]).unwrap() = session_id.to_le_bytes();
+    buffer[10] = epoch;
+    buffer[11] = Mirroring::into_usize(mirroring) as u8;
+    let check = header_check.authenticate(&buffer[0..12]);
+    *<&mut [u8; 8]>::try_from(&mut buffer[12..20]).unwrap() = check.to_le_bytes();
+    buffer
+}
+
+// Everything a heartbeat says about itself, before it's been checked.
+pub(crate) struct Heartbeat
+{
+    pub(crate) session_id: u64,
+    pub(crate) epoch: u8,
+    pub(crate) mirroring: Mirroring,
+}
+
+pub(crate) fn decode_heartbeat(channel_id: u8, buffer: &[u8]) -> Option<Heartbeat>
+{
+    if buffer.len() != HEARTBEAT_SIZE
+        || !supports_version(buffer[0])
+        || buffer[1] != channel_id
+        || (buffer[11] as usize) >= Mirroring::LENGTH
+    {
+        return None;
+    }
+
+    Some(Heartbeat {
+        session_id: u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[2..10]).unwrap()),
+        epoch: buffer[10],
+        mirroring: Mirroring::from_usize(buffer[11] as usize),
+    })
+}
+
+pub(crate) fn check_heartbeat(header_check: &HeaderCheck, buffer: &[u8]) -> bool
+{
+    let check = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[12..20]).unwrap());
+    check == header_check.authenticate(&buffer[0..12])
+}
@@ -0,0 +1,69 @@
+use crate::serial_diff;
+
+// Playout delay covers this many times the jitter.
+const JITTER_MULTIPLIER: u16 = 4;
+
+pub(crate) struct JitterEstimate
+{
+    origin: Option<u16>,
+    last_transit: i32,
+    transit: i32,
+    jitter: i32,
+}
+
+impl JitterEstimate
+{
+    pub(crate) fn new() -> Self
+    {
+        Self {
+            origin: None,
+            last_transit: 0,
+            transit: 0,
+            jitter: 0,
+        }
+    }
+
+    pub(crate) fn observe(&mut self, timestamp: u16, receive_timestamp: u16)
+    {
+        // Transit takes in the offset between clocks as well, which wraps, so transits are kept
+        // relative to the first one seen.  Both the mean transit and the jitter between
+        // consecutive transits are smoothed with a gain of 1/16, in sixteenths of a tick.
+        let sample = receive_timestamp.wrapping_sub(timestamp);
+        let origin = *self.origin.get_or_insert(sample);
+        let transit = (serial_diff(sample, origin) as i32) * 16;
+        self.jitter += ((transit - self.last_transit).abs() - self.jitter) / 16;
+        self.transit += (transit - self.transit) / 16;
+        self.last_transit = transit;
+    }
+
+    pub(crate) fn lag(&self, timestamp: u16, receive_timestamp: u16) -> Option<i32>
+    {
+        // How much longer than the mean transit a datagram sent at the timestamp took, by our
+        // clock.  The clocks on either end never agree, so there's nothing to go by until the
+        // first transit is in.
+        let origin = self.origin?;
+        let transit = serial_diff(receive_timestamp.wrapping_sub(timestamp), origin) as i32;
+        Some(transit - (self.transit / 16))
+    }
+
+    pub(crate) fn jitter(&self) -> u16
+    {
+        (self.jitter / 16) as u16
+    }
+
+    pub(crate) fn playout_delay(&self, min_delay: u16, max_delay: u16) -> u16
+    {
+        // Scaled before rounding to whole ticks, so it doesn't jump by the multiplier at a time.
+        let delay = ((self.jitter * (JITTER_MULTIPLIER as i32)) / 16).clamp(0, u16::MAX as i32) as u16;
+        std::cmp::min(std::cmp::max(delay, min_delay), max_delay)
+    }
+
+    pub(crate) fn playout(&self, timestamp: u16, delay: u16) -> u16
+    {
+        // When input sent at the timestamp arrives on average, by our clock, then the delay.
+        timestamp
+            .wrapping_add(self.origin.unwrap_or(0))
+            .wrapping_add((self.transit / 16) as u16)
+            .wrapping_add(delay)
+    }
+}
@@ -0,0 +1,28 @@
+use enum_map::Enum;
+
+use crate::Mirroring;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Direction
+{
+    ClientToServer,
+    ServerToClient,
+}
+
+pub(crate) fn derive_channel_key(cipher_key: u64, name: &str, direction: Direction) -> u64
+{
+    // Name goes last so it can't run into the fixed size fields before it.
+    let mut hasher = blake3::Hasher::new_derive_key("longboy 2024-07 channel key");
+    hasher.update(&cipher_key.to_le_bytes());
+    hasher.update(&[direction as u8]);
+    hasher.update(name.as_bytes());
+    u64::from_le_bytes(*hasher.finalize().as_bytes().first_chunk().unwrap())
+}
+
+pub(crate) fn derive_lane_key(cipher_key: u64, mirroring: Mirroring) -> u64
+{
+    let mut hasher = blake3::Hasher::new_derive_key("longboy 2024-07 lane key");
+    hasher.update(&cipher_key.to_le_bytes());
+    hasher.update(&[Mirroring::into_usize(mirroring) as u8]);
+    u64::from_le_bytes(*hasher.finalize().as_bytes().first_chunk().unwrap())
+}
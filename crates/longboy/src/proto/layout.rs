@@ -0,0 +1,110 @@
+use crate::{Cipher, Redundancy};
+
+// Sizes of everything in a datagram for an input size and window, worked out at runtime.  The
+// const generic Sender and Receiver get theirs from the same place, so both agree on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout
+{
+    pub size: usize,
+    pub window_size: usize,
+    pub block_size: usize,
+    pub tag_size: usize,
+
+    pub presence_size: usize,
+    pub header_size: usize,
+    pub length_size: usize,
+    pub parity_size: usize,
+    pub payload_size: usize,
+    pub datagram_size: usize,
+    pub max_cycle: usize,
+    pub max_buffered: usize,
+}
+
+impl Layout
+{
+    // The largest UDP payload an unfragmented Ethernet frame carries.  Schemas budget for less
+    // on paths that can't take it.
+    pub const MAX_DATAGRAM_SIZE: usize = 1472;
+
+    // Nothing when the newest slot alone can't fit in a datagram.
+    pub const fn new<CipherType>(size: usize, window_size: usize) -> Option<Self>
+    where
+        CipherType: Cipher,
+    {
+        if window_size == 0 || window_size > (u16::MAX as usize)
+        {
+            return None;
+        }
+
+        let presence_size = window_size.div_ceil(8);
+        let header_size = (std::mem::size_of::<u16>() * 3) + (std::mem::size_of::<u8>() * 5) + presence_size;
+        let length_size = match size <= (u8::MAX as usize)
+        {
+            true => 1,
+            false => 2,
+        };
+
+        // Parity datagrams carry their group's shape and presence, then the length and input of
+        // every present member XORed together.
+        let parity_size = (std::mem::size_of::<u8>() * 2) + std::mem::size_of::<u32>() + length_size + size;
+
+        // Delta encoded slots can run past their input by a token per 128 bytes.  Only the newest
+        // slot must fit, older slots are left out of datagrams once they run out of room.  Room is
+        // made for parity where it fits.
+        if header_size + CipherType::TAG_SIZE >= Self::MAX_DATAGRAM_SIZE
+        {
+            return None;
+        }
+        let max_payload_size = ((Self::MAX_DATAGRAM_SIZE - header_size - CipherType::TAG_SIZE)
+            / CipherType::BLOCK_SIZE)
+            * CipherType::BLOCK_SIZE;
+        if length_size + size > max_payload_size
+        {
+            return None;
+        }
+        let payload_size =
+            (window_size * (length_size + size + size.div_ceil(128))).next_multiple_of(CipherType::BLOCK_SIZE);
+        let payload_size = match payload_size < parity_size
+        {
+            true => parity_size.next_multiple_of(CipherType::BLOCK_SIZE),
+            false => payload_size,
+        };
+        let payload_size = match payload_size < max_payload_size
+        {
+            true => payload_size,
+            false => max_payload_size,
+        };
+
+        Some(Self {
+            size,
+            window_size,
+            block_size: CipherType::BLOCK_SIZE,
+            tag_size: CipherType::TAG_SIZE,
+
+            presence_size,
+            header_size,
+            length_size,
+            parity_size,
+            payload_size,
+            datagram_size: header_size + payload_size + CipherType::TAG_SIZE,
+            max_cycle: ((u16::MAX as usize) / window_size) * window_size,
+            max_buffered: match window_size < 4
+            {
+                true => 8,
+                false => window_size * 2,
+            },
+        })
+    }
+
+    // The smallest budget a schema can give its datagrams, which still has to fit the newest
+    // slot, and parity if it's sent.
+    pub const fn min_datagram_size(&self, redundancy: Redundancy) -> usize
+    {
+        let payload_size = match redundancy
+        {
+            Redundancy::Repetition => self.length_size + self.size,
+            Redundancy::Parity { .. } => self.parity_size,
+        };
+        self.header_size + payload_size.next_multiple_of(self.block_size) + self.tag_size
+    }
+}
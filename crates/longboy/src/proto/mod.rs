@@ -1,13 +1,79 @@
 // API
+mod cipher;
+pub use self::cipher::*;
+
+mod ciphers;
+pub use self::ciphers::*;
+
 mod constants;
 pub use self::constants::*;
 
+mod delivery;
+pub use self::delivery::*;
+
+mod dyn_receiver;
+pub use self::dyn_receiver::*;
+
+mod dyn_sender;
+pub use self::dyn_sender::*;
+
+mod encoding;
+pub use self::encoding::*;
+
+mod layout;
+pub use self::layout::*;
+
 mod sender;
 pub use self::sender::*;
 
+mod sink_metadata;
+pub use self::sink_metadata::*;
+
 mod receiver;
 pub use self::receiver::*;
 
+mod receiver_stats;
+pub use self::receiver_stats::*;
+
+mod redundancy;
+pub use self::redundancy::*;
+
+mod snapshot_layout;
+pub use self::snapshot_layout::*;
+
+mod snapshot_receiver;
+pub use self::snapshot_receiver::*;
+
+mod snapshot_sender;
+pub use self::snapshot_sender::*;
+
+mod snapshot_sender_stats;
+pub use self::snapshot_sender_stats::*;
+
+mod snapshot_stats;
+pub use self::snapshot_stats::*;
+
+mod version;
+pub use self::version::*;
+
 // Internal
-mod cipher;
-pub(crate) use self::cipher::*;
+mod acknowledgement;
+pub(crate) use self::acknowledgement::*;
+
+mod delta;
+pub(crate) use self::delta::*;
+
+mod header_check;
+pub(crate) use self::header_check::*;
+
+mod heartbeat;
+pub(crate) use self::heartbeat::*;
+
+mod jitter_estimate;
+pub(crate) use self::jitter_estimate::*;
+
+mod key_derivation;
+pub(crate) use self::key_derivation::*;
+
+mod replay_window;
+pub(crate) use self::replay_window::*;
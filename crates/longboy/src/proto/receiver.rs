@@ -1,16 +1,14 @@
-use crate::{Cipher, Constants};
+use crate::{
+    Cipher, Constants, Delivery, DynReceiver, DynSink, Mirroring, Rc5Cipher, ReceiverStats, SinkMetadata,
+    ACKNOWLEDGEMENT_SIZE,
+};
 
-pub struct Receiver<SinkType, const SIZE: usize, const WINDOW_SIZE: usize>
+pub struct Receiver<SinkType, const SIZE: usize, const WINDOW_SIZE: usize, CipherType = Rc5Cipher>
 where
-    SinkType: Sink<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkType: MetadataSink<SIZE>,
+    CipherType: Cipher,
 {
-    sink: SinkType,
-    cipher: Cipher<SIZE>,
-
-    cycle: usize,
-    flags: [bool; <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED],
+    inner: DynReceiver<SizedSink<SinkType, SIZE>, CipherType>,
 }
 
 pub trait Sink<const SIZE: usize>
@@ -18,120 +16,175 @@ where
     Self: 'static + Send,
 {
     fn handle(&mut self, buffer: &[u8; SIZE]);
+
+    fn skipped(&mut self, _cycle: u64)
+    {
+    }
 }
 
-impl<SinkType, const SIZE: usize, const WINDOW_SIZE: usize> Receiver<SinkType, SIZE, WINDOW_SIZE>
+pub trait VariableSink<const SIZE: usize>
 where
-    SinkType: Sink<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    Self: 'static + Send,
 {
-    pub fn new(cipher_key: u64, sink: SinkType) -> Self
+    // Whether the sink only takes input of exactly SIZE, so the Receiver can turn away and count
+    // anything else before it gets here.
+    const FIXED_SIZE: bool = false;
+
+    fn handle(&mut self, buffer: &[u8]);
+
+    fn skipped(&mut self, _cycle: u64)
     {
-        Self {
-            sink,
-            cipher: Cipher::new(cipher_key),
+    }
+}
+
+impl<SinkType, const SIZE: usize> VariableSink<SIZE> for SinkType
+where
+    SinkType: Sink<SIZE>,
+{
+    const FIXED_SIZE: bool = true;
 
-            cycle: 0,
-            flags: [false; <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED],
+    fn handle(&mut self, buffer: &[u8])
+    {
+        // Fixed size sinks only ever see input of exactly their size, anything else having been
+        // counted and dropped by the Receiver.
+        if let Ok(buffer) = <&[u8; SIZE]>::try_from(buffer)
+        {
+            Sink::handle(self, buffer);
         }
     }
 
-    pub fn cycle(&self) -> usize
+    fn skipped(&mut self, cycle: u64)
     {
-        self.cycle
+        Sink::skipped(self, cycle);
     }
+}
 
-    pub fn handle_datagram(
-        &mut self,
-        timestamp: u16,
-        datagram: &mut [u8; <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE],
-    )
+pub trait MetadataSink<const SIZE: usize>
+where
+    Self: 'static + Send,
+{
+    const FIXED_SIZE: bool = false;
+
+    fn handle(&mut self, metadata: &SinkMetadata, buffer: &[u8]);
+
+    fn skipped(&mut self, _cycle: u64)
     {
-        // Alias constants so they're less painful to read.
-        #[allow(non_snake_case)]
-        let MAX_CYCLE: usize = Constants::<SIZE, WINDOW_SIZE>::MAX_CYCLE;
-        #[allow(non_snake_case)]
-        let MAX_BUFFERED: usize = Constants::<SIZE, WINDOW_SIZE>::MAX_BUFFERED;
+    }
+}
 
-        // Grab cycle and timestamp.
-        self.cipher
-            .decrypt_header(<&mut [u8; 4]>::try_from(&mut datagram[0..4]).unwrap());
-        let datagram_cycle = u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[0..2]).unwrap()) as usize;
-        let datagram_timestamp = u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[2..4]).unwrap());
+impl<SinkType, const SIZE: usize> MetadataSink<SIZE> for SinkType
+where
+    SinkType: VariableSink<SIZE>,
+{
+    const FIXED_SIZE: bool = <SinkType as VariableSink<SIZE>>::FIXED_SIZE;
 
-        // Calculate diff for cycle and timestamp.
-        let cycle_diff = ((datagram_cycle + MAX_CYCLE) - self.cycle) % MAX_CYCLE;
-        let timestamp_diff = ((datagram_timestamp + u16::MAX) - timestamp) % u16::MAX;
+    fn handle(&mut self, _metadata: &SinkMetadata, buffer: &[u8])
+    {
+        VariableSink::handle(self, buffer);
+    }
 
-        // Check for bad datagrams or late datagrams that are already processed.  Because
-        // we ensure only a positive diff, this is done by checking for any values greater
-        // that a certain threshold.
-        if cycle_diff > 256 || timestamp_diff > 2048
-        {
-            // Bad datagram or already received.
-            return;
-        }
+    fn skipped(&mut self, cycle: u64)
+    {
+        VariableSink::skipped(self, cycle);
+    }
+}
 
-        // Check for late or missing packets from between local cycle and the datagram
-        // cycle just received.
-        if cycle_diff > std::cmp::min(8, WINDOW_SIZE + 1)
-        {
-            // soft warning
+// Hands input to a fixed size sink from the runtime sized Receiver underneath.
+pub(crate) struct SizedSink<SinkType, const SIZE: usize>(pub(crate) SinkType);
+
+impl<SinkType, const SIZE: usize> DynSink for SizedSink<SinkType, SIZE>
+where
+    SinkType: MetadataSink<SIZE>,
+{
+    fn handle(&mut self, metadata: &SinkMetadata, buffer: &[u8])
+    {
+        MetadataSink::handle(&mut self.0, metadata, buffer);
+    }
+
+    fn accepts(&self, length: usize) -> bool
+    {
+        !SinkType::FIXED_SIZE || length == SIZE
+    }
+
+    fn skipped(&mut self, cycle: u64)
+    {
+        MetadataSink::skipped(&mut self.0, cycle);
+    }
+}
+
+impl<SinkType, const SIZE: usize, const WINDOW_SIZE: usize, CipherType>
+    Receiver<SinkType, SIZE, WINDOW_SIZE, CipherType>
+where
+    SinkType: MetadataSink<SIZE>,
+    CipherType: Cipher,
+{
+    pub fn new(cipher_key: u64, sink: SinkType) -> Self
+    {
+        // Input and window that don't fit in a datagram fail to build rather than panic here.
+        const { Constants::<SIZE, WINDOW_SIZE, CipherType>::LAYOUT };
+        Self {
+            inner: DynReceiver::new(cipher_key, SIZE, WINDOW_SIZE, SizedSink(sink)),
         }
-        if cycle_diff > MAX_BUFFERED
-        {
-            // hard warning
-
-            for _ in 0..(cycle_diff - MAX_BUFFERED)
-            {
-                let index = self.cycle % MAX_BUFFERED;
-                self.flags[index] = false;
-                self.cycle = (self.cycle + 1) % MAX_CYCLE;
-            }
+    }
+
+    pub fn with_channel(self, channel_id: u8) -> Self
+    {
+        Self {
+            inner: self.inner.with_channel(channel_id),
         }
+    }
 
-        // Sink input.
-        for i in 0..WINDOW_SIZE
-        {
-            let cycle_i = ((datagram_cycle + MAX_CYCLE) - i) % MAX_CYCLE;
-
-            // If we're before local cycle, early out.  This is effectively checking for distance
-            // being out of the buffer's size, which is only possible if before because we've
-            // already adanced the local cycle to catch up, if applicable.
-            if ((cycle_i + MAX_CYCLE) - self.cycle) % MAX_CYCLE > MAX_BUFFERED
-            {
-                break;
-            }
-
-            let source_index = cycle_i % WINDOW_SIZE;
-            let destination_index = cycle_i % MAX_BUFFERED;
-
-            if !self.flags[destination_index]
-            {
-                let start = (std::mem::size_of::<u16>() * 2) + (SIZE * source_index);
-                let end = start + SIZE;
-                if *<&[u8; SIZE]>::try_from(&datagram[start..end]).unwrap() != [0; SIZE]
-                {
-                    self.cipher
-                        .decrypt_slot(<&mut [u8; SIZE]>::try_from(&mut datagram[start..end]).unwrap());
-                    self.sink
-                        .handle(<&[u8; SIZE]>::try_from(&datagram[start..end]).unwrap());
-                }
-                self.flags[destination_index] = true;
-            }
+    pub fn with_delivery(self, delivery: Delivery) -> Self
+    {
+        Self {
+            inner: self.inner.with_delivery(delivery),
         }
+    }
 
-        // Advance cycles.
-        loop
-        {
-            let index = self.cycle % MAX_BUFFERED;
-            if !self.flags[index]
-            {
-                break;
-            }
-            self.flags[index] = false;
-            self.cycle = (self.cycle + 1) % MAX_CYCLE;
+    pub fn with_key_grace_period(self, period: u16) -> Self
+    {
+        Self {
+            inner: self.inner.with_key_grace_period(period),
         }
     }
+
+    pub fn cycle(&self) -> usize
+    {
+        self.inner.cycle()
+    }
+
+    pub fn acknowledgement(&self) -> u64
+    {
+        self.inner.acknowledgement()
+    }
+
+    pub fn acknowledgement_datagram(&self, session_id: u64, mirroring: Mirroring) -> [u8; ACKNOWLEDGEMENT_SIZE]
+    {
+        self.inner.acknowledgement_datagram(session_id, mirroring)
+    }
+
+    pub fn check_heartbeat(&self, session_id: u64, datagram: &[u8]) -> Option<Mirroring>
+    {
+        self.inner.check_heartbeat(session_id, datagram)
+    }
+
+    pub fn stats(&self) -> ReceiverStats
+    {
+        self.inner.stats()
+    }
+
+    pub fn rekey(&mut self, epoch: u8, cipher_key: u64)
+    {
+        self.inner.rekey(epoch, cipher_key);
+    }
+
+    pub fn poll(&mut self, timestamp: u16)
+    {
+        self.inner.poll(timestamp);
+    }
+
+    pub fn handle_datagram(&mut self, timestamp: u16, datagram: &mut [u8])
+    {
+        self.inner.handle_datagram(timestamp, datagram);
+    }
 }
@@ -0,0 +1,23 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReceiverStats
+{
+    pub datagrams_accepted: u64,
+    pub datagrams_stale: u64,
+    pub datagrams_replayed: u64,
+    pub datagrams_malformed: u64,
+    pub datagrams_unsupported_version: u64,
+    pub datagrams_unknown_epoch: u64,
+    pub datagrams_unknown_channel: u64,
+    pub header_check_failures: u64,
+    pub authentication_failures: u64,
+
+    pub duplicate_slots: u64,
+    pub slots_wrong_length: u64,
+    pub slots_recovered: u64,
+    pub cycles_skipped: u64,
+    pub cycles_timed_out: u64,
+    pub soft_warnings: u64,
+    pub max_cycle_gap: usize,
+    pub jitter: u16,
+    pub playout_delay: u16,
+}
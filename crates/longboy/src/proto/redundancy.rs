@@ -0,0 +1,17 @@
+// Parity datagrams are sealed under the sequence of the cycle after their group, with the top
+// bit set so they never share a nonce with the datagram for that cycle.
+pub(crate) const PARITY_SEQUENCE: u64 = 1 << 63;
+
+// Parity presence is a u32, one bit per member.
+pub(crate) const MAX_PARITY_GROUP: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Redundancy
+{
+    Repetition,
+    Parity
+    {
+        group: usize,
+        interleave: usize,
+    },
+}
@@ -0,0 +1,49 @@
+pub(crate) struct ReplayWindow
+{
+    sequence: u64,
+    bitmap: u128,
+}
+
+impl ReplayWindow
+{
+    pub(crate) fn new() -> Self
+    {
+        Self { sequence: 0, bitmap: 0 }
+    }
+
+    pub(crate) fn check(&self, sequence: u64) -> bool
+    {
+        // Sequences past the newest seen are always fresh, older ones must be inside the window
+        // and not yet seen.
+        match sequence > self.sequence
+        {
+            true => true,
+            false =>
+            {
+                let distance = self.sequence - sequence;
+                distance < u128::BITS as u64 && self.bitmap & (1 << distance) == 0
+            }
+        }
+    }
+
+    pub(crate) fn accept(&mut self, sequence: u64)
+    {
+        match sequence > self.sequence
+        {
+            true =>
+            {
+                let distance = sequence - self.sequence;
+                self.bitmap = match distance < u128::BITS as u64
+                {
+                    true => (self.bitmap << distance) | 1,
+                    false => 1,
+                };
+                self.sequence = sequence;
+            }
+            false =>
+            {
+                self.bitmap |= 1 << (self.sequence - sequence);
+            }
+        }
+    }
+}
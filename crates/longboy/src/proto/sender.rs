@@ -1,15 +1,10 @@
-use crate::{Cipher, Constants};
+use crate::{Cipher, Constants, DynSender, DynSource, Encoding, Mirroring, Rc5Cipher, Redundancy, HEARTBEAT_SIZE};
 
-pub struct Sender<SourceType, const SIZE: usize, const WINDOW_SIZE: usize>
+pub struct Sender<SourceType, const SIZE: usize, const WINDOW_SIZE: usize, CipherType = Rc5Cipher>
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    CipherType: Cipher,
 {
-    source: SourceType,
-    cipher: Cipher<SIZE>,
-
-    cycle: usize,
-    flags: [bool; WINDOW_SIZE],
-    buffer: [u8; <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE],
+    inner: DynSender<SizedSource<SourceType, SIZE>, CipherType>,
 }
 
 pub trait Source<const SIZE: usize>
@@ -19,70 +14,129 @@ where
     fn poll(&mut self, buffer: &mut [u8; SIZE]) -> bool;
 }
 
-impl<SourceType, const SIZE: usize, const WINDOW_SIZE: usize> Sender<SourceType, SIZE, WINDOW_SIZE>
+pub trait VariableSource<const SIZE: usize>
+where
+    Self: 'static + Send,
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool;
+}
+
+impl<SourceType, const SIZE: usize> VariableSource<SIZE> for SourceType
 where
     SourceType: Source<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        buffer.resize(SIZE, 0);
+        Source::poll(self, <&mut [u8; SIZE]>::try_from(buffer.as_mut_slice()).unwrap())
+    }
+}
+
+// Hands fixed size input to the runtime sized Sender underneath.
+pub(crate) struct SizedSource<SourceType, const SIZE: usize>(pub(crate) SourceType);
+
+impl<SourceType, const SIZE: usize> DynSource for SizedSource<SourceType, SIZE>
+where
+    SourceType: VariableSource<SIZE>,
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        VariableSource::poll(&mut self.0, buffer)
+    }
+}
+
+impl<SourceType, const SIZE: usize, const WINDOW_SIZE: usize, CipherType>
+    Sender<SourceType, SIZE, WINDOW_SIZE, CipherType>
+where
+    SourceType: VariableSource<SIZE>,
+    CipherType: Cipher,
 {
     pub fn new(cipher_key: u64, source: SourceType) -> Self
     {
+        // Input and window that don't fit in a datagram fail to build rather than panic here.
+        const { Constants::<SIZE, WINDOW_SIZE, CipherType>::LAYOUT };
         Self {
-            source,
-            cipher: Cipher::new(cipher_key),
+            inner: DynSender::new(cipher_key, SIZE, WINDOW_SIZE, SizedSource(source)),
+        }
+    }
 
-            cycle: 0,
-            flags: [false; WINDOW_SIZE],
-            buffer: [0; <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE],
+    pub fn with_channel(self, channel_id: u8) -> Self
+    {
+        Self {
+            inner: self.inner.with_channel(channel_id),
         }
     }
 
-    pub fn cycle(&self) -> usize
+    pub fn with_encoding(self, encoding: Encoding) -> Self
+    {
+        Self {
+            inner: self.inner.with_encoding(encoding),
+        }
+    }
+
+    pub fn with_keepalive(self, period: u16) -> Self
+    {
+        Self {
+            inner: self.inner.with_keepalive(period),
+        }
+    }
+
+    pub fn with_redundancy(self, redundancy: Redundancy) -> Self
     {
-        self.cycle
-    }
-
-    pub fn poll_datagram(&mut self, timestamp: u16) -> Option<&[u8; <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]>
-    {
-        // Alias constants so they're less painful to read.
-        #[allow(non_snake_case)]
-        let MAX_CYCLE: usize = Constants::<SIZE, WINDOW_SIZE>::MAX_CYCLE;
-
-        // Record cycle and timestamp.
-        *<&mut [u8; 2]>::try_from(&mut self.buffer[0..2]).unwrap() = (self.cycle as u16).to_le_bytes();
-        *<&mut [u8; 2]>::try_from(&mut self.buffer[2..4]).unwrap() = timestamp.to_le_bytes();
-        self.cipher
-            .encrypt_header(<&mut [u8; 4]>::try_from(&mut self.buffer[0..4]).unwrap());
-
-        // Poll source.
-        let index = self.cycle % WINDOW_SIZE;
-        let start = (std::mem::size_of::<u16>() * 2) + (SIZE * index);
-        let end = start + SIZE;
-        match self
-            .source
-            .poll(<&mut [u8; SIZE]>::try_from(&mut self.buffer[start..end]).unwrap())
-        {
-            true =>
-            {
-                self.cipher
-                    .encrypt_slot(<&mut [u8; SIZE]>::try_from(&mut self.buffer[start..end]).unwrap());
-                self.flags[index] = true;
-            }
-            false =>
-            {
-                self.buffer[start..end].fill(0);
-                self.flags[index] = false;
-            }
+        Self {
+            inner: self.inner.with_redundancy(redundancy),
         }
+    }
 
-        // Check for transmit and potentially advance cycle.
-        match self.flags.iter().any(|flag| *flag)
-        {
-            true =>
-            {
-                self.cycle = (self.cycle + 1) % MAX_CYCLE;
-                Some(&self.buffer)
-            }
-            false => None,
+    pub fn with_max_datagram_size(self, max_datagram_size: usize) -> Self
+    {
+        Self {
+            inner: self.inner.with_max_datagram_size(max_datagram_size),
         }
     }
+
+    pub fn cycle(&self) -> usize
+    {
+        self.inner.cycle()
+    }
+
+    pub fn acknowledge(&mut self, sequence: u64)
+    {
+        self.inner.acknowledge(sequence);
+    }
+
+    pub fn check_acknowledgement(&self, session_id: u64, datagram: &[u8]) -> Option<u64>
+    {
+        self.inner.check_acknowledgement(session_id, datagram)
+    }
+
+    pub fn heartbeat_datagram(&self, session_id: u64, mirroring: Mirroring) -> [u8; HEARTBEAT_SIZE]
+    {
+        self.inner.heartbeat_datagram(session_id, mirroring)
+    }
+
+    pub fn announce_key(&mut self, epoch: u8, cipher_key: u64)
+    {
+        self.inner.announce_key(epoch, cipher_key);
+    }
+
+    pub fn rekey(&mut self, epoch: u8, cipher_key: u64)
+    {
+        self.inner.rekey(epoch, cipher_key);
+    }
+
+    pub fn poll_datagram(&mut self, timestamp: u16) -> Option<&[u8]>
+    {
+        self.inner.poll_datagram(timestamp)
+    }
+
+    pub fn poll_parity(&mut self, timestamp: u16) -> Option<&[u8]>
+    {
+        self.inner.poll_parity(timestamp)
+    }
+
+    pub fn mirror_datagram(&mut self, mirroring: Mirroring) -> &[u8]
+    {
+        self.inner.mirror_datagram(mirroring)
+    }
 }
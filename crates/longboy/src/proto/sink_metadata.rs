@@ -0,0 +1,9 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SinkMetadata
+{
+    // Counts up from the first cycle without wrapping, unlike the cycle carried in datagrams.
+    pub cycle: u64,
+    pub timestamp: u16,
+    pub receive_timestamp: u16,
+    pub age: usize,
+}
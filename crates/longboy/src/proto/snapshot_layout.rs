@@ -0,0 +1,84 @@
+use crate::{Cipher, Layout};
+
+// Fragments follow the header every datagram shares: version, channel, sequence, timestamp,
+// flags, key epoch, lane and check value.
+pub(crate) const FRAGMENT_OFFSET: usize = 11;
+
+// Sizes of everything in a snapshot datagram for a snapshot size and datagram budget.  Snapshots
+// too big for one datagram are split evenly across as few fragments as they fit in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotLayout
+{
+    pub max_snapshot_size: usize,
+    pub block_size: usize,
+    pub tag_size: usize,
+
+    pub header_size: usize,
+    pub fragment_size: usize,
+    pub max_fragments: usize,
+    pub datagram_size: usize,
+}
+
+impl SnapshotLayout
+{
+    // Nothing when the budget can't hold a header and a block, or the snapshot needs more
+    // fragments than can be counted.
+    pub const fn new<CipherType>(max_snapshot_size: usize, max_datagram_size: usize) -> Option<Self>
+    where
+        CipherType: Cipher,
+    {
+        let header_size = FRAGMENT_OFFSET + (std::mem::size_of::<u16>() * 3) + (std::mem::size_of::<u32>() * 3);
+        let max_datagram_size = match max_datagram_size < Layout::MAX_DATAGRAM_SIZE
+        {
+            true => max_datagram_size,
+            false => Layout::MAX_DATAGRAM_SIZE,
+        };
+        if header_size + CipherType::BLOCK_SIZE + CipherType::TAG_SIZE > max_datagram_size
+            || max_snapshot_size > (u32::MAX as usize)
+        {
+            return None;
+        }
+
+        let fragment_size = ((max_datagram_size - header_size - CipherType::TAG_SIZE) / CipherType::BLOCK_SIZE)
+            * CipherType::BLOCK_SIZE;
+        let max_fragments = match max_snapshot_size.div_ceil(fragment_size)
+        {
+            0 => 1,
+            max_fragments => max_fragments,
+        };
+        if max_fragments > (u16::MAX as usize)
+        {
+            return None;
+        }
+
+        Some(Self {
+            max_snapshot_size,
+            block_size: CipherType::BLOCK_SIZE,
+            tag_size: CipherType::TAG_SIZE,
+
+            header_size,
+            fragment_size,
+            max_fragments,
+            datagram_size: header_size + fragment_size + CipherType::TAG_SIZE,
+        })
+    }
+
+    // Every fragment but the last carries the same share of the snapshot.
+    pub const fn fragments(&self, length: usize) -> usize
+    {
+        match length.div_ceil(self.fragment_size)
+        {
+            0 => 1,
+            fragments => fragments,
+        }
+    }
+}
+
+// Where a fragment's share of a snapshot starts and ends.  Only fragments of snapshots split
+// as above are sure to be nonempty.
+pub(crate) fn fragment_range(length: usize, fragments: usize, index: usize) -> (usize, usize)
+{
+    let fragment_length = length.div_ceil(fragments);
+    let start = std::cmp::min(index * fragment_length, length);
+    (start, std::cmp::min(start + fragment_length, length))
+}
@@ -0,0 +1,468 @@
+use std::collections::VecDeque;
+
+use enum_map::{Enum, EnumMap};
+
+use crate::{
+    decode_delta, derive_lane_key, encode_acknowledgement, fragment_range, supports_version, Cipher, Encoding,
+    EpochKeys, HeaderCheck, Layout, Mirroring, SnapshotLayout, SnapshotStats, ACKNOWLEDGEMENT_SIZE,
+    DEFAULT_KEY_GRACE_PERIOD, FRAGMENT_OFFSET, HEADER_CHECK_OFFSET,
+};
+
+// Reassembles fragmented snapshots, newest first.  Anything older than the last snapshot handed
+// to the sink is worthless, as is whatever's left of a snapshot once a newer one starts arriving.
+// Snapshots sent as differences are rebuilt from the baseline they name, which has to be one of
+// the last few handed off.
+pub struct SnapshotReceiver<SinkType, CipherType>
+where
+    SinkType: SnapshotSink,
+    CipherType: Cipher,
+{
+    sink: SinkType,
+    ciphers: EnumMap<Mirroring, CipherType>,
+    header_checks: EnumMap<Mirroring, HeaderCheck>,
+    epoch: u8,
+    channel_id: u8,
+    previous_ciphers: Vec<EpochKeys<CipherType>>,
+    rekey_timestamp: Option<u16>,
+    rekey_elapsed: u16,
+    key_grace_period: u16,
+    layout: SnapshotLayout,
+
+    delivered: Option<u64>,
+    finished: Option<u64>,
+    assembly: Option<Assembly>,
+    // The lanes each fragment of the current snapshot has arrived on.
+    received: Vec<u8>,
+    payload: Vec<u8>,
+    snapshot: Vec<u8>,
+    max_baselines: usize,
+    baselines: VecDeque<(u64, Vec<u8>)>,
+
+    stats: SnapshotStats,
+}
+
+// The snapshot being put back together, and how much of it is still missing.
+struct Assembly
+{
+    sequence: u64,
+    encoding: Encoding,
+    baseline: u16,
+    snapshot_length: usize,
+    length: usize,
+    fragments: usize,
+    remaining: usize,
+}
+
+pub trait SnapshotSink
+where
+    Self: 'static + Send,
+{
+    fn handle(&mut self, sequence: u64, snapshot: &[u8]);
+}
+
+impl<SinkType, CipherType> SnapshotReceiver<SinkType, CipherType>
+where
+    SinkType: SnapshotSink,
+    CipherType: Cipher,
+{
+    pub fn new(cipher_key: u64, max_snapshot_size: usize, sink: SinkType) -> Self
+    {
+        let layout = SnapshotLayout::new::<CipherType>(max_snapshot_size, Layout::MAX_DATAGRAM_SIZE)
+            .unwrap_or_else(|| panic!("Snapshot of {} bytes doesn't fit in datagrams", max_snapshot_size));
+
+        Self {
+            sink,
+            ciphers: EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring))),
+            header_checks: EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+            epoch: 0,
+            channel_id: 0,
+            previous_ciphers: Vec::new(),
+            rekey_timestamp: None,
+            rekey_elapsed: 0,
+            key_grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            layout,
+
+            delivered: None,
+            finished: None,
+            assembly: None,
+            received: vec![0; layout.max_fragments],
+            payload: vec![0; max_snapshot_size],
+            snapshot: vec![0; max_snapshot_size],
+            max_baselines: 0,
+            baselines: VecDeque::new(),
+
+            stats: SnapshotStats::default(),
+        }
+    }
+
+    pub fn with_channel(mut self, channel_id: u8) -> Self
+    {
+        self.channel_id = channel_id;
+        self
+    }
+
+    pub fn with_max_datagram_size(mut self, max_datagram_size: usize) -> Self
+    {
+        // Has to match the Sender's budget, which fragments are split by.
+        self.layout = SnapshotLayout::new::<CipherType>(self.layout.max_snapshot_size, max_datagram_size)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Snapshot of {} bytes doesn't fit in datagrams of {} bytes",
+                    self.layout.max_snapshot_size, max_datagram_size
+                )
+            });
+        self.received = vec![0; self.layout.max_fragments];
+        self
+    }
+
+    pub fn with_key_grace_period(mut self, period: u16) -> Self
+    {
+        self.key_grace_period = period;
+        self
+    }
+
+    pub fn with_baselines(mut self, baselines: usize) -> Self
+    {
+        // Has to keep at least as many as the Sender for it to be sure of having them.
+        self.max_baselines = baselines;
+        self.baselines = VecDeque::with_capacity(baselines);
+        self
+    }
+
+    pub fn layout(&self) -> &SnapshotLayout
+    {
+        &self.layout
+    }
+
+    pub fn acknowledgement(&self) -> u64
+    {
+        // The newest snapshot handed off, counting from one, which the Sender can take as a
+        // baseline.
+        self.delivered.map_or(0, |delivered| delivered + 1)
+    }
+
+    pub fn acknowledgement_datagram(&self, session_id: u64, mirroring: Mirroring) -> [u8; ACKNOWLEDGEMENT_SIZE]
+    {
+        encode_acknowledgement(
+            &self.header_checks[mirroring],
+            self.channel_id,
+            session_id,
+            self.acknowledgement(),
+            self.epoch,
+            mirroring,
+        )
+    }
+
+    pub fn stats(&self) -> SnapshotStats
+    {
+        self.stats
+    }
+
+    pub fn rekey(&mut self, epoch: u8, cipher_key: u64)
+    {
+        // Keep outgoing keys around for datagrams sealed before the Sender switches over.  Keys
+        // can be replaced again before it does, so every one is kept until the Sender has been
+        // heard from under the newest.
+        let ciphers = std::mem::replace(
+            &mut self.ciphers,
+            EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring))),
+        );
+        let header_checks = std::mem::replace(
+            &mut self.header_checks,
+            EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+        );
+        self.previous_ciphers
+            .retain(|(previous_epoch, _, _)| *previous_epoch != epoch);
+        self.previous_ciphers.push((self.epoch, ciphers, header_checks));
+        self.epoch = epoch;
+        self.rekey_timestamp = None;
+        self.rekey_elapsed = 0;
+    }
+
+    pub fn poll(&mut self, timestamp: u16)
+    {
+        self.retire_key(timestamp);
+    }
+
+    pub fn handle_datagram(&mut self, timestamp: u16, datagram: &mut [u8])
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+        #[allow(non_snake_case)]
+        let DATAGRAM_SIZE: usize = self.layout.datagram_size;
+
+        // Check version before anything else, the rest of the header is only known for versions
+        // we support.
+        if let Some(version) = datagram.first()
+            && !supports_version(*version)
+        {
+            self.stats.datagrams_unsupported_version += 1;
+            return;
+        }
+
+        // Check for datagrams that can't hold a header and a whole number of cipher blocks.
+        if datagram.len() < HEADER_SIZE + CipherType::TAG_SIZE
+            || datagram.len() > DATAGRAM_SIZE
+            || (datagram.len() - HEADER_SIZE - CipherType::TAG_SIZE) % CipherType::BLOCK_SIZE != 0
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        }
+        let payload_end = datagram.len() - CipherType::TAG_SIZE;
+
+        // Sockets shared by several schemas hand datagrams over by channel, anything else still
+        // arriving here was meant for someone else.
+        if datagram[1] != self.channel_id
+        {
+            self.stats.datagrams_unknown_channel += 1;
+            return;
+        }
+
+        // Pick the key the datagram was sealed with, each lane has its own.  Previous keys are
+        // only good until their grace period runs out.
+        if (datagram[8] as usize) >= Mirroring::LENGTH
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        }
+        let mirroring = Mirroring::from_usize(datagram[8] as usize);
+        self.retire_key(timestamp);
+        let current_key = datagram[7] == self.epoch;
+        let (cipher, header_check) = match self.previous_ciphers.iter().find(|(epoch, _, _)| datagram[7] == *epoch)
+        {
+            _ if current_key => (&self.ciphers[mirroring], &self.header_checks[mirroring]),
+            Some((_, ciphers, header_checks)) => (&ciphers[mirroring], &header_checks[mirroring]),
+            None =>
+            {
+                self.stats.datagrams_unknown_epoch += 1;
+                return;
+            }
+        };
+
+        // Grab sequence, turning away anything whose header doesn't check out under the lane's
+        // key for it before it's trusted.  Snapshots can go missing for any number of sequences
+        // in an outage, so rather than being taken as the nearest one to the newest we know of,
+        // the rest of the sequence rides along with the fragment.
+        cipher.decrypt_header(<&mut [u8; 4]>::try_from(&mut datagram[2..6]).unwrap());
+        let sequence = ((u32::from_le_bytes(
+            *<&[u8; 4]>::try_from(&datagram[(FRAGMENT_OFFSET + 14)..(FRAGMENT_OFFSET + 18)]).unwrap(),
+        ) as u64)
+            << 16)
+            | (u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[2..4]).unwrap()) as u64);
+        let check = u16::from_le_bytes(
+            *<&[u8; 2]>::try_from(&datagram[HEADER_CHECK_OFFSET..(HEADER_CHECK_OFFSET + 2)]).unwrap(),
+        );
+        if check != header_check.compute(&datagram[0..HEADER_SIZE], sequence)
+        {
+            self.stats.header_check_failures += 1;
+            return;
+        }
+
+        // Snapshots no newer than the last one delivered or given up on, or the one being put
+        // back together, are of no use anymore.
+        if self.finished.is_some_and(|finished| sequence <= finished)
+            || self
+                .assembly
+                .as_ref()
+                .is_some_and(|assembly| sequence < assembly.sequence)
+        {
+            self.stats.datagrams_stale += 1;
+            return;
+        }
+
+        // Check the fragment's place in the snapshot, which has to fit our buffer and the
+        // datagram.
+        let index =
+            u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[FRAGMENT_OFFSET..(FRAGMENT_OFFSET + 2)]).unwrap())
+                as usize;
+        let fragments =
+            u16::from_le_bytes(*<&[u8; 2]>::try_from(&datagram[(FRAGMENT_OFFSET + 2)..(FRAGMENT_OFFSET + 4)]).unwrap())
+                as usize;
+        let length =
+            u32::from_le_bytes(*<&[u8; 4]>::try_from(&datagram[(FRAGMENT_OFFSET + 4)..(FRAGMENT_OFFSET + 8)]).unwrap())
+                as usize;
+        let snapshot_length = u32::from_le_bytes(
+            *<&[u8; 4]>::try_from(&datagram[(FRAGMENT_OFFSET + 8)..(FRAGMENT_OFFSET + 12)]).unwrap(),
+        ) as usize;
+        let baseline = u16::from_le_bytes(
+            *<&[u8; 2]>::try_from(&datagram[(FRAGMENT_OFFSET + 12)..(FRAGMENT_OFFSET + 14)]).unwrap(),
+        );
+        let encoding = match Encoding::from_flags(datagram[6])
+        {
+            Some(Encoding::Full) if baseline == 0 && length == snapshot_length => Encoding::Full,
+            Some(Encoding::Delta) if baseline > 0 && (baseline as u64) <= sequence => Encoding::Delta,
+            _ =>
+            {
+                self.stats.datagrams_malformed += 1;
+                return;
+            }
+        };
+        if length > self.layout.max_snapshot_size
+            || snapshot_length > self.layout.max_snapshot_size
+            || fragments == 0
+            || fragments > self.layout.max_fragments
+            || index >= fragments
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        }
+        let (start, end) = fragment_range(length, fragments, index);
+        if HEADER_SIZE + (end - start) > payload_end || (start == end && length > 0)
+        {
+            self.stats.datagrams_malformed += 1;
+            return;
+        }
+
+        // Reject fragments we already have from this lane, then authenticate and open the
+        // fragment.  Mirrored copies from other lanes go on to count as duplicates.
+        let current = self
+            .assembly
+            .as_ref()
+            .is_some_and(|assembly| assembly.sequence == sequence);
+        let lane = 1 << Mirroring::into_usize(mirroring);
+        let received = match current
+        {
+            true => self.received[index],
+            false => 0,
+        };
+        if received & lane != 0
+        {
+            self.stats.datagrams_replayed += 1;
+            return;
+        }
+        let (header, rest) = datagram.split_at_mut(HEADER_SIZE);
+        let (payload, tag) = rest.split_at_mut(payload_end - HEADER_SIZE);
+        if !cipher.open((sequence << 16) | (index as u64), header, payload, tag)
+        {
+            self.stats.authentication_failures += 1;
+            return;
+        }
+
+        // The Sender has switched over once it's heard from under the current key, which is
+        // when the grace period for the previous ones starts.
+        if current_key && !self.previous_ciphers.is_empty() && self.rekey_timestamp.is_none()
+        {
+            self.rekey_timestamp = Some(timestamp);
+        }
+        if received != 0
+        {
+            self.received[index] |= lane;
+            self.stats.duplicate_fragments += 1;
+            return;
+        }
+
+        // Start on a newer snapshot, giving up on whatever's left of the last one, or check the
+        // fragment agrees with the rest of its snapshot.
+        match &self.assembly
+        {
+            Some(assembly) if current =>
+            {
+                if assembly.encoding != encoding
+                    || assembly.baseline != baseline
+                    || assembly.snapshot_length != snapshot_length
+                    || assembly.length != length
+                    || assembly.fragments != fragments
+                {
+                    self.stats.datagrams_malformed += 1;
+                    return;
+                }
+            }
+            _ =>
+            {
+                if self.assembly.is_some()
+                {
+                    self.stats.snapshots_superseded += 1;
+                }
+                self.received[0..fragments].fill(0);
+                self.assembly = Some(Assembly {
+                    sequence,
+                    encoding,
+                    baseline,
+                    snapshot_length,
+                    length,
+                    fragments,
+                    remaining: fragments,
+                });
+            }
+        }
+        self.stats.datagrams_accepted += 1;
+
+        // Store the fragment, rebuilding the snapshot once it's whole.
+        self.payload[start..end].copy_from_slice(&datagram[HEADER_SIZE..(HEADER_SIZE + (end - start))]);
+        self.received[index] = lane;
+        let assembly = self.assembly.as_mut().unwrap();
+        assembly.remaining -= 1;
+        if assembly.remaining > 0
+        {
+            return;
+        }
+        self.assembly = None;
+        self.finished = Some(sequence);
+
+        let snapshot = match encoding
+        {
+            Encoding::Full => &self.payload[0..length],
+            Encoding::Delta =>
+            {
+                // Without its baseline the snapshot is lost, until the Sender hears what we
+                // have.
+                let Some((_, reference)) = self
+                    .baselines
+                    .iter()
+                    .find(|(delivered, _)| *delivered == sequence - (baseline as u64))
+                else
+                {
+                    self.stats.snapshots_missing_baseline += 1;
+                    return;
+                };
+                if decode_delta(
+                    &self.payload[0..length],
+                    reference,
+                    &mut self.snapshot[0..snapshot_length],
+                ) != Some(length)
+                {
+                    self.stats.datagrams_malformed += 1;
+                    return;
+                }
+                &self.snapshot[0..snapshot_length]
+            }
+        };
+
+        // Hand off the snapshot, keeping it as a baseline, zeroed past its end so snapshots of
+        // any length can be rebuilt from it.
+        self.sink.handle(sequence, snapshot);
+        self.stats.snapshots_delivered += 1;
+        self.delivered = Some(sequence);
+        if self.max_baselines > 0
+        {
+            let mut baseline = match self.baselines.len() >= self.max_baselines
+            {
+                true => self.baselines.pop_front().unwrap().1,
+                false => vec![0; self.layout.max_snapshot_size],
+            };
+            baseline[0..snapshot_length].copy_from_slice(snapshot);
+            baseline[snapshot_length..].fill(0);
+            self.baselines.push_back((sequence, baseline));
+        }
+    }
+
+    fn retire_key(&mut self, timestamp: u16)
+    {
+        // Timestamps wrap, so one that's gone back on the last has been all the way around.
+        if let Some(rekey_timestamp) = self.rekey_timestamp
+        {
+            let elapsed = timestamp.wrapping_sub(rekey_timestamp);
+            if elapsed >= self.key_grace_period || elapsed < self.rekey_elapsed
+            {
+                self.previous_ciphers.clear();
+                self.rekey_timestamp = None;
+                self.rekey_elapsed = 0;
+            }
+            else
+            {
+                self.rekey_elapsed = elapsed;
+            }
+        }
+    }
+}
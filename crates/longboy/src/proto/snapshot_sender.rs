@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+
+use enum_map::{Enum, EnumMap};
+
+use crate::{
+    check_acknowledgement, decode_acknowledgement, derive_lane_key, encode_delta, fragment_range, Cipher, Encoding,
+    HeaderCheck, Layout, Mirroring, SnapshotLayout, SnapshotSenderStats, FRAGMENT_OFFSET, HEADER_CHECK_OFFSET,
+    PROTOCOL_VERSION,
+};
+
+// Snapshots are only worth anything until a newer one comes along, so each goes out once, split
+// into as many fragments as it takes, and is never repeated.  With baselines kept, each goes out
+// as its difference from the newest one the Receiver has acknowledged, when that's smaller.
+pub struct SnapshotSender<SourceType, CipherType>
+where
+    CipherType: Cipher,
+{
+    source: SourceType,
+    ciphers: EnumMap<Mirroring, CipherType>,
+    header_checks: EnumMap<Mirroring, HeaderCheck>,
+    epoch: u8,
+    announced_header_checks: Vec<(u8, EnumMap<Mirroring, HeaderCheck>)>,
+    channel_id: u8,
+    layout: SnapshotLayout,
+
+    sequence: u64,
+    next_sequence: u64,
+    acknowledged: u64,
+    max_baselines: usize,
+    baselines: VecDeque<(u64, Vec<u8>)>,
+    encoding: Encoding,
+    baseline: u16,
+    fragments: usize,
+    next_fragment: usize,
+    snapshot: Vec<u8>,
+    scratch: Vec<u8>,
+    payload_length: usize,
+    payload: Vec<u8>,
+    plaintext_end: usize,
+    plaintext_nonce: u64,
+    plaintext: Vec<u8>,
+    buffer: Vec<u8>,
+
+    stats: SnapshotSenderStats,
+}
+
+pub trait SnapshotSource
+where
+    Self: 'static + Send,
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool;
+}
+
+impl<SourceType, CipherType> SnapshotSender<SourceType, CipherType>
+where
+    SourceType: SnapshotSource,
+    CipherType: Cipher,
+{
+    pub fn new(cipher_key: u64, max_snapshot_size: usize, source: SourceType) -> Self
+    {
+        let layout = SnapshotLayout::new::<CipherType>(max_snapshot_size, Layout::MAX_DATAGRAM_SIZE)
+            .unwrap_or_else(|| panic!("Snapshot of {} bytes doesn't fit in datagrams", max_snapshot_size));
+
+        Self {
+            source,
+            ciphers: EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring))),
+            header_checks: EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+            epoch: 0,
+            announced_header_checks: Vec::new(),
+            channel_id: 0,
+            layout,
+
+            sequence: 0,
+            next_sequence: 0,
+            acknowledged: 0,
+            max_baselines: 0,
+            baselines: VecDeque::new(),
+            encoding: Encoding::Full,
+            baseline: 0,
+            fragments: 0,
+            next_fragment: 0,
+            snapshot: Vec::with_capacity(max_snapshot_size),
+            scratch: Vec::with_capacity(max_snapshot_size),
+            payload_length: 0,
+            payload: vec![0; max_snapshot_size],
+            plaintext_end: 0,
+            plaintext_nonce: 0,
+            plaintext: vec![0; layout.datagram_size],
+            buffer: vec![0; layout.datagram_size],
+
+            stats: SnapshotSenderStats::default(),
+        }
+    }
+
+    pub fn with_channel(mut self, channel_id: u8) -> Self
+    {
+        self.channel_id = channel_id;
+        self
+    }
+
+    pub fn with_max_datagram_size(mut self, max_datagram_size: usize) -> Self
+    {
+        // Smaller budgets only split snapshots into more fragments.
+        self.layout = SnapshotLayout::new::<CipherType>(self.layout.max_snapshot_size, max_datagram_size)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Snapshot of {} bytes doesn't fit in datagrams of {} bytes",
+                    self.layout.max_snapshot_size, max_datagram_size
+                )
+            });
+        self.plaintext = vec![0; self.layout.datagram_size];
+        self.buffer = vec![0; self.layout.datagram_size];
+        self
+    }
+
+    pub fn with_baselines(mut self, baselines: usize) -> Self
+    {
+        // Only as many as can be told apart by how far back they are.
+        assert!(baselines <= (u16::MAX as usize), "Can't keep {} baselines", baselines);
+        self.max_baselines = baselines;
+        self.baselines = VecDeque::with_capacity(baselines);
+        self
+    }
+
+    pub fn layout(&self) -> &SnapshotLayout
+    {
+        &self.layout
+    }
+
+    pub fn sequence(&self) -> u64
+    {
+        self.sequence
+    }
+
+    pub fn encoding(&self) -> Encoding
+    {
+        self.encoding
+    }
+
+    pub fn stats(&self) -> SnapshotSenderStats
+    {
+        self.stats
+    }
+
+    pub fn fragments_remaining(&self) -> usize
+    {
+        self.fragments - self.next_fragment
+    }
+
+    pub fn acknowledge(&mut self, sequence: u64)
+    {
+        // Acknowledgements arrive out of band and out of order, so only ever move forward, and
+        // never past what we've actually sent.
+        self.acknowledged = std::cmp::max(self.acknowledged, std::cmp::min(sequence, self.next_sequence));
+    }
+
+    pub fn check_acknowledgement(&self, session_id: u64, datagram: &[u8]) -> Option<u64>
+    {
+        // Acknowledgements pick which baseline snapshots are sent against, so only ones for this
+        // Session that check out under our current key for their lane, or one announced to the
+        // Receiver that we've yet to switch over to, are taken at their word.
+        let acknowledgement = decode_acknowledgement(self.channel_id, datagram)?;
+        let header_checks = self.header_checks(acknowledgement.epoch)?;
+        (acknowledgement.session_id == session_id
+            && check_acknowledgement(&header_checks[acknowledgement.mirroring], datagram))
+        .then_some(acknowledgement.sequence)
+    }
+
+    pub fn announce_key(&mut self, epoch: u8, cipher_key: u64)
+    {
+        // Receivers take keys as soon as they're announced, well before we switch over, and
+        // acknowledge under them in the meantime.
+        self.announced_header_checks.push((
+            epoch,
+            EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring)),
+        ));
+    }
+
+    pub fn rekey(&mut self, epoch: u8, cipher_key: u64)
+    {
+        // Sequences carry on across keys, so nonces never repeat under either of them.  Keys
+        // announced before this one are never switched over to.
+        self.ciphers = EnumMap::from_fn(|mirroring| CipherType::new(derive_lane_key(cipher_key, mirroring)));
+        self.header_checks = EnumMap::from_fn(|mirroring| HeaderCheck::new(cipher_key, mirroring));
+        self.epoch = epoch;
+        if let Some(position) = self
+            .announced_header_checks
+            .iter()
+            .position(|(announced_epoch, _)| *announced_epoch == epoch)
+        {
+            self.announced_header_checks.drain(0..=position);
+        }
+    }
+
+    pub fn poll_snapshot(&mut self) -> bool
+    {
+        // A new snapshot replaces whatever is left of the last one.  Snapshots over the maximum
+        // can't be sent, so they're counted and dropped, leaving the last one to finish.
+        self.scratch.clear();
+        if !self.source.poll(&mut self.scratch)
+        {
+            return false;
+        }
+        let length = self.scratch.len();
+        if length > self.layout.max_snapshot_size
+        {
+            self.stats.snapshots_oversized += 1;
+            return false;
+        }
+        std::mem::swap(&mut self.snapshot, &mut self.scratch);
+        self.stats.snapshots_polled += 1;
+
+        self.sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        // Send the difference from the newest snapshot the Receiver has, as long as we still
+        // have it too and the difference is any smaller.
+        let sequence = self.sequence;
+        let reference = self
+            .acknowledged
+            .checked_sub(1)
+            .and_then(|acknowledged| self.baselines.iter().find(|(baseline, _)| *baseline == acknowledged));
+        match reference.and_then(|(baseline, reference)| {
+            encode_delta(&self.snapshot, reference, &mut self.payload).map(|encoded| (*baseline, encoded))
+        })
+        {
+            Some((baseline, encoded)) if encoded < length =>
+            {
+                self.encoding = Encoding::Delta;
+                self.baseline = (sequence - baseline) as u16;
+                self.payload_length = encoded;
+            }
+            _ =>
+            {
+                self.encoding = Encoding::Full;
+                self.baseline = 0;
+                self.payload[0..length].copy_from_slice(&self.snapshot);
+                self.payload_length = length;
+            }
+        }
+
+        // Keep the snapshot as a baseline, zeroed past its end so snapshots of any length can be
+        // told apart from it.
+        if self.max_baselines > 0
+        {
+            let mut baseline = match self.baselines.len() >= self.max_baselines
+            {
+                true => self.baselines.pop_front().unwrap().1,
+                false => vec![0; self.layout.max_snapshot_size],
+            };
+            baseline[0..length].copy_from_slice(&self.snapshot);
+            baseline[length..].fill(0);
+            self.baselines.push_back((sequence, baseline));
+        }
+
+        self.fragments = self.layout.fragments(self.payload_length);
+        self.next_fragment = 0;
+        true
+    }
+
+    pub fn poll_datagram(&mut self, timestamp: u16) -> Option<&[u8]>
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+
+        if self.next_fragment >= self.fragments
+        {
+            return None;
+        }
+        let index = self.next_fragment;
+        self.next_fragment += 1;
+
+        // Copy in the fragment's share of the snapshot, or of its difference from the baseline.
+        let length = self.payload_length;
+        let (start, end) = fragment_range(length, self.fragments, index);
+        self.plaintext[HEADER_SIZE..(HEADER_SIZE + (end - start))].copy_from_slice(&self.payload[start..end]);
+        let end = HEADER_SIZE + (end - start);
+
+        // Record version, channel, sequence, timestamp, encoding and key epoch, then the
+        // fragment's place in the payload, the snapshot's length, how far back its baseline is
+        // and the rest of the sequence, then pad out to the cipher's block size.
+        self.plaintext[0] = PROTOCOL_VERSION;
+        self.plaintext[1] = self.channel_id;
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[2..4]).unwrap() = (self.sequence as u16).to_le_bytes();
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[4..6]).unwrap() = timestamp.to_le_bytes();
+        self.plaintext[6] = self.encoding.flags();
+        self.plaintext[7] = self.epoch;
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[FRAGMENT_OFFSET..(FRAGMENT_OFFSET + 2)]).unwrap() =
+            (index as u16).to_le_bytes();
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[(FRAGMENT_OFFSET + 2)..(FRAGMENT_OFFSET + 4)]).unwrap() =
+            (self.fragments as u16).to_le_bytes();
+        *<&mut [u8; 4]>::try_from(&mut self.plaintext[(FRAGMENT_OFFSET + 4)..(FRAGMENT_OFFSET + 8)]).unwrap() =
+            (length as u32).to_le_bytes();
+        *<&mut [u8; 4]>::try_from(&mut self.plaintext[(FRAGMENT_OFFSET + 8)..(FRAGMENT_OFFSET + 12)]).unwrap() =
+            (self.snapshot.len() as u32).to_le_bytes();
+        *<&mut [u8; 2]>::try_from(&mut self.plaintext[(FRAGMENT_OFFSET + 12)..(FRAGMENT_OFFSET + 14)]).unwrap() =
+            self.baseline.to_le_bytes();
+        *<&mut [u8; 4]>::try_from(&mut self.plaintext[(FRAGMENT_OFFSET + 14)..(FRAGMENT_OFFSET + 18)]).unwrap() =
+            ((self.sequence >> 16) as u32).to_le_bytes();
+        let padded_end = HEADER_SIZE + (end - HEADER_SIZE).next_multiple_of(CipherType::BLOCK_SIZE);
+        self.plaintext[end..padded_end].fill(0);
+
+        // Each fragment gets its own nonce under the snapshot's sequence.
+        self.plaintext_end = padded_end;
+        self.plaintext_nonce = (self.sequence << 16) | (index as u64);
+
+        Some(self.mirror_datagram(Mirroring::AudioVideo))
+    }
+
+    pub fn mirror_datagram(&mut self, mirroring: Mirroring) -> &[u8]
+    {
+        // Alias layout so it's less painful to read.
+        #[allow(non_snake_case)]
+        let HEADER_SIZE: usize = self.layout.header_size;
+
+        assert!(self.plaintext_end > 0, "No datagram to mirror");
+        let padded_end = self.plaintext_end;
+        let datagram_size = padded_end + CipherType::TAG_SIZE;
+        self.buffer[0..padded_end].copy_from_slice(&self.plaintext[0..padded_end]);
+        self.buffer[8] = Mirroring::into_usize(mirroring) as u8;
+
+        // Check the header under the lane's key, then seal the fragment, authenticating the
+        // header along with it, then protect the header.
+        let cipher = &self.ciphers[mirroring];
+        let (header, rest) = self.buffer[0..datagram_size].split_at_mut(HEADER_SIZE);
+        let check = self.header_checks[mirroring].compute(header, self.plaintext_nonce >> 16);
+        *<&mut [u8; 2]>::try_from(&mut header[HEADER_CHECK_OFFSET..(HEADER_CHECK_OFFSET + 2)]).unwrap() =
+            check.to_le_bytes();
+        let (payload, tag) = rest.split_at_mut(padded_end - HEADER_SIZE);
+        cipher.seal(self.plaintext_nonce, header, payload, tag);
+        cipher.encrypt_header(<&mut [u8; 4]>::try_from(&mut header[2..6]).unwrap());
+
+        &self.buffer[0..datagram_size]
+    }
+
+    fn header_checks(&self, epoch: u8) -> Option<&EnumMap<Mirroring, HeaderCheck>>
+    {
+        match epoch == self.epoch
+        {
+            true => Some(&self.header_checks),
+            false => self
+                .announced_header_checks
+                .iter()
+                .find(|(announced_epoch, _)| *announced_epoch == epoch)
+                .map(|(_, header_checks)| header_checks),
+        }
+    }
+}
@@ -0,0 +1,6 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotSenderStats
+{
+    pub snapshots_polled: u64,
+    pub snapshots_oversized: u64,
+}
@@ -0,0 +1,18 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotStats
+{
+    pub datagrams_accepted: u64,
+    pub datagrams_stale: u64,
+    pub datagrams_replayed: u64,
+    pub datagrams_malformed: u64,
+    pub datagrams_unsupported_version: u64,
+    pub datagrams_unknown_epoch: u64,
+    pub datagrams_unknown_channel: u64,
+    pub header_check_failures: u64,
+    pub authentication_failures: u64,
+
+    pub duplicate_fragments: u64,
+    pub snapshots_delivered: u64,
+    pub snapshots_superseded: u64,
+    pub snapshots_missing_baseline: u64,
+}
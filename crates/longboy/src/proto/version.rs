@@ -0,0 +1,12 @@
+// Every datagram and heartbeat leads with the version it was written in.  Senders always write
+// the current version, Receivers accept anything from the oldest supported version up, so
+// either side can be upgraded first as long as support for the previous version is kept around
+// for one release after the bump.  Everything after the version byte is free to change between
+// versions.
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+pub(crate) fn supports_version(version: u8) -> bool
+{
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
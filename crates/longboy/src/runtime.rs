@@ -1,3 +1,5 @@
+use crate::Instant;
+
 pub trait Runtime
 {
     fn running(&self) -> bool;
@@ -11,5 +13,5 @@ where
 {
     fn name(&self) -> &str;
 
-    fn poll(&mut self, timestamp: u16);
+    fn poll(&mut self, now: Instant);
 }
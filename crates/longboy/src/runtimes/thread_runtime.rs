@@ -1,21 +1,23 @@
 use std::{
     thread::{Builder, JoinHandle},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use tokio_util::sync::CancellationToken;
 
-use crate::{Runtime, RuntimeTask};
+use crate::{Clock, Runtime, RuntimeTask};
 
 pub struct ThreadRuntime
 {
     handles: Vec<JoinHandle<()>>,
+    clock: Clock,
     cancellation_token: CancellationToken,
 }
 
 struct Task
 {
     task: Box<dyn RuntimeTask>,
+    clock: Clock,
     cancellation_token: CancellationToken,
 }
 
@@ -25,6 +27,7 @@ impl ThreadRuntime
     {
         Self {
             handles: Vec::new(),
+            clock: Clock::new(),
             cancellation_token,
         }
     }
@@ -42,6 +45,7 @@ impl Runtime for ThreadRuntime
         let name = String::from(task.name());
         let task = Task {
             task,
+            clock: self.clock,
             cancellation_token: self.cancellation_token.clone(),
         };
         self.handles
@@ -65,10 +69,9 @@ impl Task
 {
     fn run(mut self)
     {
-        let instant = Instant::now();
         while !self.cancellation_token.is_cancelled()
         {
-            self.task.poll(instant.elapsed().as_millis() as u16);
+            self.task.poll(self.clock.now());
             std::thread::sleep(Duration::from_millis(1));
         }
 
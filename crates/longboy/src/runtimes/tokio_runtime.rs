@@ -1,23 +1,21 @@
 use std::time::Duration;
 
-use tokio::{
-    select,
-    task::JoinHandle,
-    time::{Instant, MissedTickBehavior},
-};
+use tokio::{select, task::JoinHandle, time::MissedTickBehavior};
 use tokio_util::sync::CancellationToken;
 
-use crate::{Runtime, RuntimeTask};
+use crate::{Clock, Runtime, RuntimeTask};
 
 pub struct TokioRuntime
 {
     handles: Vec<JoinHandle<()>>,
+    clock: Clock,
     cancellation_token: CancellationToken,
 }
 
 struct Task
 {
     task: Box<dyn RuntimeTask>,
+    clock: Clock,
     cancellation_token: CancellationToken,
 }
 
@@ -27,6 +25,7 @@ impl TokioRuntime
     {
         Self {
             handles: Vec::new(),
+            clock: Clock::new(),
             cancellation_token,
         }
     }
@@ -43,6 +42,7 @@ impl Runtime for TokioRuntime
     {
         let task = Task {
             task,
+            clock: self.clock,
             cancellation_token: self.cancellation_token.clone(),
         };
         self.handles.push(tokio::spawn(task.run()));
@@ -72,7 +72,6 @@ impl Task
     {
         let mut interval = tokio::time::interval(Duration::from_millis(1));
         interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-        let mut instant = Instant::now();
 
         loop
         {
@@ -80,10 +79,9 @@ impl Task
                 tick = interval.tick() => tick,
                 _ = self.cancellation_token.cancelled() => break,
             };
-            let elapsed = tick.duration_since(instant);
-            instant = tick;
 
-            self.task.poll(elapsed.as_millis() as u16);
+            // Polled with the time the tick was due, not a delta since the last one.
+            self.task.poll(self.clock.at(tick.into_std()));
         }
 
         self.cancellation_token.cancel();
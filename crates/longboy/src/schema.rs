@@ -1,16 +1,261 @@
+use anyhow::{anyhow, Result};
+
+use crate::{Cipher, Delivery, Encoding, Layout, Redundancy, SnapshotLayout};
+
+pub const DEFAULT_HEARTBEAT_PERIOD: u16 = 1000;
+
+// Long enough for datagrams sealed under the outgoing key to still arrive after a couple of
+// missed heartbeats.
+pub const DEFAULT_KEY_GRACE_PERIOD: u16 = DEFAULT_HEARTBEAT_PERIOD * 2;
+
+// Enough for a snapshot of a few dozen kilobytes to go out in one poll, without a bigger one
+// overrunning the socket's send buffer.
+pub const DEFAULT_MAX_FRAGMENTS_PER_POLL: usize = 64;
+
+// Plenty for the odd burst, without a stalled stream soaking up memory without end.
+pub const DEFAULT_MAX_QUEUED_MESSAGES: usize = 256;
+
 pub struct ClientToServerSchema
 {
     pub name: &'static str,
+    pub channel_id: u8,
 
     pub mapper_port: u16,
     pub heartbeat_period: u16,
     pub port: u16,
+
+    pub encoding: Encoding,
+    pub keepalive_period: Option<u16>,
+    pub redundancy: Redundancy,
+    pub max_datagram_size: usize,
+    pub delivery: Delivery,
+    pub key_grace_period: u16,
+    pub ack_period: Option<u16>,
 }
 
 pub struct ServerToClientSchema
 {
     pub name: &'static str,
+    pub channel_id: u8,
+
+    pub mapper_port: u16,
+    pub heartbeat_period: u16,
+
+    pub encoding: Encoding,
+    pub keepalive_period: Option<u16>,
+    pub redundancy: Redundancy,
+    pub max_datagram_size: usize,
+    pub delivery: Delivery,
+    pub key_grace_period: u16,
+    pub ack_period: Option<u16>,
+}
+
+pub struct SnapshotSchema
+{
+    pub name: &'static str,
+    pub channel_id: u8,
 
     pub mapper_port: u16,
     pub heartbeat_period: u16,
+
+    pub max_datagram_size: usize,
+    pub baselines: usize,
+    pub key_grace_period: u16,
+    pub ack_period: Option<u16>,
+    pub max_fragments_per_poll: usize,
+}
+
+pub struct MessageSchema
+{
+    pub name: &'static str,
+    pub channel_id: u8,
+
+    pub max_message_size: usize,
+    pub max_queued_messages: usize,
+}
+
+// Defaults leave every optional behaviour off, so schemas only spell out what they need, and
+// keep building as fields are added.
+impl Default for ClientToServerSchema
+{
+    fn default() -> Self
+    {
+        Self {
+            name: "",
+            channel_id: 0,
+
+            mapper_port: 0,
+            heartbeat_period: DEFAULT_HEARTBEAT_PERIOD,
+            port: 0,
+
+            encoding: Encoding::Full,
+            keepalive_period: None,
+            redundancy: Redundancy::Repetition,
+            max_datagram_size: Layout::MAX_DATAGRAM_SIZE,
+            delivery: Delivery::Unordered,
+            key_grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            ack_period: None,
+        }
+    }
+}
+
+impl Default for ServerToClientSchema
+{
+    fn default() -> Self
+    {
+        Self {
+            name: "",
+            channel_id: 0,
+
+            mapper_port: 0,
+            heartbeat_period: DEFAULT_HEARTBEAT_PERIOD,
+
+            encoding: Encoding::Full,
+            keepalive_period: None,
+            redundancy: Redundancy::Repetition,
+            max_datagram_size: Layout::MAX_DATAGRAM_SIZE,
+            delivery: Delivery::Unordered,
+            key_grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            ack_period: None,
+        }
+    }
+}
+
+impl Default for SnapshotSchema
+{
+    fn default() -> Self
+    {
+        Self {
+            name: "",
+            channel_id: 0,
+
+            mapper_port: 0,
+            heartbeat_period: DEFAULT_HEARTBEAT_PERIOD,
+
+            max_datagram_size: Layout::MAX_DATAGRAM_SIZE,
+            baselines: 0,
+            key_grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            ack_period: None,
+            max_fragments_per_poll: DEFAULT_MAX_FRAGMENTS_PER_POLL,
+        }
+    }
+}
+
+impl Default for MessageSchema
+{
+    fn default() -> Self
+    {
+        Self {
+            name: "",
+            channel_id: 0,
+
+            max_message_size: u16::MAX as usize,
+            max_queued_messages: DEFAULT_MAX_QUEUED_MESSAGES,
+        }
+    }
+}
+
+// Schemas given their size at runtime only find out here whether it fits.
+pub(crate) fn layout<CipherType>(size: usize, window_size: usize) -> Result<Layout>
+where
+    CipherType: Cipher,
+{
+    Layout::new::<CipherType>(size, window_size).ok_or_else(|| {
+        anyhow!(
+            "Input of {} bytes and window of {} don't fit in a datagram",
+            size,
+            window_size
+        )
+    })
+}
+
+// Datagrams never grow past their schema's budget, which has to leave room for at least the
+// newest slot, and parity if it's sent.
+pub(crate) fn datagram_size(layout: &Layout, max_datagram_size: usize, redundancy: Redundancy) -> Result<usize>
+{
+    let min_datagram_size = layout.min_datagram_size(redundancy);
+    if max_datagram_size < min_datagram_size
+    {
+        return Err(anyhow!(
+            "Datagram budget of {} bytes below minimum of {}",
+            max_datagram_size,
+            min_datagram_size
+        ));
+    }
+
+    Ok(std::cmp::min(max_datagram_size, layout.datagram_size))
+}
+
+// Baselines are named by how far back they are, so there's no use keeping more than can be told
+// apart.
+pub(crate) fn check_baselines(baselines: usize) -> Result<()>
+{
+    if baselines > (u16::MAX as usize)
+    {
+        return Err(anyhow!("Can't keep {} baselines, at most {}", baselines, u16::MAX));
+    }
+
+    Ok(())
+}
+
+// Snapshots are sent a budget of fragments at a time, which has to let at least one through.
+pub(crate) fn check_max_fragments_per_poll(max_fragments_per_poll: usize) -> Result<()>
+{
+    if max_fragments_per_poll == 0
+    {
+        return Err(anyhow!("Can't send snapshots 0 fragments at a time"));
+    }
+
+    Ok(())
+}
+
+// Messages are queued up to a limit, which has to leave room for at least one.
+pub(crate) fn check_max_queued_messages(max_queued_messages: usize) -> Result<()>
+{
+    if max_queued_messages == 0
+    {
+        return Err(anyhow!("Can't queue 0 messages"));
+    }
+
+    Ok(())
+}
+
+// Datagrams bigger than the path carries are dropped along the way without a word, so schemas
+// are held to the path MTU QUIC has found for the Session, which starts out at 1200 bytes.
+pub(crate) fn check_path_mtu(datagram_size: usize, path_mtu: usize) -> Result<()>
+{
+    if datagram_size > path_mtu
+    {
+        return Err(anyhow!(
+            "Datagrams of {} bytes exceed path MTU of {}",
+            datagram_size,
+            path_mtu
+        ));
+    }
+
+    Ok(())
+}
+
+// The path MTU can still come down later on, when QUIC finds it no longer holds.
+pub(crate) fn oversized_schemas(datagram_sizes: &[(&'static str, usize)], path_mtu: usize) -> Vec<&'static str>
+{
+    datagram_sizes
+        .iter()
+        .filter(|(_, datagram_size)| *datagram_size > path_mtu)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+// Snapshots given their size at runtime only find out here whether they can be fragmented.
+pub(crate) fn snapshot_layout<CipherType>(max_snapshot_size: usize, max_datagram_size: usize) -> Result<SnapshotLayout>
+where
+    CipherType: Cipher,
+{
+    SnapshotLayout::new::<CipherType>(max_snapshot_size, max_datagram_size).ok_or_else(|| {
+        anyhow!(
+            "Snapshot of {} bytes doesn't fit in datagrams of {} bytes",
+            max_snapshot_size,
+            max_datagram_size
+        )
+    })
 }
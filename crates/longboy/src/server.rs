@@ -1,28 +1,54 @@
 mod client_to_server_receiver;
 mod factory;
+mod mapper_stats;
+mod message_session_event;
+mod server_messages;
 mod server_session;
 mod server_session_event;
 mod server_to_client_sender;
+mod server_to_client_snapshot_sender;
 
 // API
-pub use self::{factory::*, server_session::*};
+pub use self::{factory::*, mapper_stats::*, server_session::*};
 
 // Internal
-pub(crate) use self::{client_to_server_receiver::*, server_session_event::*, server_to_client_sender::*};
+pub(crate) use self::{
+    client_to_server_receiver::*, message_session_event::*, server_messages::*, server_session_event::*,
+    server_to_client_sender::*, server_to_client_snapshot_sender::*,
+};
 
-use crate::{ClientToServerSchema, Constants, Mirroring, Runtime, RuntimeTask, ServerToClientSchema, Sink, Source};
+use crate::{
+    check_baselines, check_max_fragments_per_poll, check_max_queued_messages, check_path_mtu, datagram_size, layout,
+    message_channel, oversized_schemas, snapshot_layout, ChannelStats, Cipher, ClientToServerSchema, DynSink,
+    DynSource, MessageSchema, MessageSink, MessageSource, MessageStats, MetadataSink, Mirroring, ReceiverStats,
+    Runtime, RuntimeTask, ServerToClientSchema, SharedSocket, SnapshotSchema, SnapshotSenderStats, SnapshotSource,
+    VariableSource,
+};
 use anyhow::{anyhow, Context, Result};
 use enum_map::{enum_map, EnumMap};
 use flume::Sender as FlumeSender;
 use fnv::{FnvHashMap, FnvHashSet};
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    future::Future,
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+};
 
 pub struct Server
 {
     sessions: FnvHashMap<u64, ServerSession>,
-    session_senders: Box<[FlumeSender<ServerSessionEvent>]>,
+    sender_session_senders: Box<[FlumeSender<ServerSessionEvent>]>,
+    receiver_session_senders: Box<[FlumeSender<ServerSessionEvent>]>,
+    message_session_senders: Box<[MessageSessionSender]>,
     #[allow(unused)]
     runtime: Box<dyn Runtime>,
+
+    datagram_sizes: Box<[(&'static str, usize)]>,
+    mapper_stats: FnvHashMap<&'static str, Arc<Mutex<MapperStats>>>,
+    channel_stats: FnvHashMap<&'static str, Arc<Mutex<ChannelStats>>>,
+    receiver_stats: FnvHashMap<&'static str, Arc<Mutex<FnvHashMap<u64, ReceiverStats>>>>,
+    snapshot_sender_stats: FnvHashMap<&'static str, Arc<Mutex<FnvHashMap<u64, SnapshotSenderStats>>>>,
+    message_stats: FnvHashMap<&'static str, Arc<Mutex<FnvHashMap<u64, MessageStats>>>>,
 }
 
 pub struct ServerBuilder
@@ -30,11 +56,30 @@ pub struct ServerBuilder
     session_capacity: usize,
     runtime: Box<dyn Runtime>,
 
-    ports: FnvHashSet<u16>,
-    session_senders: Vec<FlumeSender<ServerSessionEvent>>,
+    sockets: FnvHashMap<u16, Arc<SharedSocket>>,
+    lane_sockets: Option<EnumMap<Mirroring, Arc<SharedSocket>>>,
+    channels: FnvHashSet<u8>,
+    datagram_sizes: Vec<(&'static str, usize)>,
+    mapper_stats: FnvHashMap<&'static str, Arc<Mutex<MapperStats>>>,
+    channel_stats: FnvHashMap<&'static str, Arc<Mutex<ChannelStats>>>,
+    receiver_stats: FnvHashMap<&'static str, Arc<Mutex<FnvHashMap<u64, ReceiverStats>>>>,
+    snapshot_sender_stats: FnvHashMap<&'static str, Arc<Mutex<FnvHashMap<u64, SnapshotSenderStats>>>>,
+    message_stats: FnvHashMap<&'static str, Arc<Mutex<FnvHashMap<u64, MessageStats>>>>,
+    sender_session_senders: Vec<FlumeSender<ServerSessionEvent>>,
+    receiver_session_senders: Vec<FlumeSender<ServerSessionEvent>>,
+    message_session_senders: Vec<MessageSessionSender>,
     tasks: Vec<Box<dyn RuntimeTask>>,
 }
 
+// Everything needed to hand a new Session's message stream to the task serving its schema.
+struct MessageSessionSender
+{
+    channel_id: u8,
+    max_message_size: usize,
+    max_queued_messages: usize,
+    session_sender: FlumeSender<MessageSessionEvent>,
+}
+
 impl Server
 {
     pub fn builder(session_capacity: usize, runtime: Box<dyn Runtime>) -> ServerBuilder
@@ -43,73 +88,237 @@ impl Server
             session_capacity,
             runtime,
 
-            ports: FnvHashSet::default(),
+            sockets: FnvHashMap::default(),
+            lane_sockets: None,
+            channels: FnvHashSet::default(),
+            datagram_sizes: Vec::new(),
+            mapper_stats: FnvHashMap::default(),
+            channel_stats: FnvHashMap::default(),
+            receiver_stats: FnvHashMap::default(),
+            snapshot_sender_stats: FnvHashMap::default(),
+            message_stats: FnvHashMap::default(),
             tasks: Vec::new(),
-            session_senders: Vec::new(),
+            sender_session_senders: Vec::new(),
+            receiver_session_senders: Vec::new(),
+            message_session_senders: Vec::new(),
         }
     }
 
-    pub fn register(&mut self, session: ServerSession)
+    pub fn register(&mut self, mut session: ServerSession) -> Result<()>
     {
         assert!(self.sessions.len() < self.sessions.capacity());
 
+        // Sessions whose path won't carry every schema's datagrams are turned away before anything
+        // hears of them.
+        let path_mtu = session.path_mtu();
+        for (name, datagram_size) in self.datagram_sizes.iter()
+        {
+            check_path_mtu(*datagram_size, path_mtu).context(*name)?;
+        }
+
         let session_id = session.session_id();
         let cipher_key = session.cipher_key();
 
+        // Message streams are served from the Session's connection, only when there are any.
+        if !self.message_session_senders.is_empty()
+        {
+            let mut routes = FnvHashMap::default();
+            for message_session_sender in self.message_session_senders.iter()
+            {
+                let (endpoint, route) = message_channel(
+                    message_session_sender.max_message_size,
+                    message_session_sender.max_queued_messages,
+                );
+                message_session_sender
+                    .session_sender
+                    .send(MessageSessionEvent::Connected { session_id, endpoint })
+                    .unwrap();
+                routes.insert(message_session_sender.channel_id, route);
+            }
+            session.serve_messages(routes);
+        }
+
         self.sessions.insert(session_id, session);
-        self.session_senders.iter().for_each(|session_sender| {
+        self.session_senders().for_each(|session_sender| {
             session_sender
                 .send(ServerSessionEvent::Connected { session_id, cipher_key })
                 .unwrap()
         });
+        Ok(())
     }
 
     pub fn unregister(&mut self, session_id: u64)
     {
         assert!(self.sessions.contains_key(&session_id));
 
-        self.session_senders.iter().for_each(|session_sender| {
+        self.session_senders().for_each(|session_sender| {
             session_sender
                 .send(ServerSessionEvent::Disconnected { session_id })
                 .unwrap()
         });
+        self.message_session_senders.iter().for_each(|message_session_sender| {
+            message_session_sender
+                .session_sender
+                .send(MessageSessionEvent::Disconnected { session_id })
+                .unwrap()
+        });
         self.sessions.remove(&session_id);
     }
+
+    pub fn rotate_key(&mut self, session_id: u64, cipher_key: u64) -> impl Future<Output = Result<()>> + 'static
+    {
+        let session = self.sessions.get_mut(&session_id).expect("Unknown Session ID");
+        let epoch = session.epoch().wrapping_add(1);
+        let event = move || ServerSessionEvent::Rekeyed {
+            session_id,
+            epoch,
+            cipher_key,
+        };
+
+        // Receivers take the new key first, holding on to the old one until its grace period
+        // runs out, so they're ready the moment the Client starts using it.  Senders only switch
+        // over once the Client has acknowledged it, which is left to the Session rather than
+        // holding up the Server, and by then the tasks may have gone with it.  Until then they
+        // only check the Client's acknowledgements under it.
+        self.receiver_session_senders
+            .iter()
+            .for_each(|session_sender| session_sender.send(event()).unwrap());
+        self.sender_session_senders.iter().for_each(|session_sender| {
+            session_sender
+                .send(ServerSessionEvent::Announced {
+                    session_id,
+                    epoch,
+                    cipher_key,
+                })
+                .unwrap()
+        });
+        let sender_session_senders = self.sender_session_senders.clone();
+        session.announce_key(epoch, cipher_key, move || {
+            sender_session_senders.iter().for_each(|session_sender| {
+                let _ = session_sender.send(event());
+            })
+        })
+    }
+
+    pub fn mapper_stats(&self, name: &str) -> Option<MapperStats>
+    {
+        self.mapper_stats.get(name).map(|stats| *stats.lock().unwrap())
+    }
+
+    pub fn channel_stats(&self, name: &str) -> Option<ChannelStats>
+    {
+        self.channel_stats.get(name).map(|stats| *stats.lock().unwrap())
+    }
+
+    pub fn receiver_stats(&self, name: &str, session_id: u64) -> Option<ReceiverStats>
+    {
+        self.receiver_stats
+            .get(name)
+            .and_then(|stats| stats.lock().unwrap().get(&session_id).copied())
+    }
+
+    pub fn snapshot_sender_stats(&self, name: &str, session_id: u64) -> Option<SnapshotSenderStats>
+    {
+        self.snapshot_sender_stats
+            .get(name)
+            .and_then(|stats| stats.lock().unwrap().get(&session_id).copied())
+    }
+
+    pub fn message_stats(&self, name: &str, session_id: u64) -> Option<MessageStats>
+    {
+        self.message_stats
+            .get(name)
+            .and_then(|stats| stats.lock().unwrap().get(&session_id).copied())
+    }
+
+    pub fn oversized_schemas(&self, session_id: u64) -> Option<Vec<&'static str>>
+    {
+        self.sessions
+            .get(&session_id)
+            .map(|session| oversized_schemas(&self.datagram_sizes, session.path_mtu()))
+    }
+
+    fn session_senders(&self) -> impl Iterator<Item = &FlumeSender<ServerSessionEvent>>
+    {
+        self.sender_session_senders
+            .iter()
+            .chain(self.receiver_session_senders.iter())
+    }
 }
 
 impl ServerBuilder
 {
-    pub fn sender<SourceFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn sender<SourceFactoryType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
         self,
         schema: &ServerToClientSchema,
         source_factory: SourceFactoryType,
     ) -> Result<Self>
     where
-        SourceFactoryType: Factory<Type: Source<SIZE>>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+        SourceFactoryType: Factory<Type: VariableSource<SIZE>>,
+        CipherType: Cipher,
     {
-        let mapper_socket =
-            UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], schema.mapper_port))).context(schema.name)?;
+        self.dyn_sender::<_, CipherType>(schema, SIZE, WINDOW_SIZE, SizedSourceFactory(source_factory))
+    }
 
-        let sockets = enum_map! {
-            Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?,
-            Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?,
-            Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?,
-        };
+    pub fn sender_with_sockets<SourceFactoryType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
+        self,
+        schema: &ServerToClientSchema,
+        mapper_socket: UdpSocket,
+        sockets: EnumMap<Mirroring, UdpSocket>,
+        source_factory: SourceFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: VariableSource<SIZE>>,
+        CipherType: Cipher,
+    {
+        self.dyn_sender_with_sockets::<_, CipherType>(
+            schema,
+            SIZE,
+            WINDOW_SIZE,
+            mapper_socket,
+            sockets,
+            SizedSourceFactory(source_factory),
+        )
+    }
 
-        self.sender_with_sockets::<SourceFactoryType, SIZE, WINDOW_SIZE>(schema, mapper_socket, sockets, source_factory)
+    pub fn dyn_sender<SourceFactoryType, CipherType>(
+        mut self,
+        schema: &ServerToClientSchema,
+        size: usize,
+        window_size: usize,
+        source_factory: SourceFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: DynSource>,
+        CipherType: Cipher,
+    {
+        // Senders share one socket per lane, and any mapper port already bound, told apart by
+        // channel.
+        let mapper_socket = self.bind(schema.name, schema.mapper_port)?;
+        let sockets = self.lane_sockets(schema.name)?;
+
+        self.add_sender::<SourceFactoryType, CipherType>(
+            schema,
+            size,
+            window_size,
+            mapper_socket,
+            sockets,
+            source_factory,
+        )
     }
 
-    pub fn sender_with_sockets<SourceFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn dyn_sender_with_sockets<SourceFactoryType, CipherType>(
         mut self,
         schema: &ServerToClientSchema,
+        size: usize,
+        window_size: usize,
         mapper_socket: UdpSocket,
         sockets: EnumMap<Mirroring, UdpSocket>,
         source_factory: SourceFactoryType,
     ) -> Result<Self>
     where
-        SourceFactoryType: Factory<Type: Source<SIZE>>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+        SourceFactoryType: Factory<Type: DynSource>,
+        CipherType: Cipher,
     {
         if schema.mapper_port != mapper_socket.local_addr().unwrap().port()
         {
@@ -120,57 +329,268 @@ impl ServerBuilder
             ))
             .context(schema.name);
         }
-        if !self.ports.insert(schema.mapper_port)
+        let mapper_socket = self.register(schema.name, mapper_socket)?;
+        let sockets = sockets.map(|_, socket| SharedSocket::new(socket));
+
+        self.add_sender::<SourceFactoryType, CipherType>(
+            schema,
+            size,
+            window_size,
+            mapper_socket,
+            sockets,
+            source_factory,
+        )
+    }
+
+    fn add_sender<SourceFactoryType, CipherType>(
+        mut self,
+        schema: &ServerToClientSchema,
+        size: usize,
+        window_size: usize,
+        mapper_socket: Arc<SharedSocket>,
+        sockets: EnumMap<Mirroring, Arc<SharedSocket>>,
+        source_factory: SourceFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: DynSource>,
+        CipherType: Cipher,
+    {
+        if !self.channels.insert(schema.channel_id)
+        {
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
+        }
+        if self.mapper_stats.contains_key(schema.name)
         {
-            return Err(anyhow!("Reused port {}", schema.mapper_port)).context(schema.name);
+            return Err(anyhow!("Reused mapper name {}", schema.name)).context(schema.name);
         }
+        let layout = layout::<CipherType>(size, window_size).context(schema.name)?;
+        let datagram_size = datagram_size(&layout, schema.max_datagram_size, schema.redundancy).context(schema.name)?;
 
         let (session_sender, session_receiver) = flume::unbounded();
+        let mapper_stats = Arc::new(Mutex::new(MapperStats::default()));
+        let channel_stats = Arc::new(Mutex::new(ChannelStats::default()));
 
-        let server_to_client_sender = ServerToClientSender::<SourceFactoryType, SIZE, WINDOW_SIZE>::new(
+        let server_to_client_sender = ServerToClientSender::<SourceFactoryType, CipherType>::new(
             format!("ServerToClientSender: {}", schema.name),
+            schema.name,
+            layout,
+            schema.channel_id,
+            mapper_socket
+                .channel(schema.channel_id, channel_stats.clone())
+                .expect("Reused channel"),
+            sockets.map(|_, socket| {
+                socket
+                    .channel(schema.channel_id, channel_stats.clone())
+                    .expect("Reused channel")
+            }),
+            schema.encoding,
+            schema.keepalive_period,
+            schema.redundancy,
+            schema.max_datagram_size,
+            schema.ack_period,
+            self.session_capacity,
+            session_receiver,
+            source_factory,
+            mapper_stats.clone(),
+        )
+        .context(schema.name)?;
+
+        self.datagram_sizes.push((schema.name, datagram_size));
+        self.mapper_stats.insert(schema.name, mapper_stats);
+        self.channel_stats.insert(schema.name, channel_stats);
+        self.tasks.push(Box::new(server_to_client_sender));
+        self.sender_session_senders.push(session_sender);
+        Ok(self)
+    }
+
+    pub fn snapshot_sender<SourceFactoryType, CipherType>(
+        mut self,
+        schema: &SnapshotSchema,
+        max_snapshot_size: usize,
+        source_factory: SourceFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: SnapshotSource>,
+        CipherType: Cipher,
+    {
+        let mapper_socket = self.bind(schema.name, schema.mapper_port)?;
+        let sockets = self.lane_sockets(schema.name)?;
+
+        self.add_snapshot_sender::<SourceFactoryType, CipherType>(
+            schema,
+            max_snapshot_size,
             mapper_socket,
             sockets,
+            source_factory,
+        )
+    }
+
+    pub fn snapshot_sender_with_sockets<SourceFactoryType, CipherType>(
+        mut self,
+        schema: &SnapshotSchema,
+        max_snapshot_size: usize,
+        mapper_socket: UdpSocket,
+        sockets: EnumMap<Mirroring, UdpSocket>,
+        source_factory: SourceFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: SnapshotSource>,
+        CipherType: Cipher,
+    {
+        if schema.mapper_port != mapper_socket.local_addr().unwrap().port()
+        {
+            return Err(anyhow!(
+                "Schema's `mapper_port` does not match Mapper Socket port: {} vs {}",
+                schema.mapper_port,
+                mapper_socket.local_addr().unwrap().port()
+            ))
+            .context(schema.name);
+        }
+        let mapper_socket = self.register(schema.name, mapper_socket)?;
+        let sockets = sockets.map(|_, socket| SharedSocket::new(socket));
+
+        self.add_snapshot_sender::<SourceFactoryType, CipherType>(
+            schema,
+            max_snapshot_size,
+            mapper_socket,
+            sockets,
+            source_factory,
+        )
+    }
+
+    fn add_snapshot_sender<SourceFactoryType, CipherType>(
+        mut self,
+        schema: &SnapshotSchema,
+        max_snapshot_size: usize,
+        mapper_socket: Arc<SharedSocket>,
+        sockets: EnumMap<Mirroring, Arc<SharedSocket>>,
+        source_factory: SourceFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: SnapshotSource>,
+        CipherType: Cipher,
+    {
+        if !self.channels.insert(schema.channel_id)
+        {
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
+        }
+        if self.mapper_stats.contains_key(schema.name)
+        {
+            return Err(anyhow!("Reused mapper name {}", schema.name)).context(schema.name);
+        }
+        let layout = snapshot_layout::<CipherType>(max_snapshot_size, schema.max_datagram_size).context(schema.name)?;
+        check_baselines(schema.baselines).context(schema.name)?;
+        check_max_fragments_per_poll(schema.max_fragments_per_poll).context(schema.name)?;
+
+        let (session_sender, session_receiver) = flume::unbounded();
+        let mapper_stats = Arc::new(Mutex::new(MapperStats::default()));
+        let channel_stats = Arc::new(Mutex::new(ChannelStats::default()));
+        let stats = Arc::new(Mutex::new(FnvHashMap::with_capacity_and_hasher(
+            self.session_capacity,
+            Default::default(),
+        )));
+
+        let server_to_client_snapshot_sender = ServerToClientSnapshotSender::<SourceFactoryType, CipherType>::new(
+            format!("ServerToClientSnapshotSender: {}", schema.name),
+            schema.name,
+            max_snapshot_size,
+            schema.baselines,
+            schema.channel_id,
+            mapper_socket
+                .channel(schema.channel_id, channel_stats.clone())
+                .expect("Reused channel"),
+            sockets.map(|_, socket| {
+                socket
+                    .channel(schema.channel_id, channel_stats.clone())
+                    .expect("Reused channel")
+            }),
+            schema.max_datagram_size,
+            schema.max_fragments_per_poll,
             self.session_capacity,
             session_receiver,
             source_factory,
+            mapper_stats.clone(),
+            stats.clone(),
         )
         .context(schema.name)?;
 
-        self.tasks.push(Box::new(server_to_client_sender));
-        self.session_senders.push(session_sender);
+        self.datagram_sizes.push((schema.name, layout.datagram_size));
+        self.mapper_stats.insert(schema.name, mapper_stats);
+        self.channel_stats.insert(schema.name, channel_stats);
+        self.snapshot_sender_stats.insert(schema.name, stats);
+        self.tasks.push(Box::new(server_to_client_snapshot_sender));
+        self.sender_session_senders.push(session_sender);
         Ok(self)
     }
 
-    pub fn receiver<SinkFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn receiver<SinkFactoryType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
+        self,
+        schema: &ClientToServerSchema,
+        sink_factory: SinkFactoryType,
+    ) -> Result<Self>
+    where
+        SinkFactoryType: Factory<Type: MetadataSink<SIZE>>,
+        CipherType: Cipher,
+    {
+        self.dyn_receiver::<_, CipherType>(schema, SIZE, WINDOW_SIZE, SizedSinkFactory(sink_factory))
+    }
+
+    pub fn receiver_with_socket<SinkFactoryType, CipherType, const SIZE: usize, const WINDOW_SIZE: usize>(
         self,
         schema: &ClientToServerSchema,
+        mapper_socket: UdpSocket,
+        socket: UdpSocket,
+        sink_factory: SinkFactoryType,
+    ) -> Result<Self>
+    where
+        SinkFactoryType: Factory<Type: MetadataSink<SIZE>>,
+        CipherType: Cipher,
+    {
+        self.dyn_receiver_with_socket::<_, CipherType>(
+            schema,
+            SIZE,
+            WINDOW_SIZE,
+            mapper_socket,
+            socket,
+            SizedSinkFactory(sink_factory),
+        )
+    }
+
+    pub fn dyn_receiver<SinkFactoryType, CipherType>(
+        mut self,
+        schema: &ClientToServerSchema,
+        size: usize,
+        window_size: usize,
         sink_factory: SinkFactoryType,
     ) -> Result<Self>
     where
-        SinkFactoryType: Factory<Type: Sink<SIZE>>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+        SinkFactoryType: Factory<Type: DynSink>,
+        CipherType: Cipher,
     {
-        let mapper_socket =
-            UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], schema.mapper_port))).context(schema.name)?;
+        if schema.mapper_port == schema.port
+        {
+            return Err(anyhow!("Reused port {}", schema.port)).context(schema.name);
+        }
 
-        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(schema.name)?;
+        // Receivers share any port already bound, told apart by channel.
+        let mapper_socket = self.bind(schema.name, schema.mapper_port)?;
+        let socket = self.bind(schema.name, schema.port)?;
 
-        self.receiver_with_socket::<SinkFactoryType, SIZE, WINDOW_SIZE>(schema, mapper_socket, socket, sink_factory)
+        self.add_receiver::<SinkFactoryType, CipherType>(schema, size, window_size, mapper_socket, socket, sink_factory)
     }
 
-    pub fn receiver_with_socket<SinkFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>(
+    pub fn dyn_receiver_with_socket<SinkFactoryType, CipherType>(
         mut self,
         schema: &ClientToServerSchema,
+        size: usize,
+        window_size: usize,
         mapper_socket: UdpSocket,
         socket: UdpSocket,
         sink_factory: SinkFactoryType,
     ) -> Result<Self>
     where
-        SinkFactoryType: Factory<Type: Sink<SIZE>>,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-        [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+        SinkFactoryType: Factory<Type: DynSink>,
+        CipherType: Cipher,
     {
         if schema.mapper_port != mapper_socket.local_addr().unwrap().port()
         {
@@ -185,35 +605,170 @@ impl ServerBuilder
         {
             return Err(anyhow!(
                 "Schema's `port` does not match Socket port: {} vs {}",
-                schema.mapper_port,
-                mapper_socket.local_addr().unwrap().port()
+                schema.port,
+                socket.local_addr().unwrap().port()
             ))
             .context(schema.name);
         }
-        if !self.ports.insert(schema.mapper_port)
+        let mapper_socket = self.register(schema.name, mapper_socket)?;
+        let socket = self.register(schema.name, socket)?;
+
+        self.add_receiver::<SinkFactoryType, CipherType>(schema, size, window_size, mapper_socket, socket, sink_factory)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_receiver<SinkFactoryType, CipherType>(
+        mut self,
+        schema: &ClientToServerSchema,
+        size: usize,
+        window_size: usize,
+        mapper_socket: Arc<SharedSocket>,
+        socket: Arc<SharedSocket>,
+        sink_factory: SinkFactoryType,
+    ) -> Result<Self>
+    where
+        SinkFactoryType: Factory<Type: DynSink>,
+        CipherType: Cipher,
+    {
+        if !self.channels.insert(schema.channel_id)
         {
-            return Err(anyhow!("Reused port {}", schema.mapper_port)).context(schema.name);
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
         }
-
-        socket.set_nonblocking(true).context(schema.name)?;
+        if self.receiver_stats.contains_key(schema.name)
+        {
+            return Err(anyhow!("Reused receiver name {}", schema.name)).context(schema.name);
+        }
+        if self.mapper_stats.contains_key(schema.name)
+        {
+            return Err(anyhow!("Reused mapper name {}", schema.name)).context(schema.name);
+        }
+        let layout = layout::<CipherType>(size, window_size).context(schema.name)?;
+        let datagram_size = datagram_size(&layout, schema.max_datagram_size, schema.redundancy).context(schema.name)?;
 
         let (session_sender, session_receiver) = flume::unbounded();
+        let mapper_stats = Arc::new(Mutex::new(MapperStats::default()));
+        let channel_stats = Arc::new(Mutex::new(ChannelStats::default()));
+        let stats = Arc::new(Mutex::new(FnvHashMap::with_capacity_and_hasher(
+            self.session_capacity,
+            Default::default(),
+        )));
 
-        let client_to_server_receiver = ClientToServerReceiver::<SinkFactoryType, SIZE, WINDOW_SIZE>::new(
+        let client_to_server_receiver = ClientToServerReceiver::<SinkFactoryType, CipherType>::new(
             format!("ClientToServerReceiver: {}", schema.name),
-            mapper_socket,
-            socket,
+            schema.name,
+            layout,
+            schema.channel_id,
+            mapper_socket
+                .channel(schema.channel_id, channel_stats.clone())
+                .expect("Reused channel"),
+            socket
+                .channel(schema.channel_id, channel_stats.clone())
+                .expect("Reused channel"),
+            schema.delivery,
+            schema.key_grace_period,
+            schema.ack_period,
             self.session_capacity,
             session_receiver,
             sink_factory,
+            mapper_stats.clone(),
+            stats.clone(),
         )
         .context(schema.name)?;
 
+        self.datagram_sizes.push((schema.name, datagram_size));
+        self.mapper_stats.insert(schema.name, mapper_stats);
+        self.channel_stats.insert(schema.name, channel_stats);
+        self.receiver_stats.insert(schema.name, stats);
         self.tasks.push(Box::new(client_to_server_receiver));
-        self.session_senders.push(session_sender);
+        self.receiver_session_senders.push(session_sender);
         Ok(self)
     }
 
+    pub fn messages<SourceFactoryType, SinkFactoryType>(
+        mut self,
+        schema: &MessageSchema,
+        source_factory: SourceFactoryType,
+        sink_factory: SinkFactoryType,
+    ) -> Result<Self>
+    where
+        SourceFactoryType: Factory<Type: MessageSource>,
+        SinkFactoryType: Factory<Type: MessageSink>,
+    {
+        if !self.channels.insert(schema.channel_id)
+        {
+            return Err(anyhow!("Reused channel {}", schema.channel_id)).context(schema.name);
+        }
+        check_max_queued_messages(schema.max_queued_messages).context(schema.name)?;
+
+        let (session_sender, session_receiver) = flume::unbounded();
+        let stats = Arc::new(Mutex::new(FnvHashMap::with_capacity_and_hasher(
+            self.session_capacity,
+            Default::default(),
+        )));
+
+        let server_messages = ServerMessages::new(
+            format!("ServerMessages: {}", schema.name),
+            schema.max_message_size,
+            self.session_capacity,
+            session_receiver,
+            source_factory,
+            sink_factory,
+            stats.clone(),
+        );
+
+        self.message_stats.insert(schema.name, stats);
+        self.tasks.push(Box::new(server_messages));
+        self.message_session_senders.push(MessageSessionSender {
+            channel_id: schema.channel_id,
+            max_message_size: schema.max_message_size,
+            max_queued_messages: schema.max_queued_messages,
+            session_sender,
+        });
+        Ok(self)
+    }
+
+    // Ports bound by an earlier schema are shared with it.
+    fn bind(&mut self, name: &'static str, port: u16) -> Result<Arc<SharedSocket>>
+    {
+        if let Some(socket) = self.sockets.get(&port)
+        {
+            return Ok(socket.clone());
+        }
+
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))).context(name)?;
+        self.register(name, socket)
+    }
+
+    // Sockets handed over by the caller are theirs alone, so their port can't already be bound.
+    fn register(&mut self, name: &'static str, socket: UdpSocket) -> Result<Arc<SharedSocket>>
+    {
+        let socket = SharedSocket::new(socket);
+        if self.sockets.contains_key(&socket.port())
+        {
+            return Err(anyhow!("Reused port {}", socket.port())).context(name);
+        }
+
+        self.sockets.insert(socket.port(), socket.clone());
+        Ok(socket)
+    }
+
+    // Senders share one socket per lane, bound by whichever comes first.
+    fn lane_sockets(&mut self, name: &'static str) -> Result<EnumMap<Mirroring, Arc<SharedSocket>>>
+    {
+        if let Some(sockets) = self.lane_sockets.as_ref()
+        {
+            return Ok(sockets.clone());
+        }
+
+        let sockets = enum_map! {
+            Mirroring::AudioVideo => SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(name)?),
+            Mirroring::Background => SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(name)?),
+            Mirroring::Voice => SharedSocket::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).context(name)?),
+        };
+        self.lane_sockets = Some(sockets.clone());
+        Ok(sockets)
+    }
+
     pub fn build(mut self) -> Server
     {
         for task in self.tasks.into_iter()
@@ -223,8 +778,17 @@ impl ServerBuilder
 
         Server {
             sessions: FnvHashMap::with_capacity_and_hasher(self.session_capacity, Default::default()),
-            session_senders: self.session_senders.into_boxed_slice(),
+            sender_session_senders: self.sender_session_senders.into_boxed_slice(),
+            receiver_session_senders: self.receiver_session_senders.into_boxed_slice(),
+            message_session_senders: self.message_session_senders.into_boxed_slice(),
             runtime: self.runtime,
+
+            datagram_sizes: self.datagram_sizes.into_boxed_slice(),
+            mapper_stats: self.mapper_stats,
+            channel_stats: self.channel_stats,
+            receiver_stats: self.receiver_stats,
+            snapshot_sender_stats: self.snapshot_sender_stats,
+            message_stats: self.message_stats,
         }
     }
 }
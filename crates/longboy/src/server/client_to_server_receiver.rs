@@ -1,4 +1,7 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
 use enum_map::{Enum, EnumMap};
@@ -6,63 +9,94 @@ use flume::Receiver as FlumeReceiver;
 use fnv::FnvHashMap;
 use thunderdome::{Arena, Index};
 
-use crate::{Constants, Factory, Mirroring, Receiver, RuntimeTask, ServerSessionEvent, Sink};
+use crate::{
+    derive_channel_key, supports_version, ChannelSocket, Cipher, Delivery, Direction, DynReceiver, DynSink, Factory,
+    Instant, Layout, MapperStats, Mirroring, ReceiverStats, RuntimeTask, ServerSessionEvent, HEARTBEAT_SIZE,
+};
 
-pub(crate) struct ClientToServerReceiver<SinkFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>
+pub(crate) struct ClientToServerReceiver<SinkFactoryType, CipherType>
 where
-    SinkFactoryType: Factory<Type: Sink<SIZE>>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkFactoryType: Factory<Type: DynSink>,
+    CipherType: Cipher,
 {
     name: String,
+    schema_name: &'static str,
+    layout: Layout,
+    channel_id: u8,
 
-    mapper_socket: UdpSocket,
+    mapper_socket: ChannelSocket,
+    mapper_stats: MapperStats,
+    shared_mapper_stats: Arc<Mutex<MapperStats>>,
 
-    socket: UdpSocket,
+    socket: ChannelSocket,
+    buffer: Box<[u8]>,
+    delivery: Delivery,
+    key_grace_period: u16,
+    ack_period: Option<u16>,
+    last_ack: Instant,
 
     session_receiver: FlumeReceiver<ServerSessionEvent>,
-    sessions: Arena<ReceiverSession<SinkFactoryType::Type, SIZE, WINDOW_SIZE>>,
+    sessions: Arena<ReceiverSession<SinkFactoryType::Type, CipherType>>,
     session_id_to_session_map: FnvHashMap<u64, Index>,
     socket_addr_to_session_map: FnvHashMap<SocketAddr, Index>,
     sink_factory: SinkFactoryType,
+    stats: Arc<Mutex<FnvHashMap<u64, ReceiverStats>>>,
 }
 
-struct ReceiverSession<SinkType, const SIZE: usize, const WINDOW_SIZE: usize>
+struct ReceiverSession<SinkType, CipherType>
 where
-    SinkType: Sink<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkType: DynSink,
+    CipherType: Cipher,
 {
     socket_addrs: EnumMap<Mirroring, Option<SocketAddr>>,
-    receiver: Receiver<SinkType, SIZE, WINDOW_SIZE>,
+    receiver: DynReceiver<SinkType, CipherType>,
 }
 
-impl<SinkFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>
-    ClientToServerReceiver<SinkFactoryType, SIZE, WINDOW_SIZE>
+impl<SinkFactoryType, CipherType> ClientToServerReceiver<SinkFactoryType, CipherType>
 where
-    SinkFactoryType: Factory<Type: Sink<SIZE>>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkFactoryType: Factory<Type: DynSink>,
+    CipherType: Cipher,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         name: String,
-        mapper_socket: UdpSocket,
-        socket: UdpSocket,
+        schema_name: &'static str,
+        layout: Layout,
+        channel_id: u8,
+        mapper_socket: ChannelSocket,
+        socket: ChannelSocket,
+        delivery: Delivery,
+        key_grace_period: u16,
+        ack_period: Option<u16>,
         session_capacity: usize,
         session_receiver: FlumeReceiver<ServerSessionEvent>,
         sink_factory: SinkFactoryType,
+        mapper_stats: Arc<Mutex<MapperStats>>,
+        stats: Arc<Mutex<FnvHashMap<u64, ReceiverStats>>>,
     ) -> Result<Self>
     {
-        mapper_socket.set_nonblocking(true)?;
+        mapper_socket.socket().set_nonblocking(true)?;
 
-        socket.set_nonblocking(true)?;
+        socket.socket().set_nonblocking(true)?;
 
         Ok(Self {
             name,
+            schema_name,
+            layout,
+            channel_id,
 
             mapper_socket,
+            mapper_stats: MapperStats::default(),
+            shared_mapper_stats: mapper_stats,
 
             socket,
+            // One byte past the largest datagram, so oversized datagrams are left for the
+            // Receiver to count.
+            buffer: vec![0; layout.datagram_size + 1].into_boxed_slice(),
+            delivery,
+            key_grace_period,
+            ack_period,
+            last_ack: Instant::default(),
 
             session_receiver,
             sessions: Arena::with_capacity(session_capacity),
@@ -72,27 +106,24 @@ where
                 Default::default(),
             ),
             sink_factory,
+            stats,
         })
     }
 }
 
-impl<SinkFactoryType, const SIZE: usize, const WINDOW_SIZE: usize> RuntimeTask
-    for ClientToServerReceiver<SinkFactoryType, SIZE, WINDOW_SIZE>
+impl<SinkFactoryType, CipherType> RuntimeTask for ClientToServerReceiver<SinkFactoryType, CipherType>
 where
-    SinkFactoryType: Factory<Type: Sink<SIZE>>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    SinkFactoryType: Factory<Type: DynSink>,
+    CipherType: Cipher,
 {
     fn name(&self) -> &str
     {
         &self.name
     }
 
-    fn poll(&mut self, timestamp: u16)
+    fn poll(&mut self, now: Instant)
     {
-        // Alias constants so they're less painful to read.
-        #[allow(non_snake_case)]
-        let DATAGRAM_SIZE: usize = Constants::<SIZE, WINDOW_SIZE>::DATAGRAM_SIZE;
+        let timestamp = now.timestamp();
 
         // Handle Session changes.
         for event in self.session_receiver.try_iter()
@@ -101,14 +132,38 @@ where
             {
                 ServerSessionEvent::Connected { session_id, cipher_key } =>
                 {
+                    let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ClientToServer);
                     let index = self.sessions.insert(ReceiverSession {
                         socket_addrs: EnumMap::default(),
-                        receiver: Receiver::new(cipher_key, self.sink_factory.invoke(session_id)),
+                        receiver: DynReceiver::new(
+                            cipher_key,
+                            self.layout.size,
+                            self.layout.window_size,
+                            self.sink_factory.invoke(session_id),
+                        )
+                        .with_channel(self.channel_id)
+                        .with_delivery(self.delivery)
+                        .with_key_grace_period(self.key_grace_period),
                     });
                     self.session_id_to_session_map
                         .try_insert(session_id, index)
                         .expect("Duplicate Session ID");
                 }
+                // Receivers take keys as soon as they're announced.
+                ServerSessionEvent::Announced { .. } => (),
+                ServerSessionEvent::Rekeyed {
+                    session_id,
+                    epoch,
+                    cipher_key,
+                } =>
+                {
+                    let index = self
+                        .session_id_to_session_map
+                        .get(&session_id)
+                        .expect("Unknown Session ID");
+                    let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ClientToServer);
+                    self.sessions[*index].receiver.rekey(epoch, cipher_key);
+                }
                 ServerSessionEvent::Disconnected { session_id } =>
                 {
                     let index = self
@@ -120,31 +175,51 @@ where
                     {
                         self.socket_addr_to_session_map.remove(socket_addr);
                     }
+                    self.stats.lock().unwrap().remove(&session_id);
                 }
             }
         }
 
-        // Update Client socket addresses.
+        // Update Client socket addresses.  Heartbeats lead with their version, anything past it
+        // is only known for versions we support.
         let mut buffer = [0; 64];
         while let Ok((len, socket_addr)) = self.mapper_socket.recv_from(&mut buffer)
         {
-            if len != std::mem::size_of::<u64>() + std::mem::size_of::<u8>()
+            if len > 0 && !supports_version(buffer[0])
             {
+                self.mapper_stats.heartbeats_unsupported_version += 1;
+                continue;
+            }
+            if len != HEARTBEAT_SIZE
+            {
+                self.mapper_stats.heartbeats_malformed += 1;
+                continue;
+            }
+            if buffer[1] != self.channel_id
+            {
+                self.mapper_stats.heartbeats_unknown_channel += 1;
                 continue;
             }
 
-            let session_id = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[0..8]).unwrap());
-
-            let mirroring = buffer[8] as usize;
-            if mirroring >= Mirroring::LENGTH
+            if (buffer[11] as usize) >= Mirroring::LENGTH
             {
+                self.mapper_stats.heartbeats_malformed += 1;
                 continue;
             }
-            let mirroring = Mirroring::from_usize(mirroring);
 
+            // Heartbeats have to check out under the Session's key before they're trusted with
+            // where its lanes are.
+            let session_id = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[2..10]).unwrap());
             if let Some(index) = self.session_id_to_session_map.get(&session_id)
             {
                 let session = self.sessions.get_mut(*index).unwrap();
+                let Some(mirroring) = session.receiver.check_heartbeat(session_id, &buffer[0..len])
+                else
+                {
+                    self.mapper_stats.heartbeats_check_failures += 1;
+                    continue;
+                };
+                self.mapper_stats.heartbeats_accepted += 1;
 
                 if let Some(socket_addr) = session.socket_addrs[mirroring]
                 {
@@ -155,16 +230,12 @@ where
                 self.socket_addr_to_session_map.insert(socket_addr, *index);
             }
         }
+        *self.shared_mapper_stats.lock().unwrap() = self.mapper_stats;
 
         // Process datagrams.
-        let mut buffer = [0; 512];
-        while let Ok((len, socket_addr)) = self.socket.recv_from(&mut buffer)
+        while let Ok((len, socket_addr)) = self.socket.recv_from(&mut self.buffer)
         {
-            if len != DATAGRAM_SIZE
-            {
-                continue;
-            }
-            let datagram = (&mut buffer[0..DATAGRAM_SIZE]).try_into().unwrap();
+            let datagram = &mut self.buffer[0..len];
 
             if let Some(index) = self.socket_addr_to_session_map.get(&socket_addr)
             {
@@ -175,5 +246,41 @@ where
                     .handle_datagram(timestamp, datagram);
             }
         }
+
+        // Release held input and publish stats.
+        let mut stats = self.stats.lock().unwrap();
+        for (session_id, index) in self.session_id_to_session_map.iter()
+        {
+            let receiver = &mut self.sessions.get_mut(*index).unwrap().receiver;
+            receiver.poll(timestamp);
+            stats.insert(*session_id, receiver.stats());
+        }
+
+        // Acknowledge what we've received back to each lane the Client heartbeats from, so its
+        // Sender can stop repeating it.  Heartbeats are checked before we take their word for
+        // where a lane is, and acknowledgements under the lane's key, so nobody else can cut the
+        // repetition short.
+        if let Some(ack_period) = self.ack_period
+            && now.saturating_since(self.last_ack) >= (ack_period as u64)
+        {
+            self.last_ack = now;
+
+            for (session_id, index) in self.session_id_to_session_map.iter()
+            {
+                let session = &self.sessions[*index];
+                for (mirroring, socket_addr) in session.socket_addrs.iter()
+                {
+                    if let Some(socket_addr) = socket_addr
+                    {
+                        self.mapper_socket
+                            .send_to(
+                                &session.receiver.acknowledgement_datagram(*session_id, mirroring),
+                                *socket_addr,
+                            )
+                            .expect("send_to failure");
+                    }
+                }
+            }
+        }
     }
 }
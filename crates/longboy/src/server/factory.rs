@@ -1,3 +1,5 @@
+use crate::{MetadataSink, SizedSink, SizedSource, VariableSource};
+
 pub trait Factory
 where
     Self: 'static + Send,
@@ -6,3 +8,32 @@ where
 
     fn invoke(&mut self, session_id: u64) -> Self::Type;
 }
+
+// Hands out fixed size sources and sinks to the runtime sized tasks underneath.
+pub(crate) struct SizedSourceFactory<FactoryType, const SIZE: usize>(pub(crate) FactoryType);
+
+impl<FactoryType, const SIZE: usize> Factory for SizedSourceFactory<FactoryType, SIZE>
+where
+    FactoryType: Factory<Type: VariableSource<SIZE>>,
+{
+    type Type = SizedSource<FactoryType::Type, SIZE>;
+
+    fn invoke(&mut self, session_id: u64) -> Self::Type
+    {
+        SizedSource(self.0.invoke(session_id))
+    }
+}
+
+pub(crate) struct SizedSinkFactory<FactoryType, const SIZE: usize>(pub(crate) FactoryType);
+
+impl<FactoryType, const SIZE: usize> Factory for SizedSinkFactory<FactoryType, SIZE>
+where
+    FactoryType: Factory<Type: MetadataSink<SIZE>>,
+{
+    type Type = SizedSink<FactoryType::Type, SIZE>;
+
+    fn invoke(&mut self, session_id: u64) -> Self::Type
+    {
+        SizedSink(self.0.invoke(session_id))
+    }
+}
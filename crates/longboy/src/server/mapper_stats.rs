@@ -0,0 +1,9 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapperStats
+{
+    pub heartbeats_accepted: u64,
+    pub heartbeats_malformed: u64,
+    pub heartbeats_unsupported_version: u64,
+    pub heartbeats_unknown_channel: u64,
+    pub heartbeats_check_failures: u64,
+}
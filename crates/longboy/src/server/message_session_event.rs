@@ -0,0 +1,13 @@
+use crate::MessageEndpoint;
+
+pub(crate) enum MessageSessionEvent
+{
+    Connected
+    {
+        session_id: u64, endpoint: MessageEndpoint
+    },
+    Disconnected
+    {
+        session_id: u64
+    },
+}
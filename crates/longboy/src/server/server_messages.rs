@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use flume::Receiver as FlumeReceiver;
+use fnv::FnvHashMap;
+use thunderdome::{Arena, Index};
+
+use crate::{
+    Factory, Instant, MessageEndpoint, MessageSessionEvent, MessageSink, MessageSource, MessageStats, RuntimeTask,
+};
+
+pub(crate) struct ServerMessages<SourceFactoryType, SinkFactoryType>
+where
+    SourceFactoryType: Factory<Type: MessageSource>,
+    SinkFactoryType: Factory<Type: MessageSink>,
+{
+    name: String,
+    max_message_size: usize,
+    buffer: Vec<u8>,
+
+    session_receiver: FlumeReceiver<MessageSessionEvent>,
+    sessions: Arena<MessageSession<SourceFactoryType::Type, SinkFactoryType::Type>>,
+    session_id_to_session_map: FnvHashMap<u64, Index>,
+    source_factory: SourceFactoryType,
+    sink_factory: SinkFactoryType,
+
+    stats: Arc<Mutex<FnvHashMap<u64, MessageStats>>>,
+}
+
+struct MessageSession<SourceType, SinkType>
+where
+    SourceType: MessageSource,
+    SinkType: MessageSink,
+{
+    session_id: u64,
+    endpoint: MessageEndpoint,
+    source: SourceType,
+    sink: SinkType,
+    stats: MessageStats,
+}
+
+impl<SourceFactoryType, SinkFactoryType> ServerMessages<SourceFactoryType, SinkFactoryType>
+where
+    SourceFactoryType: Factory<Type: MessageSource>,
+    SinkFactoryType: Factory<Type: MessageSink>,
+{
+    pub(crate) fn new(
+        name: String,
+        max_message_size: usize,
+        session_capacity: usize,
+        session_receiver: FlumeReceiver<MessageSessionEvent>,
+        source_factory: SourceFactoryType,
+        sink_factory: SinkFactoryType,
+        stats: Arc<Mutex<FnvHashMap<u64, MessageStats>>>,
+    ) -> Self
+    {
+        Self {
+            name,
+            max_message_size,
+            buffer: Vec::with_capacity(max_message_size),
+
+            session_receiver,
+            sessions: Arena::with_capacity(session_capacity),
+            session_id_to_session_map: FnvHashMap::with_capacity_and_hasher(session_capacity, Default::default()),
+            source_factory,
+            sink_factory,
+
+            stats,
+        }
+    }
+}
+
+impl<SourceFactoryType, SinkFactoryType> RuntimeTask for ServerMessages<SourceFactoryType, SinkFactoryType>
+where
+    SourceFactoryType: Factory<Type: MessageSource>,
+    SinkFactoryType: Factory<Type: MessageSink>,
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn poll(&mut self, _now: Instant)
+    {
+        // Handle Session changes.
+        for event in self.session_receiver.try_iter()
+        {
+            match event
+            {
+                MessageSessionEvent::Connected { session_id, endpoint } =>
+                {
+                    let index = self.sessions.insert(MessageSession {
+                        session_id,
+                        endpoint,
+                        source: self.source_factory.invoke(session_id),
+                        sink: self.sink_factory.invoke(session_id),
+                        stats: MessageStats::default(),
+                    });
+                    self.session_id_to_session_map
+                        .try_insert(session_id, index)
+                        .expect("Duplicate Session ID");
+                }
+                MessageSessionEvent::Disconnected { session_id } =>
+                {
+                    let index = self
+                        .session_id_to_session_map
+                        .remove(&session_id)
+                        .expect("Unknown Session ID");
+                    self.sessions.remove(index);
+                    self.stats.lock().unwrap().remove(&session_id);
+                }
+            }
+        }
+
+        // Poll Sessions.  Messages over the maximum would only get the stream torn down on the
+        // other end, so they're dropped here instead.
+        for (_, session) in self.sessions.iter_mut()
+        {
+            loop
+            {
+                self.buffer.clear();
+                if !session.source.poll(&mut self.buffer)
+                {
+                    break;
+                }
+                if self.buffer.len() > self.max_message_size
+                {
+                    session.stats.messages_oversized += 1;
+                    continue;
+                }
+                session.endpoint.send(&self.buffer, &mut session.stats);
+            }
+
+            for message in session.endpoint.incoming.try_iter()
+            {
+                session.sink.handle(&message);
+                session.stats.messages_received += 1;
+            }
+
+            session.endpoint.poll_closed(&mut session.stats);
+        }
+
+        // Publish stats.
+        let mut stats = self.stats.lock().unwrap();
+        for (_, session) in self.sessions.iter()
+        {
+            stats.insert(session.session_id, session.stats);
+        }
+    }
+}
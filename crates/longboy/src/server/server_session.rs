@@ -1,13 +1,26 @@
-use anyhow::Result;
+use std::{future::Future, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use fnv::FnvHashMap;
 use quinn::Connection;
-use tokio::io::AsyncWriteExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    runtime::Handle,
+    sync::Mutex,
+    task::AbortHandle,
+};
+
+use crate::{accept_message_streams, MessageRoute};
 
 pub struct ServerSession
 {
-    #[allow(unused)]
     connection: Connection,
     session_id: u64,
     cipher_key: u64,
+    epoch: u8,
+    runtime: Handle,
+    announcements: Arc<Mutex<()>>,
+    message_streams: Option<AbortHandle>,
 }
 
 impl ServerSession
@@ -24,6 +37,10 @@ impl ServerSession
             connection,
             session_id,
             cipher_key,
+            epoch: 0,
+            runtime: Handle::current(),
+            announcements: Arc::new(Mutex::new(())),
+            message_streams: None,
         })
     }
 
@@ -36,4 +53,66 @@ impl ServerSession
     {
         self.cipher_key
     }
+
+    pub(crate) fn path_mtu(&self) -> usize
+    {
+        // QUIC discovers the path MTU as the Session goes on, and our datagrams take the same path.
+        self.connection.stats().path.current_mtu as usize
+    }
+
+    pub(crate) fn epoch(&self) -> u8
+    {
+        self.epoch
+    }
+
+    pub(crate) fn serve_messages(&mut self, routes: FnvHashMap<u8, MessageRoute>)
+    {
+        self.message_streams = Some(accept_message_streams(&self.runtime, self.connection.clone(), routes));
+    }
+
+    pub(crate) fn announce_key<AcknowledgedType>(
+        &mut self,
+        epoch: u8,
+        cipher_key: u64,
+        acknowledged: AcknowledgedType,
+    ) -> impl Future<Output = Result<()>> + 'static
+    where
+        AcknowledgedType: 'static + Send + FnOnce(),
+    {
+        self.cipher_key = cipher_key;
+        self.epoch = epoch;
+
+        // Announcements go out on the Session's runtime one at a time, so they're acknowledged
+        // in the order they were made.  The Client replies with the epoch once its tasks have
+        // been handed the key.
+        let connection = self.connection.clone();
+        let announcements = self.announcements.clone();
+        let announcement = self.runtime.spawn(async move {
+            let _announcements = announcements.lock().await;
+            let (mut send, mut recv) = connection.open_bi().await?;
+            send.write_u8(epoch).await?;
+            send.write_u64_le(cipher_key).await?;
+            send.finish()?;
+            if recv.read_u8().await? != epoch
+            {
+                return Err(anyhow!("Key for epoch {} acknowledged out of turn", epoch));
+            }
+
+            acknowledged();
+            Ok(())
+        });
+        async move { announcement.await? }
+    }
+}
+
+impl Drop for ServerSession
+{
+    fn drop(&mut self)
+    {
+        // Streams already accepted carry on until the tasks using them let go.
+        if let Some(message_streams) = self.message_streams.take()
+        {
+            message_streams.abort();
+        }
+    }
 }
@@ -4,6 +4,18 @@ pub(crate) enum ServerSessionEvent
     {
         session_id: u64, cipher_key: u64
     },
+    Announced
+    {
+        session_id: u64,
+        epoch: u8,
+        cipher_key: u64,
+    },
+    Rekeyed
+    {
+        session_id: u64,
+        epoch: u8,
+        cipher_key: u64,
+    },
     Disconnected
     {
         session_id: u64
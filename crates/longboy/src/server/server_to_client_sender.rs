@@ -1,4 +1,7 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
 use enum_map::EnumMap;
@@ -6,66 +9,99 @@ use flume::Receiver as FlumeReceiver;
 use fnv::FnvHashMap;
 use thunderdome::{Arena, Index};
 
-use crate::{Constants, Factory, Mirroring, RuntimeTask, Sender, ServerSessionEvent, Source, UdpSocketExt};
+use crate::{
+    derive_channel_key, supports_version, ChannelSocket, Cipher, Direction, DynSender, DynSource, Encoding, Factory,
+    Instant, Layout, MapperStats, Mirroring, Redundancy, RuntimeTask, ServerSessionEvent, UdpSocketExt,
+    ACKNOWLEDGEMENT_SIZE,
+};
 
-pub(crate) struct ServerToClientSender<SourceFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>
+pub(crate) struct ServerToClientSender<SourceFactoryType, CipherType>
 where
-    SourceFactoryType: Factory<Type: Source<SIZE>>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    SourceFactoryType: Factory<Type: DynSource>,
+    CipherType: Cipher,
 {
     name: String,
+    schema_name: &'static str,
+    layout: Layout,
+    channel_id: u8,
 
-    mapper_socket: UdpSocket,
+    mapper_socket: ChannelSocket,
+    mapper_stats: MapperStats,
+    shared_mapper_stats: Arc<Mutex<MapperStats>>,
 
-    sockets: EnumMap<Mirroring, UdpSocket>,
+    sockets: EnumMap<Mirroring, ChannelSocket>,
+    encoding: Encoding,
+    keepalive_period: Option<u16>,
+    redundancy: Redundancy,
+    max_datagram_size: usize,
+    ack_period: Option<u16>,
 
     session_receiver: FlumeReceiver<ServerSessionEvent>,
-    sessions: Arena<SenderSession<SourceFactoryType::Type, SIZE, WINDOW_SIZE>>,
+    sessions: Arena<SenderSession<SourceFactoryType::Type, CipherType>>,
     session_id_to_session_map: FnvHashMap<u64, Index>,
     source_factory: SourceFactoryType,
 }
 
-struct SenderSession<SourceType, const SIZE: usize, const WINDOW_SIZE: usize>
+struct SenderSession<SourceType, CipherType>
 where
-    SourceType: Source<SIZE>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    SourceType: DynSource,
+    CipherType: Cipher,
 {
     socket_addr: Option<SocketAddr>,
-    sender: Sender<SourceType, SIZE, WINDOW_SIZE>,
+    sender: DynSender<SourceType, CipherType>,
 }
 
-impl<SourceFactoryType, const SIZE: usize, const WINDOW_SIZE: usize>
-    ServerToClientSender<SourceFactoryType, SIZE, WINDOW_SIZE>
+impl<SourceFactoryType, CipherType> ServerToClientSender<SourceFactoryType, CipherType>
 where
-    SourceFactoryType: Factory<Type: Source<SIZE>>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    SourceFactoryType: Factory<Type: DynSource>,
+    CipherType: Cipher,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         name: String,
-        mapper_socket: UdpSocket,
-        sockets: EnumMap<Mirroring, UdpSocket>,
+        schema_name: &'static str,
+        layout: Layout,
+        channel_id: u8,
+        mapper_socket: ChannelSocket,
+        sockets: EnumMap<Mirroring, ChannelSocket>,
+        encoding: Encoding,
+        keepalive_period: Option<u16>,
+        redundancy: Redundancy,
+        max_datagram_size: usize,
+        ack_period: Option<u16>,
         session_capacity: usize,
         session_receiver: FlumeReceiver<ServerSessionEvent>,
         source_factory: SourceFactoryType,
+        mapper_stats: Arc<Mutex<MapperStats>>,
     ) -> Result<Self>
     {
-        mapper_socket.set_nonblocking(true)?;
+        mapper_socket.socket().set_nonblocking(true)?;
 
-        sockets[Mirroring::AudioVideo].set_nonblocking(true)?;
-        sockets[Mirroring::AudioVideo].set_qos_audio_video()?;
+        sockets[Mirroring::AudioVideo].socket().set_nonblocking(true)?;
+        sockets[Mirroring::AudioVideo].socket().set_qos_audio_video()?;
 
-        sockets[Mirroring::Background].set_nonblocking(true)?;
-        sockets[Mirroring::Background].set_qos_background()?;
+        sockets[Mirroring::Background].socket().set_nonblocking(true)?;
+        sockets[Mirroring::Background].socket().set_qos_background()?;
 
-        sockets[Mirroring::Voice].set_nonblocking(true)?;
-        sockets[Mirroring::Voice].set_qos_voice()?;
+        sockets[Mirroring::Voice].socket().set_nonblocking(true)?;
+        sockets[Mirroring::Voice].socket().set_qos_voice()?;
 
         Ok(Self {
             name,
+            schema_name,
+            layout,
+            channel_id,
 
             mapper_socket,
+            mapper_stats: MapperStats::default(),
+            shared_mapper_stats: mapper_stats,
 
             sockets,
+            encoding,
+            keepalive_period,
+            redundancy,
+            max_datagram_size,
+            ack_period,
 
             session_receiver,
             sessions: Arena::with_capacity(session_capacity),
@@ -75,19 +111,20 @@ where
     }
 }
 
-impl<SourceFactoryType, const SIZE: usize, const WINDOW_SIZE: usize> RuntimeTask
-    for ServerToClientSender<SourceFactoryType, SIZE, WINDOW_SIZE>
+impl<SourceFactoryType, CipherType> RuntimeTask for ServerToClientSender<SourceFactoryType, CipherType>
 where
-    SourceFactoryType: Factory<Type: Source<SIZE>>,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
+    SourceFactoryType: Factory<Type: DynSource>,
+    CipherType: Cipher,
 {
     fn name(&self) -> &str
     {
         &self.name
     }
 
-    fn poll(&mut self, timestamp: u16)
+    fn poll(&mut self, now: Instant)
     {
+        let timestamp = now.timestamp();
+
         // Handle Session changes.
         for event in self.session_receiver.try_iter()
         {
@@ -95,14 +132,56 @@ where
             {
                 ServerSessionEvent::Connected { session_id, cipher_key } =>
                 {
+                    let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient);
+                    let sender = DynSender::new(
+                        cipher_key,
+                        self.layout.size,
+                        self.layout.window_size,
+                        self.source_factory.invoke(session_id),
+                    )
+                    .with_channel(self.channel_id)
+                    .with_encoding(self.encoding)
+                    .with_redundancy(self.redundancy)
+                    .with_max_datagram_size(self.max_datagram_size);
                     let index = self.sessions.insert(SenderSession {
                         socket_addr: None,
-                        sender: Sender::new(cipher_key, self.source_factory.invoke(session_id)),
+                        sender: match self.keepalive_period
+                        {
+                            Some(keepalive_period) => sender.with_keepalive(keepalive_period),
+                            None => sender,
+                        },
                     });
                     self.session_id_to_session_map
                         .try_insert(session_id, index)
                         .expect("Duplicate Session ID");
                 }
+                ServerSessionEvent::Announced {
+                    session_id,
+                    epoch,
+                    cipher_key,
+                } =>
+                {
+                    let index = self
+                        .session_id_to_session_map
+                        .get(&session_id)
+                        .expect("Unknown Session ID");
+                    let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient);
+                    self.sessions[*index].sender.announce_key(epoch, cipher_key);
+                }
+                ServerSessionEvent::Rekeyed {
+                    session_id,
+                    epoch,
+                    cipher_key,
+                } =>
+                {
+                    // Keys are only handed over once the Client acknowledges them, which can be
+                    // after the Session has been unregistered.
+                    if let Some(index) = self.session_id_to_session_map.get(&session_id)
+                    {
+                        let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient);
+                        self.sessions[*index].sender.rekey(epoch, cipher_key);
+                    }
+                }
                 ServerSessionEvent::Disconnected { session_id } =>
                 {
                     let index = self
@@ -114,32 +193,71 @@ where
             }
         }
 
-        // Update Client socket addresses.
+        // Update Client socket addresses and acknowledgements.  Heartbeats lead with their
+        // version, anything past it is only known for versions we support.
         let mut buffer = [0; 64];
         while let Ok((len, socket_addr)) = self.mapper_socket.recv_from(&mut buffer)
         {
-            if len != std::mem::size_of::<u64>()
+            if len > 0 && !supports_version(buffer[0])
             {
+                self.mapper_stats.heartbeats_unsupported_version += 1;
+                continue;
+            }
+            if len != ACKNOWLEDGEMENT_SIZE
+            {
+                self.mapper_stats.heartbeats_malformed += 1;
+                continue;
+            }
+            if buffer[1] != self.channel_id
+            {
+                self.mapper_stats.heartbeats_unknown_channel += 1;
                 continue;
             }
 
-            let session_id = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[0..8]).unwrap());
-
+            // Heartbeats have to check out under the Session's key before they're trusted with
+            // where it is or what it's received.
+            let session_id = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[2..10]).unwrap());
             if let Some(index) = self.session_id_to_session_map.get(&session_id)
             {
-                self.sessions[*index].socket_addr = Some(socket_addr);
+                let session = &mut self.sessions[*index];
+                let Some(acknowledgement) = session.sender.check_acknowledgement(session_id, &buffer[0..len])
+                else
+                {
+                    self.mapper_stats.heartbeats_check_failures += 1;
+                    continue;
+                };
+                session.socket_addr = Some(socket_addr);
+                if self.ack_period.is_some()
+                {
+                    session.sender.acknowledge(acknowledgement);
+                }
+                self.mapper_stats.heartbeats_accepted += 1;
             }
         }
+        *self.shared_mapper_stats.lock().unwrap() = self.mapper_stats;
 
-        // Poll Sessions
+        // Poll Sessions, sealing a copy of each datagram and any parity following it for each
+        // lane.
         for (_, session) in self.sessions.iter_mut()
         {
-            if let Some(datagram) = session.sender.poll_datagram(timestamp)
+            if session.sender.poll_datagram(timestamp).is_some()
+                && let Some(socket_addr) = session.socket_addr
+            {
+                for (mirroring, socket) in self.sockets.iter()
+                {
+                    socket
+                        .send_to(session.sender.mirror_datagram(mirroring), socket_addr)
+                        .expect("send_to failure");
+                }
+            }
+            if session.sender.poll_parity(timestamp).is_some()
                 && let Some(socket_addr) = session.socket_addr
             {
-                for socket in self.sockets.values()
+                for (mirroring, socket) in self.sockets.iter()
                 {
-                    socket.send_to(datagram, socket_addr).expect("send_to failure");
+                    socket
+                        .send_to(session.sender.mirror_datagram(mirroring), socket_addr)
+                        .expect("send_to failure");
                 }
             }
         }
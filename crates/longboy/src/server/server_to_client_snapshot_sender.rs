@@ -0,0 +1,286 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use enum_map::EnumMap;
+use flume::Receiver as FlumeReceiver;
+use fnv::FnvHashMap;
+use thunderdome::{Arena, Index};
+
+use crate::{
+    derive_channel_key, supports_version, ChannelSocket, Cipher, Direction, Factory, Instant, MapperStats, Mirroring,
+    RuntimeTask, ServerSessionEvent, SnapshotSender, SnapshotSenderStats, SnapshotSource, UdpSocketExt,
+    ACKNOWLEDGEMENT_SIZE,
+};
+
+pub(crate) struct ServerToClientSnapshotSender<SourceFactoryType, CipherType>
+where
+    SourceFactoryType: Factory<Type: SnapshotSource>,
+    CipherType: Cipher,
+{
+    name: String,
+    schema_name: &'static str,
+    max_snapshot_size: usize,
+    baselines: usize,
+    channel_id: u8,
+
+    mapper_socket: ChannelSocket,
+    mapper_stats: MapperStats,
+    shared_mapper_stats: Arc<Mutex<MapperStats>>,
+
+    sockets: EnumMap<Mirroring, ChannelSocket>,
+    max_datagram_size: usize,
+    max_fragments_per_poll: usize,
+
+    session_receiver: FlumeReceiver<ServerSessionEvent>,
+    sessions: Arena<SenderSession<SourceFactoryType::Type, CipherType>>,
+    session_id_to_session_map: FnvHashMap<u64, Index>,
+    source_factory: SourceFactoryType,
+    stats: Arc<Mutex<FnvHashMap<u64, SnapshotSenderStats>>>,
+}
+
+struct SenderSession<SourceType, CipherType>
+where
+    SourceType: SnapshotSource,
+    CipherType: Cipher,
+{
+    socket_addr: Option<SocketAddr>,
+    sender: SnapshotSender<SourceType, CipherType>,
+    lanes_sent: Option<usize>,
+}
+
+impl<SourceFactoryType, CipherType> ServerToClientSnapshotSender<SourceFactoryType, CipherType>
+where
+    SourceFactoryType: Factory<Type: SnapshotSource>,
+    CipherType: Cipher,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        name: String,
+        schema_name: &'static str,
+        max_snapshot_size: usize,
+        baselines: usize,
+        channel_id: u8,
+        mapper_socket: ChannelSocket,
+        sockets: EnumMap<Mirroring, ChannelSocket>,
+        max_datagram_size: usize,
+        max_fragments_per_poll: usize,
+        session_capacity: usize,
+        session_receiver: FlumeReceiver<ServerSessionEvent>,
+        source_factory: SourceFactoryType,
+        mapper_stats: Arc<Mutex<MapperStats>>,
+        stats: Arc<Mutex<FnvHashMap<u64, SnapshotSenderStats>>>,
+    ) -> Result<Self>
+    {
+        mapper_socket.socket().set_nonblocking(true)?;
+
+        sockets[Mirroring::AudioVideo].socket().set_nonblocking(true)?;
+        sockets[Mirroring::AudioVideo].socket().set_qos_audio_video()?;
+
+        sockets[Mirroring::Background].socket().set_nonblocking(true)?;
+        sockets[Mirroring::Background].socket().set_qos_background()?;
+
+        sockets[Mirroring::Voice].socket().set_nonblocking(true)?;
+        sockets[Mirroring::Voice].socket().set_qos_voice()?;
+
+        Ok(Self {
+            name,
+            schema_name,
+            max_snapshot_size,
+            baselines,
+            channel_id,
+
+            mapper_socket,
+            mapper_stats: MapperStats::default(),
+            shared_mapper_stats: mapper_stats,
+
+            sockets,
+            max_datagram_size,
+            max_fragments_per_poll,
+
+            session_receiver,
+            sessions: Arena::with_capacity(session_capacity),
+            session_id_to_session_map: FnvHashMap::with_capacity_and_hasher(session_capacity, Default::default()),
+            source_factory,
+            stats,
+        })
+    }
+}
+
+impl<SourceFactoryType, CipherType> RuntimeTask for ServerToClientSnapshotSender<SourceFactoryType, CipherType>
+where
+    SourceFactoryType: Factory<Type: SnapshotSource>,
+    CipherType: Cipher,
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn poll(&mut self, now: Instant)
+    {
+        let timestamp = now.timestamp();
+
+        // Handle Session changes.
+        for event in self.session_receiver.try_iter()
+        {
+            match event
+            {
+                ServerSessionEvent::Connected { session_id, cipher_key } =>
+                {
+                    let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient);
+                    let sender = SnapshotSender::new(
+                        cipher_key,
+                        self.max_snapshot_size,
+                        self.source_factory.invoke(session_id),
+                    )
+                    .with_channel(self.channel_id)
+                    .with_max_datagram_size(self.max_datagram_size)
+                    .with_baselines(self.baselines);
+                    let index = self.sessions.insert(SenderSession {
+                        socket_addr: None,
+                        sender,
+                        lanes_sent: None,
+                    });
+                    self.session_id_to_session_map
+                        .try_insert(session_id, index)
+                        .expect("Duplicate Session ID");
+                }
+                ServerSessionEvent::Announced {
+                    session_id,
+                    epoch,
+                    cipher_key,
+                } =>
+                {
+                    let index = self
+                        .session_id_to_session_map
+                        .get(&session_id)
+                        .expect("Unknown Session ID");
+                    let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient);
+                    self.sessions[*index].sender.announce_key(epoch, cipher_key);
+                }
+                ServerSessionEvent::Rekeyed {
+                    session_id,
+                    epoch,
+                    cipher_key,
+                } =>
+                {
+                    // Keys are only handed over once the Client acknowledges them, which can be
+                    // after the Session has been unregistered.
+                    if let Some(index) = self.session_id_to_session_map.get(&session_id)
+                    {
+                        let cipher_key = derive_channel_key(cipher_key, self.schema_name, Direction::ServerToClient);
+                        self.sessions[*index].sender.rekey(epoch, cipher_key);
+                    }
+                }
+                ServerSessionEvent::Disconnected { session_id } =>
+                {
+                    let index = self
+                        .session_id_to_session_map
+                        .remove(&session_id)
+                        .expect("Unknown Session ID");
+                    self.sessions.remove(index);
+                    self.stats.lock().unwrap().remove(&session_id);
+                }
+            }
+        }
+
+        // Update Client socket addresses and acknowledgements.  Heartbeats lead with their
+        // version, anything past it is only known for versions we support.
+        let mut buffer = [0; 64];
+        while let Ok((len, socket_addr)) = self.mapper_socket.recv_from(&mut buffer)
+        {
+            if len > 0 && !supports_version(buffer[0])
+            {
+                self.mapper_stats.heartbeats_unsupported_version += 1;
+                continue;
+            }
+            if len != ACKNOWLEDGEMENT_SIZE
+            {
+                self.mapper_stats.heartbeats_malformed += 1;
+                continue;
+            }
+            if buffer[1] != self.channel_id
+            {
+                self.mapper_stats.heartbeats_unknown_channel += 1;
+                continue;
+            }
+
+            // Heartbeats have to check out under the Session's key before they're trusted with
+            // where it is or which snapshots it has.
+            let session_id = u64::from_le_bytes(*<&[u8; 8]>::try_from(&buffer[2..10]).unwrap());
+            if let Some(index) = self.session_id_to_session_map.get(&session_id)
+            {
+                let session = &mut self.sessions[*index];
+                let Some(acknowledgement) = session.sender.check_acknowledgement(session_id, &buffer[0..len])
+                else
+                {
+                    self.mapper_stats.heartbeats_check_failures += 1;
+                    continue;
+                };
+                session.socket_addr = Some(socket_addr);
+                session.sender.acknowledge(acknowledgement);
+                self.mapper_stats.heartbeats_accepted += 1;
+            }
+        }
+
+        // Poll Sessions, sealing a copy of every fragment of each new snapshot for each lane.  Only
+        // so many fragments go out per poll, and a full socket holds back the rest, so a snapshot
+        // can take a few polls to send, picking up where it left off, while newer ones wait their
+        // turn.  Snapshots polled before the Client is known are dropped, the next one supersedes
+        // them anyway.
+        for (_, session) in self.sessions.iter_mut()
+        {
+            if session.lanes_sent.is_none()
+                && session.sender.fragments_remaining() == 0
+                && !session.sender.poll_snapshot()
+            {
+                continue;
+            }
+            let Some(socket_addr) = session.socket_addr
+            else
+            {
+                while session.sender.poll_datagram(timestamp).is_some()
+                {}
+                continue;
+            };
+
+            let mut fragments = 0;
+            'fragments: while fragments < self.max_fragments_per_poll
+            {
+                let lanes_sent = match session.lanes_sent.take()
+                {
+                    Some(lanes_sent) => lanes_sent,
+                    None if session.sender.poll_datagram(timestamp).is_some() => 0,
+                    None => break,
+                };
+                for (lane, (mirroring, socket)) in self.sockets.iter().enumerate().skip(lanes_sent)
+                {
+                    match socket.send_to(session.sender.mirror_datagram(mirroring), socket_addr)
+                    {
+                        Err(error) if error.kind() == std::io::ErrorKind::WouldBlock =>
+                        {
+                            session.lanes_sent = Some(lane);
+                            break 'fragments;
+                        }
+                        result =>
+                        {
+                            result.expect("send_to failure");
+                        }
+                    }
+                }
+                fragments += 1;
+            }
+        }
+
+        // Publish stats.
+        *self.shared_mapper_stats.lock().unwrap() = self.mapper_stats;
+        let mut stats = self.stats.lock().unwrap();
+        for (session_id, index) in self.session_id_to_session_map.iter()
+        {
+            stats.insert(*session_id, self.sessions[*index].sender.stats());
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use longboy::{ChaCha20Poly1305Cipher, Cipher};
+
+#[test]
+fn chacha20_poly1305_nonces()
+{
+    let cipher = ChaCha20Poly1305Cipher::new(0xDEADBEEFDEADBEEF);
+    let seal = |header: &[u8]| {
+        let mut payload = [0xAB; 32];
+        let mut tag = [0; 16];
+        cipher.seal(1, header, &mut payload, &mut tag);
+        (payload, tag)
+    };
+
+    // Datagrams for different channels or lanes never share a keystream under the same key and
+    // sequence.
+    let header = [0; 11];
+    let mut channel_header = header;
+    channel_header[1] = 1;
+    let mut lane_header = header;
+    lane_header[8] = 2;
+    let (payload, tag) = seal(&header);
+    assert_ne!(payload, seal(&channel_header).0);
+    assert_ne!(payload, seal(&lane_header).0);
+
+    // And each only opens under the header it was sealed with.
+    let mut opened = payload;
+    assert!(cipher.open(1, &header, &mut opened, &tag));
+    assert_eq!(opened, [0xAB; 32]);
+    let mut opened = payload;
+    assert!(!cipher.open(1, &channel_header, &mut opened, &tag));
+}
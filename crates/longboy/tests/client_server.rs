@@ -1,6 +1,7 @@
 use std::{
     net::{SocketAddr, UdpSocket},
     sync::Arc,
+    time::Duration,
 };
 
 use enum_map::enum_map;
@@ -8,8 +9,10 @@ use flume::{Receiver as FlumeReceiver, Sender as FlumeSender};
 use parking_lot::Mutex;
 
 use longboy::{
-    Client, ClientSession, ClientToServerSchema, Factory, Mirroring, Runtime, RuntimeTask, Server, ServerSession,
-    ServerToClientSchema, Sink, Source,
+    ChaCha20Poly1305Cipher, Client, ClientSession, ClientToServerSchema, Delivery, DynSink, DynSource, Encoding,
+    Factory, Instant, MessageSchema, MessageSink, MessageSource, MessageStats, Mirroring, Rc5Cipher, Redundancy,
+    Runtime, RuntimeTask, Server, ServerSession, ServerToClientSchema, Sink, SinkMetadata, SnapshotSchema,
+    SnapshotSenderStats, SnapshotSink, SnapshotSource, Source, VariableSource, PROTOCOL_VERSION,
 };
 use quinn::{
     rustls::{
@@ -21,6 +24,10 @@ use quinn::{
 use rcgen::CertifiedKey;
 use tokio::join;
 
+const TICK_PERIOD: u16 = 0;
+
+type Channel<T> = (FlumeSender<T>, FlumeReceiver<T>);
+
 #[derive(Clone)]
 struct TestRuntime
 {
@@ -31,7 +38,7 @@ struct TestRuntimeInner
 {
     tick_period: u16,
 
-    timestamp: u16,
+    now: Instant,
     tasks: Vec<Box<dyn RuntimeTask>>,
 }
 
@@ -66,6 +73,75 @@ struct TestServerToClientSink
     channel: FlumeSender<(u32, [u64; 2])>,
 }
 
+struct TestMessageSourceFactory
+{
+    channels: [FlumeReceiver<Vec<u8>>; 2],
+}
+
+struct TestMessageSinkFactory
+{
+    channel: FlumeSender<(u8, Vec<u8>)>,
+}
+
+struct TestSnapshotSourceFactory
+{
+    channels: [FlumeReceiver<Vec<u8>>; 2],
+}
+
+struct TestSnapshotSource
+{
+    channel: FlumeReceiver<Vec<u8>>,
+}
+
+struct TestSnapshotSink
+{
+    channel: FlumeSender<(u64, Vec<u8>)>,
+}
+
+struct TestMessageSource
+{
+    channel: FlumeReceiver<Vec<u8>>,
+}
+
+struct TestMessageSink
+{
+    player_index: u8,
+    channel: FlumeSender<(u8, Vec<u8>)>,
+}
+
+// Two Clients connected to one Server, over every kind of channel there is.
+struct TestHarness
+{
+    server_runtime: TestRuntime,
+    server: Server,
+    client_runtimes: [TestRuntime; 2],
+    client_1: Client,
+    client_2: Client,
+
+    client_to_server_schema: ClientToServerSchema,
+    server_to_client_schema: ServerToClientSchema,
+    chat_schema: ClientToServerSchema,
+    events_schema: ServerToClientSchema,
+    snapshot_schema: SnapshotSchema,
+    message_schema: MessageSchema,
+
+    server_source_channels: [Channel<(u32, [u64; 2])>; 2],
+    server_sink_channel: Channel<(u32, u8, u64)>,
+    events_source_channels: [Channel<(u32, [u64; 2])>; 2],
+    chat_sink_channel: Channel<(u32, u8, u64)>,
+    server_message_source_channels: [Channel<Vec<u8>>; 2],
+    server_message_sink_channel: Channel<(u8, Vec<u8>)>,
+    snapshot_source_channels: [Channel<Vec<u8>>; 2],
+
+    client_source_channels: [Channel<(u32, u64)>; 2],
+    client_sink_channels: [Channel<(u32, [u64; 2])>; 2],
+    chat_source_channel: Channel<(u32, u64)>,
+    events_sink_channel: Channel<(u32, [u64; 2])>,
+    client_message_source_channels: [Channel<Vec<u8>>; 2],
+    client_message_sink_channels: [Channel<(u8, Vec<u8>)>; 2],
+    snapshot_sink_channel: Channel<(u64, Vec<u8>)>,
+}
+
 impl TestRuntime
 {
     fn new(tick_period: u16) -> Self
@@ -74,19 +150,25 @@ impl TestRuntime
             inner: Arc::new(Mutex::new(TestRuntimeInner {
                 tick_period,
 
-                timestamp: 0,
+                now: Instant::default(),
                 tasks: Vec::new(),
             })),
         }
     }
 
     fn tick(&self)
+    {
+        let tick_period = self.inner.lock().tick_period;
+        self.advance(tick_period as u64);
+    }
+
+    fn advance(&self, millis: u64)
     {
         let mut inner = self.inner.lock();
-        inner.timestamp += inner.tick_period;
+        inner.now += millis;
 
-        let timestamp = inner.timestamp;
-        inner.tasks.iter_mut().for_each(|task| task.poll(timestamp));
+        let now = inner.now;
+        inner.tasks.iter_mut().for_each(|task| task.poll(now));
     }
 }
 
@@ -142,6 +224,92 @@ impl Factory for TestClientToServerSinkFactory
     }
 }
 
+impl Factory for TestMessageSourceFactory
+{
+    type Type = TestMessageSource;
+
+    fn invoke(&mut self, session_id: u64) -> Self::Type
+    {
+        TestMessageSource {
+            channel: self.channels[(session_id - 1) as usize].clone(),
+        }
+    }
+}
+
+impl Factory for TestMessageSinkFactory
+{
+    type Type = TestMessageSink;
+
+    fn invoke(&mut self, session_id: u64) -> Self::Type
+    {
+        TestMessageSink {
+            player_index: (session_id - 1) as u8,
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl Factory for TestSnapshotSourceFactory
+{
+    type Type = TestSnapshotSource;
+
+    fn invoke(&mut self, session_id: u64) -> Self::Type
+    {
+        TestSnapshotSource {
+            channel: self.channels[(session_id - 1) as usize].clone(),
+        }
+    }
+}
+
+impl SnapshotSource for TestSnapshotSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        // Only the latest snapshot is worth sending.
+        match self.channel.try_iter().last()
+        {
+            Some(snapshot) =>
+            {
+                buffer.extend_from_slice(&snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl SnapshotSink for TestSnapshotSink
+{
+    fn handle(&mut self, sequence: u64, snapshot: &[u8])
+    {
+        self.channel.send((sequence, snapshot.to_vec())).unwrap();
+    }
+}
+
+impl MessageSource for TestMessageSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        match self.channel.try_recv()
+        {
+            Ok(message) =>
+            {
+                buffer.extend_from_slice(&message);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl MessageSink for TestMessageSink
+{
+    fn handle(&mut self, message: &[u8])
+    {
+        self.channel.send((self.player_index, message.to_vec())).unwrap();
+    }
+}
+
 impl Source<16> for TestClientToServerSource
 {
     fn poll(&mut self, buffer: &mut [u8; 16]) -> bool
@@ -159,6 +327,14 @@ impl Source<16> for TestClientToServerSource
     }
 }
 
+impl DynSource for TestClientToServerSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        VariableSource::<16>::poll(self, buffer)
+    }
+}
+
 impl Sink<16> for TestClientToServerSink
 {
     fn handle(&mut self, buffer: &[u8; 16])
@@ -187,6 +363,14 @@ impl Source<32> for TestServerToClientSource
     }
 }
 
+impl DynSource for TestServerToClientSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        VariableSource::<32>::poll(self, buffer)
+    }
+}
+
 impl Sink<32> for TestServerToClientSink
 {
     fn handle(&mut self, buffer: &[u8; 32])
@@ -198,6 +382,14 @@ impl Sink<32> for TestServerToClientSink
     }
 }
 
+impl DynSink for TestServerToClientSink
+{
+    fn handle(&mut self, _metadata: &SinkMetadata, buffer: &[u8])
+    {
+        Sink::<32>::handle(self, <&[u8; 32]>::try_from(buffer).unwrap());
+    }
+}
+
 async fn connect(
     server_endpoint: &Endpoint,
     client_endpoint: &Endpoint,
@@ -223,13 +415,10 @@ async fn connect(
     )
 }
 
-#[tokio::test]
-async fn golden()
+// A Session of its own, for when the harness' Server and Clients are already set up.
+async fn sessions(session_id: u64, cipher_key: u64) -> (ServerSession, ClientSession)
 {
-    const TICK_PERIOD: u16 = 0;
-
     let certified_key = rcgen::generate_simple_self_signed([String::from("localhost")]).unwrap();
-
     let server_endpoint = Endpoint::server(
         ServerConfig::with_single_cert(
             Vec::from([certified_key.cert.der().clone()]),
@@ -239,52 +428,547 @@ async fn golden()
         SocketAddr::from(([127, 0, 0, 1], 0)),
     )
     .unwrap();
-    let client_endpoint_1 = Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
-    let client_endpoint_2 = Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let client_endpoint = Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let connections = connect(&server_endpoint, &client_endpoint, &certified_key).await;
+
+    (
+        ServerSession::new(session_id, cipher_key, connections.0).await.unwrap(),
+        ClientSession::new(connections.1).await.unwrap(),
+    )
+}
 
-    let connections_1 = connect(&server_endpoint, &client_endpoint_1, &certified_key).await;
-    let connections_2 = connect(&server_endpoint, &client_endpoint_2, &certified_key).await;
+impl TestHarness
+{
+    async fn new() -> Self
+    {
+        let certified_key = rcgen::generate_simple_self_signed([String::from("localhost")]).unwrap();
 
-    let server_session_1 = ServerSession::new(1, 0xDEADBEEFDEADBEEF, connections_1.0)
-        .await
-        .unwrap();
-    let server_session_2 = ServerSession::new(2, 0xBEEFDEADBEEFDEAD, connections_2.0)
-        .await
+        let server_endpoint = Endpoint::server(
+            ServerConfig::with_single_cert(
+                Vec::from([certified_key.cert.der().clone()]),
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified_key.key_pair.serialize_der())),
+            )
+            .unwrap(),
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+        )
         .unwrap();
-    let client_session_1 = ClientSession::new(connections_1.1).await.unwrap();
-    let client_session_2 = ClientSession::new(connections_2.1).await.unwrap();
+        let client_endpoint_1 = Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let client_endpoint_2 = Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
 
-    let client_to_server_mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
-    let client_to_server_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+        let connections_1 = connect(&server_endpoint, &client_endpoint_1, &certified_key).await;
+        let connections_2 = connect(&server_endpoint, &client_endpoint_2, &certified_key).await;
 
-    let server_to_client_mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+        let server_session_1 = ServerSession::new(1, 0xDEADBEEFDEADBEEF, connections_1.0)
+            .await
+            .unwrap();
+        let server_session_2 = ServerSession::new(2, 0xBEEFDEADBEEFDEAD, connections_2.0)
+            .await
+            .unwrap();
+        let client_session_1 = ClientSession::new(connections_1.1).await.unwrap();
+        let client_session_2 = ClientSession::new(connections_2.1).await.unwrap();
 
-    let client_to_server_schema = ClientToServerSchema {
-        name: "Input",
+        let client_to_server_mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+        let client_to_server_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
 
-        mapper_port: client_to_server_mapper_socket.local_addr().unwrap().port(),
-        heartbeat_period: 2000,
+        let server_to_client_mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
 
-        port: client_to_server_socket.local_addr().unwrap().port(),
-    };
+        let client_to_server_schema = ClientToServerSchema {
+            name: "Input",
+            channel_id: 0,
 
-    let server_to_client_schema = ServerToClientSchema {
-        name: "State",
+            mapper_port: client_to_server_mapper_socket.local_addr().unwrap().port(),
+            heartbeat_period: 2000,
 
-        mapper_port: server_to_client_mapper_socket.local_addr().unwrap().port(),
-        heartbeat_period: 2000,
-    };
+            port: client_to_server_socket.local_addr().unwrap().port(),
 
-    let server_runtime = TestRuntime::new(TICK_PERIOD);
-    let server_source_channels = [flume::unbounded(), flume::unbounded()];
-    let server_sink_channel = flume::unbounded();
+            encoding: Encoding::Delta,
+            keepalive_period: Some(1000),
+            redundancy: Redundancy::Parity {
+                group: 3,
+                interleave: 2,
+            },
+            max_datagram_size: 1200,
+            delivery: Delivery::Ordered { deadline: 100 },
+            key_grace_period: 500,
+            ack_period: Some(100),
+        };
+
+        let server_to_client_schema = ServerToClientSchema {
+            name: "State",
+            channel_id: 1,
+
+            mapper_port: server_to_client_mapper_socket.local_addr().unwrap().port(),
+            heartbeat_period: 2000,
+
+            max_datagram_size: 1200,
+            key_grace_period: 500,
+            ack_period: Some(100),
+            ..Default::default()
+        };
+
+        // Share ports with the schemas above, told apart by channel alone.
+        let chat_schema = ClientToServerSchema {
+            name: "Chat",
+            channel_id: 2,
+
+            mapper_port: client_to_server_schema.mapper_port,
+            heartbeat_period: 2000,
+            port: client_to_server_schema.port,
+
+            max_datagram_size: 1200,
+            key_grace_period: 500,
+            ack_period: Some(100),
+            ..Default::default()
+        };
+
+        let events_schema = ServerToClientSchema {
+            name: "Events",
+            channel_id: 3,
+            ..server_to_client_schema
+        };
+
+        let snapshot_schema = SnapshotSchema {
+            name: "World",
+            channel_id: 5,
+
+            mapper_port: server_to_client_schema.mapper_port,
+            heartbeat_period: 2000,
+
+            max_datagram_size: 1200,
+            baselines: 8,
+            key_grace_period: 500,
+            ack_period: Some(100),
+            max_fragments_per_poll: 64,
+        };
+
+        let message_schema = MessageSchema {
+            name: "Lobby",
+            channel_id: 4,
+
+            max_message_size: 256,
+            max_queued_messages: 256,
+        };
+
+        let server_runtime = TestRuntime::new(TICK_PERIOD);
+        let server_source_channels = [flume::unbounded(), flume::unbounded()];
+        let server_sink_channel = flume::unbounded();
+        let events_source_channels = [flume::unbounded(), flume::unbounded()];
+        let chat_sink_channel = flume::unbounded();
+        let server_message_source_channels = [flume::unbounded(), flume::unbounded()];
+        let server_message_sink_channel = flume::unbounded();
+        let snapshot_source_channels = [flume::unbounded(), flume::unbounded()];
+
+        let client_runtimes = [TestRuntime::new(TICK_PERIOD), TestRuntime::new(TICK_PERIOD)];
+        let client_source_channels = [flume::unbounded(), flume::unbounded()];
+        let client_sink_channels = [flume::unbounded(), flume::unbounded()];
+        let chat_source_channel = flume::unbounded();
+        let events_sink_channel = flume::unbounded();
+        let client_message_source_channels = [flume::unbounded(), flume::unbounded()];
+        let client_message_sink_channels = [flume::unbounded(), flume::unbounded()];
+        let snapshot_sink_channel = flume::unbounded();
+
+        let mut server = Server::builder(2, Box::new(server_runtime.clone()))
+            .sender_with_sockets::<_, Rc5Cipher, 32, 3>(
+                &server_to_client_schema,
+                server_to_client_mapper_socket,
+                enum_map! {
+                    Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                    Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                    Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                },
+                TestServerToClientSourceFactory {
+                    channels: [server_source_channels[0].1.clone(), server_source_channels[1].1.clone()],
+                },
+            )
+            .unwrap()
+            .receiver_with_socket::<_, ChaCha20Poly1305Cipher, 16, 3>(
+                &client_to_server_schema,
+                client_to_server_mapper_socket,
+                client_to_server_socket,
+                TestClientToServerSinkFactory {
+                    channel: server_sink_channel.0.clone(),
+                },
+            )
+            .unwrap()
+            .receiver::<_, ChaCha20Poly1305Cipher, 16, 3>(
+                &chat_schema,
+                TestClientToServerSinkFactory {
+                    channel: chat_sink_channel.0.clone(),
+                },
+            )
+            .unwrap()
+            .sender::<_, Rc5Cipher, 32, 3>(
+                &events_schema,
+                TestServerToClientSourceFactory {
+                    channels: [events_source_channels[0].1.clone(), events_source_channels[1].1.clone()],
+                },
+            )
+            .unwrap()
+            .snapshot_sender::<_, Rc5Cipher>(
+                &snapshot_schema,
+                4096,
+                TestSnapshotSourceFactory {
+                    channels: [
+                        snapshot_source_channels[0].1.clone(),
+                        snapshot_source_channels[1].1.clone(),
+                    ],
+                },
+            )
+            .unwrap()
+            .messages(
+                &message_schema,
+                TestMessageSourceFactory {
+                    channels: [
+                        server_message_source_channels[0].1.clone(),
+                        server_message_source_channels[1].1.clone(),
+                    ],
+                },
+                TestMessageSinkFactory {
+                    channel: server_message_sink_channel.0.clone(),
+                },
+            )
+            .unwrap()
+            .build();
+        server.register(server_session_1).unwrap();
+        server.register(server_session_2).unwrap();
+
+        let client_1 = Client::builder(client_session_1, Box::new(client_runtimes[0].clone()))
+            .sender::<_, ChaCha20Poly1305Cipher, 16, 3>(
+                &client_to_server_schema,
+                TestClientToServerSource {
+                    channel: client_source_channels[0].1.clone(),
+                },
+            )
+            .unwrap()
+            .receiver::<_, Rc5Cipher, 32, 3>(
+                &server_to_client_schema,
+                TestServerToClientSink {
+                    channel: client_sink_channels[0].0.clone(),
+                },
+            )
+            .unwrap()
+            .sender::<_, ChaCha20Poly1305Cipher, 16, 3>(
+                &chat_schema,
+                TestClientToServerSource {
+                    channel: chat_source_channel.1.clone(),
+                },
+            )
+            .unwrap()
+            .receiver::<_, Rc5Cipher, 32, 3>(
+                &events_schema,
+                TestServerToClientSink {
+                    channel: events_sink_channel.0.clone(),
+                },
+            )
+            .unwrap()
+            .snapshot_receiver::<_, Rc5Cipher>(
+                &snapshot_schema,
+                4096,
+                TestSnapshotSink {
+                    channel: snapshot_sink_channel.0.clone(),
+                },
+            )
+            .unwrap()
+            .messages(
+                &message_schema,
+                TestMessageSource {
+                    channel: client_message_source_channels[0].1.clone(),
+                },
+                TestMessageSink {
+                    player_index: 0,
+                    channel: client_message_sink_channels[0].0.clone(),
+                },
+            )
+            .unwrap()
+            .build();
+
+        // The second Client sizes its channels at runtime, and has to interoperate all the same.
+        let client_2 = Client::builder(client_session_2, Box::new(client_runtimes[1].clone()))
+            .dyn_sender::<_, ChaCha20Poly1305Cipher>(
+                &client_to_server_schema,
+                16,
+                3,
+                TestClientToServerSource {
+                    channel: client_source_channels[1].1.clone(),
+                },
+            )
+            .unwrap()
+            .dyn_receiver::<_, Rc5Cipher>(
+                &server_to_client_schema,
+                32,
+                3,
+                TestServerToClientSink {
+                    channel: client_sink_channels[1].0.clone(),
+                },
+            )
+            .unwrap()
+            .messages(
+                &message_schema,
+                TestMessageSource {
+                    channel: client_message_source_channels[1].1.clone(),
+                },
+                TestMessageSink {
+                    player_index: 1,
+                    channel: client_message_sink_channels[1].0.clone(),
+                },
+            )
+            .unwrap()
+            .build();
+
+        Self {
+            server_runtime,
+            server,
+            client_runtimes,
+            client_1,
+            client_2,
 
-    let client_runtimes = [TestRuntime::new(TICK_PERIOD), TestRuntime::new(TICK_PERIOD)];
-    let client_source_channels = [flume::unbounded(), flume::unbounded()];
-    let client_sink_channels = [flume::unbounded(), flume::unbounded()];
+            client_to_server_schema,
+            server_to_client_schema,
+            chat_schema,
+            events_schema,
+            snapshot_schema,
+            message_schema,
 
-    let mut server = Server::builder(2, Box::new(server_runtime.clone()))
-        .sender_with_sockets::<_, 32, 3>(
+            server_source_channels,
+            server_sink_channel,
+            events_source_channels,
+            chat_sink_channel,
+            server_message_source_channels,
+            server_message_sink_channel,
+            snapshot_source_channels,
+
+            client_source_channels,
+            client_sink_channels,
+            chat_source_channel,
+            events_sink_channel,
+            client_message_source_channels,
+            client_message_sink_channels,
+            snapshot_sink_channel,
+        }
+    }
+
+    fn tick(&self)
+    {
+        self.server_runtime.tick();
+        self.client_runtimes[0].tick();
+        self.client_runtimes[1].tick();
+    }
+}
+
+#[tokio::test]
+async fn golden()
+{
+    let harness = TestHarness::new().await;
+
+    // Initial
+    {
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert!(harness.client_sink_channels[1].1.is_empty());
+
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert!(harness.client_sink_channels[1].1.is_empty());
+    }
+
+    // Client 1 input
+    {
+        harness.client_source_channels[0].0.send((1, 10)).unwrap();
+        harness.client_runtimes[0].tick();
+
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (1, 0, 10));
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert!(harness.client_sink_channels[1].1.is_empty());
+    }
+
+    // Client 2 input
+    {
+        harness.client_source_channels[1].0.send((1, 20)).unwrap();
+        harness.client_runtimes[1].tick();
+
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (1, 1, 20));
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert!(harness.client_sink_channels[1].1.is_empty());
+    }
+
+    // Server input
+    {
+        harness.server_source_channels[0].0.send((1, [10, 20])).unwrap();
+        harness.server_source_channels[1].0.send((1, [10, 20])).unwrap();
+        harness.server_runtime.tick();
+
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert_eq!(harness.client_sink_channels[0].1.try_recv().unwrap(), (1, [10, 20]));
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert_eq!(harness.client_sink_channels[1].1.try_recv().unwrap(), (1, [10, 20]));
+        assert!(harness.client_sink_channels[1].1.is_empty());
+    }
+
+    // All inputs (with double tick)
+    {
+        harness.client_source_channels[0].0.send((2, 30)).unwrap();
+        harness.client_source_channels[1].0.send((2, 40)).unwrap();
+        harness.server_source_channels[0].0.send((3, [50, 60])).unwrap();
+        harness.server_source_channels[1].0.send((3, [50, 60])).unwrap();
+
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (2, 0, 30));
+        assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (2, 1, 40));
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert_eq!(harness.client_sink_channels[0].1.try_recv().unwrap(), (3, [50, 60]));
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert_eq!(harness.client_sink_channels[1].1.try_recv().unwrap(), (3, [50, 60]));
+        assert!(harness.client_sink_channels[1].1.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn key_rotation()
+{
+    let mut harness = TestHarness::new().await;
+
+    // The Client takes keys on its own, and the Server carries on while it hears back, with
+    // rotations acknowledged in the order they were made.
+    let rotations = [
+        harness.server.rotate_key(1, 0xFEEDFACEFEEDFACE),
+        harness.server.rotate_key(1, 0xFACEFEEDFACEFEED),
+    ];
+    assert!(harness.server.mapper_stats("Input").is_some());
+    for rotation in rotations
+    {
+        rotation.await.unwrap();
+    }
+
+    harness.client_source_channels[0].0.send((4, 70)).unwrap();
+    harness.client_source_channels[1].0.send((4, 80)).unwrap();
+    harness.server_source_channels[0].0.send((5, [70, 80])).unwrap();
+    harness.server_source_channels[1].0.send((5, [70, 80])).unwrap();
+
+    harness.tick();
+    harness.tick();
+    assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (4, 0, 70));
+    assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (4, 1, 80));
+    assert!(harness.server_sink_channel.1.is_empty());
+    assert_eq!(harness.client_sink_channels[0].1.try_recv().unwrap(), (5, [70, 80]));
+    assert!(harness.client_sink_channels[0].1.is_empty());
+    assert_eq!(harness.client_sink_channels[1].1.try_recv().unwrap(), (5, [70, 80]));
+    assert!(harness.client_sink_channels[1].1.is_empty());
+}
+
+#[tokio::test]
+async fn key_rotation_unregistered()
+{
+    let mut harness = TestHarness::new().await;
+
+    // A Session unregistered while its key is still being announced is gone for good, however
+    // the announcement turns out.
+    let rotation = harness.server.rotate_key(1, 0xFEEDFACEFEEDFACE);
+    harness.server.unregister(1);
+    let _ = rotation.await;
+
+    harness.server_source_channels[1].0.send((5, [70, 80])).unwrap();
+    harness.tick();
+    harness.tick();
+    assert_eq!(harness.client_sink_channels[1].1.try_recv().unwrap(), (5, [70, 80]));
+    assert!(harness.server.receiver_stats("Input", 1).is_none());
+}
+
+#[tokio::test]
+async fn multiplexed_channels()
+{
+    let harness = TestHarness::new().await;
+
+    harness.chat_source_channel.0.send((1, 110)).unwrap();
+    harness.events_source_channels[0].0.send((1, [110, 120])).unwrap();
+    harness.events_source_channels[1].0.send((1, [110, 120])).unwrap();
+    harness.server_source_channels[0].0.send((1, [10, 20])).unwrap();
+
+    harness.tick();
+    harness.tick();
+    assert_eq!(harness.chat_sink_channel.1.try_recv().unwrap(), (1, 0, 110));
+    assert!(harness.chat_sink_channel.1.is_empty());
+    assert_eq!(harness.events_sink_channel.1.try_recv().unwrap(), (1, [110, 120]));
+    assert!(harness.events_sink_channel.1.is_empty());
+    assert!(harness.server_sink_channel.1.is_empty());
+    assert_eq!(harness.client_sink_channels[0].1.try_recv().unwrap(), (1, [10, 20]));
+    assert!(harness.client_sink_channels[0].1.is_empty());
+    assert!(harness.client_sink_channels[1].1.is_empty());
+
+    // Heartbeats naming a channel nobody on the port uses are left to the schema that bound
+    // it first.
+    let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let mut heartbeat = [0; 20];
+    heartbeat[0] = PROTOCOL_VERSION;
+    heartbeat[1] = 9;
+    heartbeat[2..10].copy_from_slice(&1u64.to_le_bytes());
+    socket
+        .send_to(
+            &heartbeat,
+            SocketAddr::from(([127, 0, 0, 1], harness.client_to_server_schema.mapper_port)),
+        )
+        .unwrap();
+
+    harness.server_runtime.tick();
+    assert_eq!(
+        harness.server.mapper_stats("Input").unwrap().heartbeats_unknown_channel,
+        1
+    );
+    assert_eq!(
+        harness.server.mapper_stats("Chat").unwrap().heartbeats_unknown_channel,
+        0
+    );
+    assert!(harness.server.mapper_stats("Chat").unwrap().heartbeats_accepted > 0);
+    assert!(harness.server.mapper_stats("Events").unwrap().heartbeats_accepted > 0);
+
+    for (name, session_id) in [("Input", 1), ("Input", 2), ("Chat", 1)]
+    {
+        let stats = harness.server.receiver_stats(name, session_id).unwrap();
+        assert_eq!(stats.datagrams_unknown_channel, 0);
+    }
+    for name in ["State", "Events"]
+    {
+        let stats = harness.client_1.receiver_stats(name).unwrap();
+        assert!(stats.datagrams_accepted > 0);
+        assert_eq!(stats.datagrams_unknown_channel, 0);
+    }
+    assert!(harness.client_2.receiver_stats("Events").is_none());
+
+    // Channels are only told apart when no two schemas share one.
+    let server_to_client_mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let client_to_server_mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let client_to_server_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let server_to_client_schema = ServerToClientSchema {
+        mapper_port: server_to_client_mapper_socket.local_addr().unwrap().port(),
+        ..harness.events_schema
+    };
+    let client_to_server_schema = ClientToServerSchema {
+        channel_id: harness.events_schema.channel_id,
+        mapper_port: client_to_server_mapper_socket.local_addr().unwrap().port(),
+        port: client_to_server_socket.local_addr().unwrap().port(),
+        ..harness.chat_schema
+    };
+
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .sender_with_sockets::<_, Rc5Cipher, 32, 3>(
             &server_to_client_schema,
             server_to_client_mapper_socket,
             enum_map! {
@@ -293,134 +977,937 @@ async fn golden()
                 Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
             },
             TestServerToClientSourceFactory {
-                channels: [server_source_channels[0].1.clone(), server_source_channels[1].1.clone()],
+                channels: [
+                    harness.events_source_channels[0].1.clone(),
+                    harness.events_source_channels[1].1.clone(),
+                ],
             },
         )
         .unwrap()
-        .receiver_with_socket::<_, 16, 3>(
+        .receiver_with_socket::<_, ChaCha20Poly1305Cipher, 16, 3>(
             &client_to_server_schema,
             client_to_server_mapper_socket,
             client_to_server_socket,
             TestClientToServerSinkFactory {
-                channel: server_sink_channel.0.clone(),
+                channel: harness.chat_sink_channel.0.clone(),
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "Chat");
+    assert_eq!(error.root_cause().to_string(), "Reused channel 3");
+}
+
+#[tokio::test]
+async fn messages()
+{
+    let harness = TestHarness::new().await;
+
+    // Every message arrives exactly once and in order, however large, with the streams
+    // carrying them pumped along in between ticks.
+    // Sources going over the maximum only lose that message.
+    let messages = (0..64u8).map(|i| vec![i; i as usize * 4]).collect::<Vec<_>>();
+    for message in messages.iter().chain([&vec![0xFF; 257]])
+    {
+        harness.client_message_source_channels[0]
+            .0
+            .send(message.clone())
+            .unwrap();
+        harness.client_message_source_channels[1]
+            .0
+            .send(message.clone())
+            .unwrap();
+        harness.server_message_source_channels[0]
+            .0
+            .send(message.clone())
+            .unwrap();
+        harness.server_message_source_channels[1]
+            .0
+            .send(message.clone())
+            .unwrap();
+    }
+
+    for _ in 0..1000
+    {
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        if harness.server_message_sink_channel.1.len() == 128
+            && harness.client_message_sink_channels[0].1.len() == 64
+            && harness.client_message_sink_channels[1].1.len() == 64
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let (player_1, player_2): (Vec<_>, Vec<_>) = harness
+        .server_message_sink_channel
+        .1
+        .try_iter()
+        .partition(|(player_index, _)| *player_index == 0);
+    assert_eq!(
+        player_1.into_iter().map(|(_, message)| message).collect::<Vec<_>>(),
+        messages
+    );
+    assert_eq!(
+        player_2.into_iter().map(|(_, message)| message).collect::<Vec<_>>(),
+        messages
+    );
+    for (player_index, client_message_sink_channel) in harness.client_message_sink_channels.iter().enumerate()
+    {
+        let received = client_message_sink_channel.1.try_iter().collect::<Vec<_>>();
+        assert!(received.iter().all(|(index, _)| *index == player_index as u8));
+        assert_eq!(
+            received.into_iter().map(|(_, message)| message).collect::<Vec<_>>(),
+            messages
+        );
+    }
+    assert!(harness.server_sink_channel.1.is_empty());
+    assert!(harness.client_sink_channels[0].1.is_empty());
+    assert!(harness.client_sink_channels[1].1.is_empty());
+
+    let stats = MessageStats {
+        messages_sent: 64,
+        messages_received: 64,
+        messages_oversized: 1,
+        messages_refused: 0,
+        messages_undelivered: 0,
+
+        stream_closed: false,
+        stream_failed: false,
+    };
+    for session_id in [1, 2]
+    {
+        assert_eq!(harness.server.message_stats("Lobby", session_id), Some(stats));
+    }
+    assert!(harness.server.message_stats("Lobby", 3).is_none());
+    for client in [&harness.client_1, &harness.client_2]
+    {
+        assert_eq!(client.message_stats("Lobby"), Some(stats));
+    }
+    assert!(harness.client_1.message_stats("Input").is_none());
+
+    // Message schemas share the channels of everything else.
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .messages(
+            &harness.message_schema,
+            TestMessageSourceFactory {
+                channels: [
+                    harness.server_message_source_channels[0].1.clone(),
+                    harness.server_message_source_channels[1].1.clone(),
+                ],
+            },
+            TestMessageSinkFactory {
+                channel: harness.server_message_sink_channel.0.clone(),
             },
         )
         .unwrap()
-        .build();
-    server.register(server_session_1);
-    server.register(server_session_2);
+        .messages(
+            &MessageSchema {
+                name: "Trade",
+                ..harness.message_schema
+            },
+            TestMessageSourceFactory {
+                channels: [
+                    harness.server_message_source_channels[0].1.clone(),
+                    harness.server_message_source_channels[1].1.clone(),
+                ],
+            },
+            TestMessageSinkFactory {
+                channel: harness.server_message_sink_channel.0.clone(),
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "Trade");
+    assert_eq!(error.root_cause().to_string(), "Reused channel 4");
+}
 
-    let _client_1 = Client::builder(client_session_1, Box::new(client_runtimes[0].clone()))
-        .sender::<_, 16, 3>(
-            &client_to_server_schema,
-            TestClientToServerSource {
-                channel: client_source_channels[0].1.clone(),
+#[tokio::test]
+async fn snapshots()
+{
+    let harness = TestHarness::new().await;
+
+    // Nothing goes out before the Client's heartbeats tell the Server where to send it.
+    harness.tick();
+
+    // Snapshots too big for one datagram arrive whole, and only the latest one polled goes out.
+    let snapshots = (0..3u8).map(|i| vec![i; 3000 + (i as usize)]).collect::<Vec<_>>();
+    for snapshot in snapshots.iter()
+    {
+        harness.snapshot_source_channels[0].0.send(snapshot.clone()).unwrap();
+    }
+    harness.snapshot_source_channels[1]
+        .0
+        .send(snapshots[0].clone())
+        .unwrap();
+
+    harness.tick();
+    assert_eq!(
+        harness.snapshot_sink_channel.1.try_recv().unwrap(),
+        (0, snapshots[2].clone())
+    );
+    assert!(harness.snapshot_sink_channel.1.is_empty());
+
+    harness.snapshot_source_channels[0]
+        .0
+        .send(snapshots[1].clone())
+        .unwrap();
+    harness.server_runtime.tick();
+    harness.client_runtimes[0].tick();
+    assert_eq!(
+        harness.snapshot_sink_channel.1.try_recv().unwrap(),
+        (1, snapshots[1].clone())
+    );
+    assert!(harness.snapshot_sink_channel.1.is_empty());
+
+    // Once the Client acknowledges a snapshot, the next goes out as its difference from it.
+    let mut snapshot = snapshots[1].clone();
+    snapshot[1000] = 10;
+    snapshot.push(10);
+    harness.snapshot_source_channels[0].0.send(snapshot.clone()).unwrap();
+    harness.client_runtimes[0].advance(100);
+    harness.client_runtimes[1].advance(100);
+    harness.server_runtime.advance(100);
+    harness.client_runtimes[0].tick();
+    assert_eq!(harness.snapshot_sink_channel.1.try_recv().unwrap(), (2, snapshot));
+    assert!(harness.snapshot_sink_channel.1.is_empty());
+
+    // Snapshots over the maximum are dropped and counted, not sent.
+    harness.snapshot_source_channels[0].0.send(vec![0; 4097]).unwrap();
+    harness.tick();
+    assert!(harness.snapshot_sink_channel.1.is_empty());
+    assert_eq!(
+        harness.server.snapshot_sender_stats("World", 1).unwrap(),
+        SnapshotSenderStats {
+            snapshots_polled: 3,
+            snapshots_oversized: 1,
+        }
+    );
+    assert_eq!(
+        harness
+            .server
+            .snapshot_sender_stats("World", 2)
+            .unwrap()
+            .snapshots_polled,
+        1
+    );
+    assert!(harness.server.snapshot_sender_stats("World", 3).is_none());
+    assert!(harness.server.snapshot_sender_stats("State", 1).is_none());
+
+    let stats = harness.client_1.snapshot_stats("World").unwrap();
+    assert_eq!(stats.snapshots_delivered, 3);
+    assert_eq!(stats.snapshots_missing_baseline, 0);
+    assert_eq!(stats.snapshots_superseded, 0);
+    assert_eq!(stats.datagrams_malformed, 0);
+    assert_eq!(stats.datagrams_unknown_channel, 0);
+    assert_eq!(stats.authentication_failures, 0);
+    // Three fragments each for the full snapshots and one for the difference, mirrored on two
+    // more lanes, and late copies are only stale.
+    assert_eq!(stats.datagrams_accepted, 7);
+    assert_eq!(stats.duplicate_fragments + stats.datagrams_stale, 14);
+    assert_eq!(stats.datagrams_replayed, 0);
+    assert!(harness.client_1.snapshot_stats("State").is_none());
+    assert!(harness.client_2.snapshot_stats("World").is_none());
+    assert!(harness.server.mapper_stats("World").unwrap().heartbeats_accepted > 0);
+    assert_eq!(
+        harness.server.mapper_stats("State").unwrap().heartbeats_unknown_channel,
+        0
+    );
+
+    // Snapshots have to fit in as many fragments as can be counted.
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = SnapshotSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        max_datagram_size: 64,
+        ..harness.snapshot_schema
+    };
+
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .snapshot_sender_with_sockets::<_, Rc5Cipher>(
+            &schema,
+            1 << 24,
+            mapper_socket,
+            enum_map! {
+                Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            },
+            TestSnapshotSourceFactory {
+                channels: [
+                    harness.snapshot_source_channels[0].1.clone(),
+                    harness.snapshot_source_channels[1].1.clone(),
+                ],
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "World");
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Snapshot of 16777216 bytes doesn't fit in datagrams of 64 bytes"
+    );
+
+    // And only keep as many baselines as can be told apart.
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = SnapshotSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        baselines: 1 << 16,
+        ..harness.snapshot_schema
+    };
+
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .snapshot_sender_with_sockets::<_, Rc5Cipher>(
+            &schema,
+            4096,
+            mapper_socket,
+            enum_map! {
+                Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            },
+            TestSnapshotSourceFactory {
+                channels: [
+                    harness.snapshot_source_channels[0].1.clone(),
+                    harness.snapshot_source_channels[1].1.clone(),
+                ],
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "World");
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Can't keep 65536 baselines, at most 65535"
+    );
+}
+
+#[tokio::test]
+async fn snapshot_fragment_budget()
+{
+    let harness = TestHarness::new().await;
+    let (server_session, client_session) = sessions(1, 0xDEADBEEFDEADBEEF).await;
+
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = SnapshotSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        max_fragments_per_poll: 1,
+        ..harness.snapshot_schema
+    };
+
+    let server_runtime = TestRuntime::new(TICK_PERIOD);
+    let snapshot_source_channel = flume::unbounded();
+    let mut server = Server::builder(1, Box::new(server_runtime.clone()))
+        .snapshot_sender_with_sockets::<_, Rc5Cipher>(
+            &schema,
+            4096,
+            mapper_socket,
+            enum_map! {
+                Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            },
+            TestSnapshotSourceFactory {
+                channels: [snapshot_source_channel.1.clone(), flume::unbounded().1],
             },
         )
         .unwrap()
-        .receiver::<_, 32, 3>(
-            &server_to_client_schema,
-            TestServerToClientSink {
-                channel: client_sink_channels[0].0.clone(),
+        .build();
+    server.register(server_session).unwrap();
+
+    let client_runtime = TestRuntime::new(TICK_PERIOD);
+    let snapshot_sink_channel = flume::unbounded();
+    let _client = Client::builder(client_session, Box::new(client_runtime.clone()))
+        .snapshot_receiver::<_, Rc5Cipher>(
+            &schema,
+            4096,
+            TestSnapshotSink {
+                channel: snapshot_sink_channel.0.clone(),
             },
         )
         .unwrap()
         .build();
+    server_runtime.tick();
+    client_runtime.tick();
 
-    let _client_2 = Client::builder(client_session_2, Box::new(client_runtimes[1].clone()))
-        .sender::<_, 16, 3>(
-            &client_to_server_schema,
-            TestClientToServerSource {
-                channel: client_source_channels[1].1.clone(),
+    // Snapshots go out a fragment per poll, so a three fragment snapshot takes three polls, and the
+    // next waits for it to finish.
+    let snapshots = (0..2u8).map(|i| vec![i; 3000]).collect::<Vec<_>>();
+    snapshot_source_channel.0.send(snapshots[0].clone()).unwrap();
+    for _ in 0..2
+    {
+        server_runtime.tick();
+        client_runtime.tick();
+        assert!(snapshot_sink_channel.1.is_empty());
+        snapshot_source_channel.0.send(snapshots[1].clone()).unwrap();
+    }
+    server_runtime.tick();
+    client_runtime.tick();
+    assert_eq!(snapshot_sink_channel.1.try_recv().unwrap(), (0, snapshots[0].clone()));
+    assert!(snapshot_sink_channel.1.is_empty());
+
+    for _ in 0..3
+    {
+        server_runtime.tick();
+        client_runtime.tick();
+    }
+    assert_eq!(snapshot_sink_channel.1.try_recv().unwrap(), (1, snapshots[1].clone()));
+    assert!(snapshot_sink_channel.1.is_empty());
+    assert_eq!(
+        server.snapshot_sender_stats("World", 1).unwrap(),
+        SnapshotSenderStats {
+            snapshots_polled: 2,
+            snapshots_oversized: 0,
+        }
+    );
+
+    // Sending nothing at all would never finish a snapshot.
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = SnapshotSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        max_fragments_per_poll: 0,
+        ..harness.snapshot_schema
+    };
+
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .snapshot_sender_with_sockets::<_, Rc5Cipher>(
+            &schema,
+            4096,
+            mapper_socket,
+            enum_map! {
+                Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            },
+            TestSnapshotSourceFactory {
+                channels: [flume::unbounded().1, flume::unbounded().1],
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "World");
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Can't send snapshots 0 fragments at a time"
+    );
+}
+
+#[tokio::test]
+async fn unknown_message_channels()
+{
+    let harness = TestHarness::new().await;
+    let (server_session, client_session) = sessions(1, 0xDEADBEEFDEADBEEF).await;
+
+    let server_runtime = TestRuntime::new(TICK_PERIOD);
+    let mut server = Server::builder(1, Box::new(server_runtime.clone()))
+        .messages(
+            &harness.message_schema,
+            TestMessageSourceFactory {
+                channels: [flume::unbounded().1, flume::unbounded().1],
+            },
+            TestMessageSinkFactory {
+                channel: flume::unbounded().0,
             },
         )
         .unwrap()
-        .receiver::<_, 32, 3>(
-            &server_to_client_schema,
-            TestServerToClientSink {
-                channel: client_sink_channels[1].0.clone(),
+        .build();
+    server.register(server_session).unwrap();
+
+    let client_runtime = TestRuntime::new(TICK_PERIOD);
+    let message_source_channel = flume::unbounded();
+    let client = Client::builder(client_session, Box::new(client_runtime.clone()))
+        .messages(
+            &MessageSchema {
+                name: "Other",
+                channel_id: 9,
+                ..harness.message_schema
+            },
+            TestMessageSource {
+                channel: message_source_channel.1.clone(),
+            },
+            TestMessageSink {
+                player_index: 0,
+                channel: flume::unbounded().0,
             },
         )
         .unwrap()
         .build();
 
-    // Initial
+    // Streams on channels the Server doesn't serve are stopped, which the Client finds out about
+    // the next time it writes.
+    for _ in 0..1000
     {
-        assert!(server_sink_channel.1.is_empty());
-        assert!(client_sink_channels[0].1.is_empty());
-        assert!(client_sink_channels[1].1.is_empty());
+        message_source_channel.0.send(vec![1, 2, 3]).unwrap();
+        server_runtime.tick();
+        client_runtime.tick();
+        if client.message_stats("Other").unwrap().stream_closed
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let stats = client.message_stats("Other").unwrap();
+    assert!(stats.stream_closed);
+    assert!(stats.stream_failed);
+    assert_eq!(server.message_stats("Lobby", 1).unwrap().messages_received, 0);
+}
+
+#[tokio::test]
+async fn stalled_message_streams()
+{
+    let harness = TestHarness::new().await;
 
+    let certified_key = rcgen::generate_simple_self_signed([String::from("localhost")]).unwrap();
+    let server_endpoint = Endpoint::server(
+        ServerConfig::with_single_cert(
+            Vec::from([certified_key.cert.der().clone()]),
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified_key.key_pair.serialize_der())),
+        )
+        .unwrap(),
+        SocketAddr::from(([127, 0, 0, 1], 0)),
+    )
+    .unwrap();
+    let client_endpoint = Endpoint::client(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let connections = connect(&server_endpoint, &client_endpoint, &certified_key).await;
+    let server_session = ServerSession::new(1, 0xDEADBEEFDEADBEEF, connections.0).await.unwrap();
+    let client_session = ClientSession::new(connections.1.clone()).await.unwrap();
+
+    let server_runtime = TestRuntime::new(TICK_PERIOD);
+    let message_sink_channel = flume::unbounded();
+    let mut server = Server::builder(1, Box::new(server_runtime.clone()))
+        .messages(
+            &harness.message_schema,
+            TestMessageSourceFactory {
+                channels: [flume::unbounded().1, flume::unbounded().1],
+            },
+            TestMessageSinkFactory {
+                channel: message_sink_channel.0.clone(),
+            },
+        )
+        .unwrap()
+        .build();
+    server.register(server_session).unwrap();
+
+    // A stream that never names its channel doesn't hold up the ones opened after it.
+    let _stalled = connections.1.open_bi().await.unwrap();
+
+    let client_runtime = TestRuntime::new(TICK_PERIOD);
+    let message_source_channel = flume::unbounded();
+    let _client = Client::builder(client_session, Box::new(client_runtime.clone()))
+        .messages(
+            &harness.message_schema,
+            TestMessageSource {
+                channel: message_source_channel.1.clone(),
+            },
+            TestMessageSink {
+                player_index: 0,
+                channel: flume::unbounded().0,
+            },
+        )
+        .unwrap()
+        .build();
+
+    message_source_channel.0.send(vec![1, 2, 3]).unwrap();
+    for _ in 0..1000
+    {
+        client_runtime.tick();
         server_runtime.tick();
-        client_runtimes[0].tick();
-        client_runtimes[1].tick();
-        assert!(server_sink_channel.1.is_empty());
-        assert!(client_sink_channels[0].1.is_empty());
-        assert!(client_sink_channels[1].1.is_empty());
+        if !message_sink_channel.1.is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
     }
+    assert_eq!(message_sink_channel.1.try_recv().unwrap(), (0, vec![1, 2, 3]));
+}
 
-    // Client 1 input
+#[tokio::test]
+async fn queued_messages()
+{
+    let harness = TestHarness::new().await;
+    let (server_session, _client_session) = sessions(1, 0xDEADBEEFDEADBEEF).await;
+
+    // Messages queue up while the Client has yet to open the stream for them, up to a point,
+    // past which they're refused.
+    let schema = MessageSchema {
+        max_queued_messages: 2,
+        ..harness.message_schema
+    };
+    let server_runtime = TestRuntime::new(TICK_PERIOD);
+    let message_source_channel = flume::unbounded();
+    let mut server = Server::builder(1, Box::new(server_runtime.clone()))
+        .messages(
+            &schema,
+            TestMessageSourceFactory {
+                channels: [message_source_channel.1.clone(), flume::unbounded().1],
+            },
+            TestMessageSinkFactory {
+                channel: flume::unbounded().0,
+            },
+        )
+        .unwrap()
+        .build();
+    server.register(server_session).unwrap();
+
+    for i in 0..3u8
     {
-        client_source_channels[0].0.send((1, 10)).unwrap();
-        client_runtimes[0].tick();
+        message_source_channel.0.send(vec![i; 4]).unwrap();
+    }
+    server_runtime.tick();
+    let stats = server.message_stats("Lobby", 1).unwrap();
+    assert_eq!(stats.messages_sent, 2);
+    assert_eq!(stats.messages_refused, 1);
+    assert_eq!(stats.messages_undelivered, 0);
 
-        server_runtime.tick();
-        client_runtimes[0].tick();
-        client_runtimes[1].tick();
-        assert_eq!(server_sink_channel.1.try_recv().unwrap(), (1, 0, 10));
-        assert!(server_sink_channel.1.is_empty());
-        assert!(client_sink_channels[0].1.is_empty());
-        assert!(client_sink_channels[1].1.is_empty());
+    // And there has to be room for at least one.
+    let schema = MessageSchema {
+        max_queued_messages: 0,
+        ..harness.message_schema
+    };
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD))).messages(
+        &schema,
+        TestMessageSourceFactory {
+            channels: [flume::unbounded().1, flume::unbounded().1],
+        },
+        TestMessageSinkFactory {
+            channel: flume::unbounded().0,
+        },
+    );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "Lobby");
+    assert_eq!(error.root_cause().to_string(), "Can't queue 0 messages");
+}
+
+#[tokio::test]
+async fn unknown_versions()
+{
+    let harness = TestHarness::new().await;
+
+    let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    for mapper_port in [
+        harness.client_to_server_schema.mapper_port,
+        harness.server_to_client_schema.mapper_port,
+    ]
+    {
+        let mapper_socket_addr = SocketAddr::from(([127, 0, 0, 1], mapper_port));
+        socket.send_to(&[PROTOCOL_VERSION + 1; 10], mapper_socket_addr).unwrap();
+        socket.send_to(&[PROTOCOL_VERSION], mapper_socket_addr).unwrap();
     }
 
-    // Client 2 input
+    harness.tick();
+    harness.tick();
+    for name in ["Input", "State"]
     {
-        client_source_channels[1].0.send((1, 20)).unwrap();
-        client_runtimes[1].tick();
+        let stats = harness.server.mapper_stats(name).unwrap();
+        assert!(stats.heartbeats_accepted > 0);
+        assert_eq!(stats.heartbeats_malformed, 1);
+        assert_eq!(stats.heartbeats_unsupported_version, 1);
+    }
+    assert!(harness.server.mapper_stats("Other").is_none());
+}
 
-        server_runtime.tick();
-        client_runtimes[0].tick();
-        client_runtimes[1].tick();
-        assert_eq!(server_sink_channel.1.try_recv().unwrap(), (1, 1, 20));
-        assert!(server_sink_channel.1.is_empty());
-        assert!(client_sink_channels[0].1.is_empty());
-        assert!(client_sink_channels[1].1.is_empty());
+#[tokio::test]
+async fn forged_heartbeats()
+{
+    let harness = TestHarness::new().await;
+
+    // Heartbeats carry acknowledgements, so anything naming a Session without its key is
+    // turned away, whether it's for datagrams or snapshots.
+    let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    for channel_id in [
+        harness.server_to_client_schema.channel_id,
+        harness.snapshot_schema.channel_id,
+    ]
+    {
+        let mut heartbeat = [0; 28];
+        heartbeat[0] = PROTOCOL_VERSION;
+        heartbeat[1] = channel_id;
+        heartbeat[2..10].copy_from_slice(&1u64.to_le_bytes());
+        heartbeat[10..18].copy_from_slice(&u64::MAX.to_le_bytes());
+        socket
+            .send_to(
+                &heartbeat,
+                SocketAddr::from(([127, 0, 0, 1], harness.server_to_client_schema.mapper_port)),
+            )
+            .unwrap();
     }
 
-    // Server input
+    harness.tick();
+    harness.tick();
+    for name in ["State", "World"]
     {
-        server_source_channels[0].0.send((1, [10, 20])).unwrap();
-        server_source_channels[1].0.send((1, [10, 20])).unwrap();
-        server_runtime.tick();
+        let stats = harness.server.mapper_stats(name).unwrap();
+        assert!(stats.heartbeats_accepted > 0);
+        assert_eq!(stats.heartbeats_check_failures, 1);
+        assert_eq!(stats.heartbeats_malformed, 0);
+    }
 
-        server_runtime.tick();
-        client_runtimes[0].tick();
-        client_runtimes[1].tick();
-        assert!(server_sink_channel.1.is_empty());
-        assert_eq!(client_sink_channels[0].1.try_recv().unwrap(), (1, [10, 20]));
-        assert!(client_sink_channels[0].1.is_empty());
-        assert_eq!(client_sink_channels[1].1.try_recv().unwrap(), (1, [10, 20]));
-        assert!(client_sink_channels[1].1.is_empty());
+    // Nor can anyone without it tell the Server a Client's lane has moved.
+    let mut heartbeat = [0; 20];
+    heartbeat[0] = PROTOCOL_VERSION;
+    heartbeat[1] = harness.client_to_server_schema.channel_id;
+    heartbeat[2..10].copy_from_slice(&1u64.to_le_bytes());
+    socket
+        .send_to(
+            &heartbeat,
+            SocketAddr::from(([127, 0, 0, 1], harness.client_to_server_schema.mapper_port)),
+        )
+        .unwrap();
+
+    harness.server_runtime.tick();
+    let stats = harness.server.mapper_stats("Input").unwrap();
+    assert!(stats.heartbeats_accepted > 0);
+    assert_eq!(stats.heartbeats_check_failures, 1);
+    assert_eq!(stats.heartbeats_malformed, 0);
+
+    harness.server_source_channels[0].0.send((1, [10, 20])).unwrap();
+    harness.snapshot_source_channels[0].0.send(vec![1; 100]).unwrap();
+    harness.client_source_channels[0].0.send((1, 10)).unwrap();
+    harness.client_runtimes[0].tick();
+    harness.tick();
+    assert_eq!(harness.client_sink_channels[0].1.try_recv().unwrap(), (1, [10, 20]));
+    assert_eq!(harness.snapshot_sink_channel.1.try_recv().unwrap(), (0, vec![1; 100]));
+    assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (1, 0, 10));
+}
+
+#[tokio::test]
+async fn stats()
+{
+    let harness = TestHarness::new().await;
+
+    harness.client_source_channels[0].0.send((1, 10)).unwrap();
+    harness.client_source_channels[1].0.send((1, 20)).unwrap();
+    harness.server_source_channels[0].0.send((1, [10, 20])).unwrap();
+    harness.server_source_channels[1].0.send((1, [10, 20])).unwrap();
+    harness.tick();
+    harness.tick();
+
+    for session_id in [1, 2]
+    {
+        let stats = harness.server.receiver_stats("Input", session_id).unwrap();
+        assert!(stats.datagrams_accepted > 0);
+        assert_eq!(stats.datagrams_malformed, 0);
+        assert_eq!(stats.datagrams_unsupported_version, 0);
+        assert_eq!(stats.datagrams_unknown_epoch, 0);
+        assert_eq!(stats.header_check_failures, 0);
+        assert_eq!(stats.authentication_failures, 0);
     }
+    assert!(harness.server.receiver_stats("Input", 3).is_none());
+    assert!(harness.server.receiver_stats("State", 1).is_none());
 
-    // All inputs (with double tick)
+    for client in [&harness.client_1, &harness.client_2]
     {
-        client_source_channels[0].0.send((2, 30)).unwrap();
-        client_source_channels[1].0.send((2, 40)).unwrap();
-        server_source_channels[0].0.send((3, [50, 60])).unwrap();
-        server_source_channels[1].0.send((3, [50, 60])).unwrap();
+        let stats = client.receiver_stats("State").unwrap();
+        assert!(stats.datagrams_accepted > 0);
+        assert_eq!(stats.datagrams_malformed, 0);
+        assert_eq!(stats.datagrams_unsupported_version, 0);
+        assert_eq!(stats.datagrams_unknown_epoch, 0);
+        assert_eq!(stats.header_check_failures, 0);
+        assert_eq!(stats.authentication_failures, 0);
+    }
+    assert!(harness.client_1.receiver_stats("Input").is_none());
 
-        server_runtime.tick();
-        client_runtimes[0].tick();
-        client_runtimes[1].tick();
-        server_runtime.tick();
-        client_runtimes[0].tick();
-        client_runtimes[1].tick();
-        assert_eq!(server_sink_channel.1.try_recv().unwrap(), (2, 0, 30));
-        assert_eq!(server_sink_channel.1.try_recv().unwrap(), (2, 1, 40));
-        assert!(server_sink_channel.1.is_empty());
-        assert_eq!(client_sink_channels[0].1.try_recv().unwrap(), (3, [50, 60]));
-        assert!(client_sink_channels[0].1.is_empty());
-        assert_eq!(client_sink_channels[1].1.try_recv().unwrap(), (3, [50, 60]));
-        assert!(client_sink_channels[1].1.is_empty());
+    // Schemas sharing a socket queue what the others read off it for them, and only lose any of
+    // it once they fall well behind.  These are a byte short, so each shows up in "Chat"'s stats.
+    let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let mut heartbeat = [0; 19];
+    heartbeat[0] = PROTOCOL_VERSION;
+    heartbeat[1] = 2;
+    for _ in 0..100
+    {
+        socket
+            .send_to(
+                &heartbeat,
+                SocketAddr::from(([127, 0, 0, 1], harness.client_to_server_schema.mapper_port)),
+            )
+            .unwrap();
+    }
+
+    harness.server_runtime.tick();
+    assert_eq!(harness.server.mapper_stats("Chat").unwrap().heartbeats_malformed, 100);
+    for name in ["Input", "Chat", "State", "World"]
+    {
+        assert_eq!(harness.server.channel_stats(name).unwrap().packets_dropped, 0);
+    }
+    assert!(harness.server.channel_stats("Lobby").is_none());
+    for name in ["Input", "State", "Events", "World"]
+    {
+        assert_eq!(harness.client_1.channel_stats(name).unwrap().packets_dropped, 0);
+    }
+    assert!(harness.client_2.channel_stats("World").is_none());
+}
+
+#[tokio::test]
+async fn datagram_budgets()
+{
+    let harness = TestHarness::new().await;
+
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = ServerToClientSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        max_datagram_size: 32,
+        ..harness.server_to_client_schema
+    };
+
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .sender_with_sockets::<_, Rc5Cipher, 32, 3>(
+            &schema,
+            mapper_socket,
+            enum_map! {
+                Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            },
+            TestServerToClientSourceFactory {
+                channels: [
+                    harness.server_source_channels[0].1.clone(),
+                    harness.server_source_channels[1].1.clone(),
+                ],
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "State");
+    assert!(error
+        .root_cause()
+        .to_string()
+        .starts_with("Datagram budget of 32 bytes"));
+
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = ServerToClientSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        ..harness.server_to_client_schema
+    };
+
+    let result = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD))).dyn_sender_with_sockets::<_, Rc5Cipher>(
+        &schema,
+        4096,
+        3,
+        mapper_socket,
+        enum_map! {
+            Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+        },
+        TestServerToClientSourceFactory {
+            channels: [
+                harness.server_source_channels[0].1.clone(),
+                harness.server_source_channels[1].1.clone(),
+            ],
+        },
+    );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "State");
+    assert_eq!(
+        error.root_cause().to_string(),
+        "Input of 4096 bytes and window of 3 don't fit in a datagram"
+    );
+}
+
+#[tokio::test]
+async fn oversized_schemas()
+{
+    let harness = TestHarness::new().await;
+
+    for session_id in [1, 2]
+    {
+        assert!(harness.server.oversized_schemas(session_id).unwrap().is_empty());
+    }
+    assert!(harness.server.oversized_schemas(3).is_none());
+
+    for client in [&harness.client_1, &harness.client_2]
+    {
+        assert!(client.oversized_schemas().is_empty());
+    }
+
+    // Full sized datagrams don't make it through QUIC's 1200 bytes until it finds out the path
+    // carries more, so either end turns them away up front.
+    let (server_session, client_session) = sessions(3, 0xDEADBEEFDEADBEEF).await;
+
+    let mapper_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap();
+    let schema = SnapshotSchema {
+        mapper_port: mapper_socket.local_addr().unwrap().port(),
+        max_datagram_size: 1472,
+        ..harness.snapshot_schema
+    };
+
+    let mut server = Server::builder(1, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .snapshot_sender_with_sockets::<_, Rc5Cipher>(
+            &schema,
+            4096,
+            mapper_socket,
+            enum_map! {
+                Mirroring::AudioVideo => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Background => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+                Mirroring::Voice => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap(),
+            },
+            TestSnapshotSourceFactory {
+                channels: [
+                    harness.snapshot_source_channels[0].1.clone(),
+                    harness.snapshot_source_channels[1].1.clone(),
+                ],
+            },
+        )
+        .unwrap()
+        .build();
+    let error = server.register(server_session).err().unwrap();
+    assert_eq!(error.to_string(), "World");
+    assert!(error.root_cause().to_string().starts_with("Datagrams of 14"));
+    assert!(server.oversized_schemas(3).is_none());
+
+    let result = Client::builder(client_session, Box::new(TestRuntime::new(TICK_PERIOD)))
+        .snapshot_receiver::<_, Rc5Cipher>(
+            &schema,
+            4096,
+            TestSnapshotSink {
+                channel: flume::unbounded().0,
+            },
+        );
+    let error = result.err().unwrap();
+    assert_eq!(error.to_string(), "World");
+    assert!(error.root_cause().to_string().starts_with("Datagrams of 14"));
+}
+
+#[tokio::test]
+async fn clock_wraparound()
+{
+    let harness = TestHarness::new().await;
+
+    let heartbeats_accepted =
+        ["Input", "State"].map(|name| harness.server.mapper_stats(name).unwrap().heartbeats_accepted);
+
+    // A second a frame, for a little over three wraps of the wire timestamps.
+    for frame in 6..206
+    {
+        harness.client_source_channels[0].0.send((frame, 90)).unwrap();
+        harness.client_source_channels[1].0.send((frame, 100)).unwrap();
+        harness.server_source_channels[0].0.send((frame, [90, 100])).unwrap();
+        harness.server_source_channels[1].0.send((frame, [90, 100])).unwrap();
+
+        harness.server_runtime.advance(1000);
+        harness.client_runtimes[0].advance(1000);
+        harness.client_runtimes[1].advance(1000);
+        harness.server_runtime.tick();
+        harness.client_runtimes[0].tick();
+        harness.client_runtimes[1].tick();
+        assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (frame, 0, 90));
+        assert_eq!(harness.server_sink_channel.1.try_recv().unwrap(), (frame, 1, 100));
+        assert!(harness.server_sink_channel.1.is_empty());
+        assert_eq!(
+            harness.client_sink_channels[0].1.try_recv().unwrap(),
+            (frame, [90, 100])
+        );
+        assert!(harness.client_sink_channels[0].1.is_empty());
+        assert_eq!(
+            harness.client_sink_channels[1].1.try_recv().unwrap(),
+            (frame, [90, 100])
+        );
+        assert!(harness.client_sink_channels[1].1.is_empty());
     }
+
+    // Heartbeats kept up throughout, every 2 frames on each of three lanes for "Input", and
+    // every frame for "State", from both Clients.
+    let heartbeats_accepted = ["Input", "State"]
+        .map(|name| harness.server.mapper_stats(name).unwrap().heartbeats_accepted)
+        .iter()
+        .zip(heartbeats_accepted)
+        .map(|(after, before)| after - before)
+        .collect::<Vec<_>>();
+    assert_eq!(heartbeats_accepted, [600, 400]);
 }
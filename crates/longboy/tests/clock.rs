@@ -0,0 +1,58 @@
+use std::{cmp::Ordering, time::Duration};
+
+use longboy::{serial_cmp, serial_diff, Clock, Instant};
+
+#[test]
+fn instant()
+{
+    let instant = Instant::from_millis(65_535);
+    assert_eq!(instant.timestamp(), u16::MAX);
+    assert_eq!((instant + 1).timestamp(), 0);
+    assert_eq!((instant + 1).as_millis(), 65_536);
+    assert!(instant + 1 > instant);
+
+    // Keeps counting past several wraps of the wire timestamp.
+    let mut now = Instant::default();
+    for _ in 0..4 * 65_536 / 1000
+    {
+        let next = now + 1000;
+        assert!(next > now);
+        assert_eq!(next.saturating_since(now), 1000);
+        assert_eq!(serial_diff(next.timestamp(), now.timestamp()), 1000);
+        now = next;
+    }
+    assert_eq!(now.as_millis(), 262_000);
+    assert_eq!(Instant::default().saturating_since(now), 0);
+}
+
+#[test]
+fn clock()
+{
+    let clock = Clock::new();
+    let earlier = clock.now();
+    std::thread::sleep(Duration::from_millis(2));
+    let later = clock.now();
+    assert!(later.saturating_since(earlier) >= 2);
+
+    // Copies share the origin.
+    let copy = clock;
+    assert!(copy.now() >= later);
+}
+
+#[test]
+fn serial()
+{
+    for a in [0, 1, 1000, 32_767, 32_768, 65_000, u16::MAX]
+    {
+        for distance in [1, 10, 1000, 32_767]
+        {
+            let b = a.wrapping_add(distance);
+            assert_eq!(serial_diff(b, a), distance as i16);
+            assert_eq!(serial_diff(a, b), -(distance as i16));
+            assert_eq!(serial_cmp(b, a), Ordering::Greater);
+            assert_eq!(serial_cmp(a, b), Ordering::Less);
+        }
+        assert_eq!(serial_diff(a, a), 0);
+        assert_eq!(serial_cmp(a, a), Ordering::Equal);
+    }
+}
@@ -3,6 +3,10 @@
 #![feature(unboxed_closures)]
 
 // Tests
+mod ciphers;
+
+mod clock;
+
 mod sender_receiver;
 
 mod client_server;
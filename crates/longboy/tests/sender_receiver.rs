@@ -1,9 +1,14 @@
 use std::sync::{
     atomic::{AtomicU64, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 
-use longboy::{Constants, Receiver, Sender, Sink, Source};
+use longboy::{
+    ChaCha20Poly1305Cipher, Cipher, Constants, Delivery, DynReceiver, DynSender, DynSink, DynSource, Encoding, Instant,
+    MetadataSink, Mirroring, NullCipher, Rc5Cipher, Receiver, ReceiverStats, Redundancy, Sender, Sink, SinkMetadata,
+    SnapshotReceiver, SnapshotSender, SnapshotSenderStats, SnapshotSink, SnapshotSource, SnapshotStats, Source,
+    VariableSink, VariableSource, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 
 struct TestSource
 {
@@ -12,6 +17,16 @@ struct TestSource
     period: u64,
 }
 
+struct TestZeroSource
+{
+    counter: Arc<AtomicU64>,
+}
+
+struct TestVariableSource
+{
+    counter: Arc<AtomicU64>,
+}
+
 struct TestSink
 {
     counter: Arc<AtomicU64>,
@@ -36,6 +51,27 @@ impl<const SIZE: usize> Source<SIZE> for TestSource
     }
 }
 
+impl<const SIZE: usize> Source<SIZE> for TestZeroSource
+{
+    fn poll(&mut self, buffer: &mut [u8; SIZE]) -> bool
+    {
+        self.counter.fetch_add(1, Ordering::Relaxed);
+        buffer.fill(0);
+        true
+    }
+}
+
+impl<const SIZE: usize> VariableSource<SIZE> for TestVariableSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed) + 1;
+        let length = (counter as usize) % (SIZE + 1);
+        buffer.resize(length, length as u8);
+        true
+    }
+}
+
 impl<const SIZE: usize> Sink<SIZE> for TestSink
 {
     fn handle(&mut self, buffer: &[u8; SIZE])
@@ -46,68 +82,242 @@ impl<const SIZE: usize> Sink<SIZE> for TestSink
     }
 }
 
-macro_rules! test {
-    ($func:ident) => {
-        mod $func
-        {
-            #[test]
-            fn size_8_window_size_1()
-            {
-                super::$func::<8, 1>()
-            }
+struct TestVariableSink
+{
+    counter: Arc<AtomicU64>,
+    handled: Arc<AtomicU64>,
+}
 
-            #[test]
-            fn size_16_window_size_1()
-            {
-                super::$func::<16, 1>()
-            }
+impl<const SIZE: usize> VariableSink<SIZE> for TestVariableSink
+{
+    fn handle(&mut self, buffer: &[u8])
+    {
+        assert!(buffer.len() <= SIZE);
+        assert!(buffer.iter().all(|byte| *byte == buffer.len() as u8));
+        self.counter.fetch_add(buffer.len() as u64, Ordering::Relaxed);
+        self.handled.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-            #[test]
-            fn size_32_window_size_1()
-            {
-                super::$func::<32, 1>()
-            }
+struct TestDynSource
+{
+    counter: Arc<AtomicU64>,
+    size: usize,
+}
 
-            #[test]
-            fn size_64_window_size_1()
-            {
-                super::$func::<64, 1>()
-            }
+impl DynSource for TestDynSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed) + 1;
+        let length = (counter as usize) % (self.size + 1);
+        buffer.resize(length, length as u8);
+        true
+    }
+}
 
-            #[test]
-            fn size_128_window_size_1()
-            {
-                super::$func::<128, 1>()
-            }
+struct TestDynSink
+{
+    counter: Arc<AtomicU64>,
+    handled: Arc<AtomicU64>,
+    size: usize,
+}
 
-            #[test]
-            fn size_8_window_size_3()
-            {
-                super::$func::<8, 3>()
-            }
+impl DynSink for TestDynSink
+{
+    fn handle(&mut self, _metadata: &SinkMetadata, buffer: &[u8])
+    {
+        assert!(buffer.len() <= self.size);
+        assert!(buffer.iter().all(|byte| *byte == buffer.len() as u8));
+        self.counter.fetch_add(buffer.len() as u64, Ordering::Relaxed);
+        self.handled.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-            #[test]
-            fn size_16_window_size_3()
+struct TestMetadataSink
+{
+    handled: Arc<Mutex<Vec<(SinkMetadata, u64)>>>,
+    skipped: Arc<Mutex<Vec<u64>>>,
+}
+
+impl<const SIZE: usize> MetadataSink<SIZE> for TestMetadataSink
+{
+    fn handle(&mut self, metadata: &SinkMetadata, buffer: &[u8])
+    {
+        let counter = u64::from_le_bytes(*buffer.first_chunk().unwrap());
+        self.handled.lock().unwrap().push((*metadata, counter));
+    }
+
+    fn skipped(&mut self, cycle: u64)
+    {
+        self.skipped.lock().unwrap().push(cycle);
+    }
+}
+
+struct TestSkippedSink
+{
+    handled: Arc<Mutex<Vec<u64>>>,
+    skipped: Arc<Mutex<Vec<u64>>>,
+}
+
+impl<const SIZE: usize> Sink<SIZE> for TestSkippedSink
+{
+    fn handle(&mut self, buffer: &[u8; SIZE])
+    {
+        let counter = u64::from_le_bytes(*buffer.first_chunk().unwrap());
+        self.handled.lock().unwrap().push(counter);
+    }
+
+    fn skipped(&mut self, cycle: u64)
+    {
+        self.skipped.lock().unwrap().push(cycle);
+    }
+}
+
+struct TestSnapshotSource
+{
+    snapshots: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl SnapshotSource for TestSnapshotSource
+{
+    fn poll(&mut self, buffer: &mut Vec<u8>) -> bool
+    {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        match snapshots.is_empty()
+        {
+            true => false,
+            false =>
             {
-                super::$func::<16, 3>()
+                buffer.extend_from_slice(&snapshots.remove(0));
+                true
             }
+        }
+    }
+}
+
+struct TestSnapshotSink
+{
+    handled: Arc<Mutex<Vec<u64>>>,
+    snapshot: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SnapshotSink for TestSnapshotSink
+{
+    fn handle(&mut self, sequence: u64, snapshot: &[u8])
+    {
+        self.handled.lock().unwrap().push(sequence);
+        *self.snapshot.lock().unwrap() = snapshot.to_vec();
+    }
+}
+
+fn test_snapshot(sequence: u64, length: usize) -> Vec<u8>
+{
+    (0..length)
+        .map(|i| (i as u64).wrapping_mul(31).wrapping_add(sequence) as u8)
+        .collect()
+}
+
+macro_rules! test {
+    ($func:ident) => {
+        test!($func: null => NullCipher, rc5 => Rc5Cipher, chacha20_poly1305 => ChaCha20Poly1305Cipher);
+    };
+    ($func:ident: $($cipher_name:ident => $cipher:ident),+) => {
+        mod $func
+        {
+            $(
+                mod $cipher_name
+                {
+                    use super::super::$cipher;
+
+                    #[test]
+                    fn size_8_window_size_1()
+                    {
+                        super::super::$func::<$cipher, 8, 1>()
+                    }
+
+                    #[test]
+                    fn size_16_window_size_1()
+                    {
+                        super::super::$func::<$cipher, 16, 1>()
+                    }
+
+                    #[test]
+                    fn size_32_window_size_1()
+                    {
+                        super::super::$func::<$cipher, 32, 1>()
+                    }
+
+                    #[test]
+                    fn size_64_window_size_1()
+                    {
+                        super::super::$func::<$cipher, 64, 1>()
+                    }
+
+                    #[test]
+                    fn size_128_window_size_1()
+                    {
+                        super::super::$func::<$cipher, 128, 1>()
+                    }
+
+                    #[test]
+                    fn size_8_window_size_3()
+                    {
+                        super::super::$func::<$cipher, 8, 3>()
+                    }
+
+                    #[test]
+                    fn size_16_window_size_3()
+                    {
+                        super::super::$func::<$cipher, 16, 3>()
+                    }
+
+                    #[test]
+                    fn size_32_window_size_3()
+                    {
+                        super::super::$func::<$cipher, 32, 3>()
+                    }
+
+                    #[test]
+                    fn size_64_window_size_3()
+                    {
+                        super::super::$func::<$cipher, 64, 3>()
+                    }
+
+                    #[test]
+                    fn size_128_window_size_3()
+                    {
+                        super::super::$func::<$cipher, 128, 3>()
+                    }
+                }
+            )+
+        }
+    };
+}
+
+// Snapshots are sized at runtime, so they only need going over once per cipher.
+macro_rules! cipher_test {
+    ($func:ident) => {
+        mod $func
+        {
+            use super::{ChaCha20Poly1305Cipher, NullCipher, Rc5Cipher};
 
             #[test]
-            fn size_32_window_size_3()
+            fn null()
             {
-                super::$func::<32, 3>()
+                super::$func::<NullCipher>()
             }
 
             #[test]
-            fn size_64_window_size_3()
+            fn rc5()
             {
-                super::$func::<64, 3>()
+                super::$func::<Rc5Cipher>()
             }
 
             #[test]
-            fn size_128_window_size_3()
+            fn chacha20_poly1305()
             {
-                super::$func::<128, 3>()
+                super::$func::<ChaCha20Poly1305Cipher>()
             }
         }
     };
@@ -119,11 +329,75 @@ test!(out_of_order);
 test!(lost_in_transmission);
 test!(cycle_wrapping);
 test!(sparse);
+test!(zeroed);
+test!(variable);
+test!(budgeted);
+test!(dynamic);
+test!(delta);
+test!(stats);
+test!(metadata);
+test!(ordered);
+test!(buffered);
+test!(wraparound);
+test!(unsynchronized);
+test!(skipped);
+test!(keepalive);
+test!(acknowledged);
+test!(parity);
+test!(parity_idle);
+test!(replayed);
+test!(replayed_after_wrap);
+test!(rekeyed);
+test!(versioned);
+test!(channels);
+test!(garbage);
+cipher_test!(snapshots);
+cipher_test!(snapshot_deltas);
+cipher_test!(snapshot_outage);
+cipher_test!(snapshot_rekeyed);
+test!(tampered: chacha20_poly1305 => ChaCha20Poly1305Cipher);
+
+// Leaving the cipher out picks RC5, as it was before ciphers could be chosen.
+#[test]
+fn default_cipher()
+{
+    assert_eq!(
+        <Constants<16, 3>>::DATAGRAM_SIZE,
+        <Constants<16, 3, Rc5Cipher>>::DATAGRAM_SIZE
+    );
+
+    let key = 0xDEADBEEFDEADBEEF;
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let mut sender: Sender<TestSource, 16, 3> = Sender::new(
+        key,
+        TestSource {
+            counter: Arc::new(AtomicU64::new(0)),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, 16, 3, Rc5Cipher> = Receiver::new(
+        key,
+        TestSink {
+            counter: Arc::new(AtomicU64::new(0)),
+            handled: handled_counter.clone(),
+        },
+    );
+    for i in 0..16
+    {
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(0).unwrap());
+        receiver.handle_datagram(0, &mut datagram);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), i + 1);
+    }
+    assert_eq!(receiver.stats().authentication_failures, 0);
+}
 
-fn golden<const SIZE: usize, const WINDOW_SIZE: usize>()
+fn golden<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
 {
     let key = 0xDEADBEEFDEADBEEF;
 
@@ -133,7 +407,7 @@ where
 
     let timestamp = 0;
 
-    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
         key,
         TestSource {
             counter: source_counter.clone(),
@@ -141,7 +415,7 @@ where
             period: 1,
         },
     );
-    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
         key,
         TestSink {
             counter: sink_counter.clone(),
@@ -154,7 +428,7 @@ where
 
     for i in 0..1024
     {
-        let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
         assert_eq!(sender.cycle(), i + 1);
         assert_eq!(receiver.cycle(), i);
         assert_eq!(source_counter.load(Ordering::Relaxed), (i as u64) + 1);
@@ -170,10 +444,11 @@ where
     }
 }
 
-fn mirroring<const SIZE: usize, const WINDOW_SIZE: usize>()
+fn mirroring<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
 {
     let key = 0xDEADBEEFDEADBEEF;
 
@@ -183,7 +458,7 @@ where
 
     let timestamp = 0;
 
-    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
         key,
         TestSource {
             counter: source_counter.clone(),
@@ -191,7 +466,7 @@ where
             period: 1,
         },
     );
-    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
         key,
         TestSink {
             counter: sink_counter.clone(),
@@ -204,7 +479,7 @@ where
 
     for i in 0..1024
     {
-        let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
         assert_eq!(sender.cycle(), i + 1);
         assert_eq!(receiver.cycle(), i);
         assert_eq!(source_counter.load(Ordering::Relaxed), (i as u64) + 1);
@@ -222,14 +497,15 @@ where
     }
 }
 
-fn out_of_order<const SIZE: usize, const WINDOW_SIZE: usize>()
+fn out_of_order<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
 {
     // Alias constants so they're less painful to read.
     #[allow(non_snake_case)]
-    let MAX_BUFFERED: usize = Constants::<SIZE, WINDOW_SIZE>::MAX_BUFFERED;
+    let MAX_BUFFERED: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::MAX_BUFFERED;
 
     // Inside of window.
     {
@@ -241,7 +517,7 @@ where
 
         let timestamp = 0;
 
-        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
             key,
             TestSource {
                 counter: source_counter.clone(),
@@ -249,7 +525,7 @@ where
                 period: 1,
             },
         );
-        let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+        let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
             key,
             TestSink {
                 counter: sink_counter.clone(),
@@ -260,7 +536,7 @@ where
         assert_eq!(sender.cycle(), 0);
         assert_eq!(receiver.cycle(), 0);
 
-        let mut datagrams = [0; WINDOW_SIZE].map(|_| Box::new(*sender.poll_datagram(timestamp).unwrap()));
+        let mut datagrams = [0; WINDOW_SIZE].map(|_| Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap()));
         assert_eq!(sender.cycle(), WINDOW_SIZE);
         assert_eq!(receiver.cycle(), 0);
         assert_eq!(source_counter.load(Ordering::Relaxed), WINDOW_SIZE as u64);
@@ -288,7 +564,7 @@ where
 
         let timestamp = 0;
 
-        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
             key,
             TestSource {
                 counter: source_counter.clone(),
@@ -296,7 +572,7 @@ where
                 period: 1,
             },
         );
-        let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+        let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
             key,
             TestSink {
                 counter: sink_counter.clone(),
@@ -307,8 +583,8 @@ where
         assert_eq!(sender.cycle(), 0);
         assert_eq!(receiver.cycle(), 0);
 
-        let mut datagrams = [0; <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]
-            .map(|_| Box::new(*sender.poll_datagram(timestamp).unwrap()));
+        let mut datagrams = [0; <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]
+            .map(|_| Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap()));
         assert_eq!(sender.cycle(), MAX_BUFFERED);
         assert_eq!(receiver.cycle(), 0);
         assert_eq!(source_counter.load(Ordering::Relaxed), (MAX_BUFFERED as u64));
@@ -339,10 +615,11 @@ where
     }
 }
 
-fn lost_in_transmission<const SIZE: usize, const WINDOW_SIZE: usize>()
+fn lost_in_transmission<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
 {
     let key = 0xDEADBEEFDEADBEEF;
 
@@ -352,7 +629,7 @@ where
 
     let timestamp = 0;
 
-    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
         key,
         TestSource {
             counter: source_counter.clone(),
@@ -360,7 +637,7 @@ where
             period: 1,
         },
     );
-    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
         key,
         TestSink {
             counter: sink_counter.clone(),
@@ -380,7 +657,7 @@ where
     assert_eq!(source_counter.load(Ordering::Relaxed), 128);
     assert_eq!(sink_counter.load(Ordering::Relaxed), 0);
 
-    let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
     assert_eq!(sender.cycle(), 129);
     assert_eq!(receiver.cycle(), 0);
     assert_eq!(source_counter.load(Ordering::Relaxed), 129);
@@ -395,10 +672,11 @@ where
     assert_eq!(handled_counter.load(Ordering::Relaxed), WINDOW_SIZE as u64);
 }
 
-fn cycle_wrapping<const SIZE: usize, const WINDOW_SIZE: usize>()
+fn cycle_wrapping<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
 {
     let key = 0xDEADBEEFDEADBEEF;
 
@@ -408,7 +686,7 @@ where
 
     let timestamp = 0;
 
-    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
         key,
         TestSource {
             counter: source_counter.clone(),
@@ -416,7 +694,7 @@ where
             period: 1,
         },
     );
-    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
         key,
         TestSink {
             counter: sink_counter.clone(),
@@ -429,7 +707,7 @@ where
 
     for _ in 0..(65535 - 1)
     {
-        let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
         receiver.handle_datagram(timestamp, &mut datagram);
     }
     assert_eq!(sender.cycle(), 65534);
@@ -438,7 +716,7 @@ where
     assert_eq!(sink_counter.load(Ordering::Relaxed), 65534);
     assert_eq!(handled_counter.load(Ordering::Relaxed), 65534);
 
-    let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
     assert_eq!(sender.cycle(), 0);
     assert_eq!(receiver.cycle(), 65534);
     assert_eq!(source_counter.load(Ordering::Relaxed), 65535);
@@ -452,7 +730,7 @@ where
     assert_eq!(sink_counter.load(Ordering::Relaxed), 65535);
     assert_eq!(handled_counter.load(Ordering::Relaxed), 65535);
 
-    let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
     assert_eq!(sender.cycle(), 1);
     assert_eq!(receiver.cycle(), 0);
     assert_eq!(source_counter.load(Ordering::Relaxed), 65536);
@@ -467,10 +745,11 @@ where
     assert_eq!(handled_counter.load(Ordering::Relaxed), 65536);
 }
 
-fn sparse<const SIZE: usize, const WINDOW_SIZE: usize>()
+fn sparse<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
 where
-    [(); <Constants<SIZE, WINDOW_SIZE>>::DATAGRAM_SIZE]:,
-    [(); <Constants<SIZE, WINDOW_SIZE>>::MAX_BUFFERED]:,
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
 {
     let key = 0xDEADBEEFDEADBEEF;
 
@@ -482,7 +761,7 @@ where
 
     let timestamp = 0;
 
-    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE> = Sender::new(
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
         key,
         TestSource {
             counter: source_counter.clone(),
@@ -490,7 +769,7 @@ where
             period,
         },
     );
-    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE> = Receiver::new(
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
         key,
         TestSink {
             counter: sink_counter.clone(),
@@ -520,7 +799,7 @@ where
     {
         accumulator += 1;
 
-        let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
         receiver.handle_datagram(timestamp, &mut datagram);
         assert_eq!(sender.cycle(), 1 + i);
         assert_eq!(receiver.cycle(), 1 + i);
@@ -546,7 +825,7 @@ where
     {
         accumulator += 1;
 
-        let mut datagram = Box::new(*sender.poll_datagram(timestamp).unwrap());
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
         receiver.handle_datagram(timestamp, &mut datagram);
         assert_eq!(sender.cycle(), WINDOW_SIZE + 1 + i);
         assert_eq!(receiver.cycle(), WINDOW_SIZE + 1 + i);
@@ -555,3 +834,2282 @@ where
         assert_eq!(handled_counter.load(Ordering::Relaxed), 2);
     }
 }
+
+fn replayed<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    let datagrams = (0..4)
+        .map(|_| Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap()))
+        .collect::<Vec<_>>();
+
+    // Ahead of the receiver's cycle, so only the replay window can tell the copies apart.
+    receiver.handle_datagram(timestamp, &mut datagrams[3].clone());
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 4);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), WINDOW_SIZE as u64);
+
+    receiver.handle_datagram(timestamp, &mut datagrams[3].clone());
+    receiver.handle_datagram(timestamp, &mut datagrams[3].clone());
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 4);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), WINDOW_SIZE as u64);
+    assert_eq!(receiver.stats().datagrams_accepted, 1);
+    assert_eq!(receiver.stats().datagrams_replayed, 2);
+
+    // Lanes are tracked apart, so a mirrored copy is only a duplicate.
+    receiver.handle_datagram(
+        timestamp,
+        &mut Box::<[u8]>::from(sender.mirror_datagram(Mirroring::Voice)),
+    );
+    assert_eq!(handled_counter.load(Ordering::Relaxed), WINDOW_SIZE as u64);
+    assert_eq!(receiver.stats().datagrams_accepted, 2);
+    assert_eq!(receiver.stats().datagrams_replayed, 2);
+
+    // Older datagrams inside the window are still fresh.
+    receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+    assert_eq!(
+        handled_counter.load(Ordering::Relaxed),
+        std::cmp::min(WINDOW_SIZE + 1, 4) as u64
+    );
+    assert_eq!(receiver.stats().datagrams_accepted, 3);
+    assert_eq!(receiver.stats().datagrams_replayed, 2);
+}
+
+fn replayed_after_wrap<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    // Alias constants so they're less painful to read.
+    #[allow(non_snake_case)]
+    let MAX_CYCLE: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::MAX_CYCLE;
+
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    let first = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    receiver.handle_datagram(timestamp, &mut first.clone());
+    for _ in 1..MAX_CYCLE
+    {
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        receiver.handle_datagram(timestamp, &mut datagram);
+    }
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), MAX_CYCLE as u64);
+
+    // Back on the first datagram's cycle, it still belongs to the time around before, whichever
+    // cipher sealed it.
+    receiver.handle_datagram(timestamp, &mut first.clone());
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), MAX_CYCLE as u64);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), MAX_CYCLE as u64);
+    assert_eq!(receiver.stats().header_check_failures, 1);
+    assert_eq!(receiver.stats().datagrams_accepted, MAX_CYCLE as u64);
+
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    receiver.handle_datagram(timestamp, &mut datagram);
+    assert_eq!(receiver.cycle(), 1);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), (MAX_CYCLE as u64) + 1);
+}
+
+fn rekeyed<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let next_key = 0xBEEFDEADBEEFDEAD;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    )
+    .with_key_grace_period(100);
+
+    // Sealed under the old key before the Sender hears about the new one.
+    let datagram_0 = Box::<[u8]>::from(sender.poll_datagram(0).unwrap());
+    let datagram_1 = Box::<[u8]>::from(sender.poll_datagram(1000).unwrap());
+    let datagram_2 = Box::<[u8]>::from(sender.poll_datagram(1050).unwrap());
+    receiver.handle_datagram(0, &mut datagram_0.clone());
+    receiver.rekey(1, next_key);
+
+    // The old key holds for as long as the Sender hasn't been heard from under the new one,
+    // however long it takes to hear about it.
+    receiver.poll(1000);
+    receiver.handle_datagram(1000, &mut datagram_1.clone());
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 2);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), 2);
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 0);
+
+    // Heartbeats are checked under whichever key the Sender is on.
+    let heartbeat = sender.heartbeat_datagram(1, Mirroring::Background);
+    assert!(matches!(
+        receiver.check_heartbeat(1, &heartbeat),
+        Some(Mirroring::Background)
+    ));
+
+    // Acknowledgements under the new key check out once it's been announced to the Sender, ahead
+    // of it switching over.
+    let acknowledgement = receiver.acknowledgement_datagram(1, Mirroring::AudioVideo);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), None);
+    sender.announce_key(1, next_key);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), Some(2));
+
+    // The new key picks up where the old one left off, and from then on the old one only holds
+    // for the grace period.
+    sender.rekey(1, next_key);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), Some(2));
+    assert!(receiver.check_heartbeat(1, &heartbeat).is_some());
+    assert!(receiver
+        .check_heartbeat(1, &sender.heartbeat_datagram(1, Mirroring::Background))
+        .is_some());
+    let datagram_3 = Box::<[u8]>::from(sender.poll_datagram(1100).unwrap());
+    receiver.handle_datagram(1100, &mut datagram_3.clone());
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 4);
+    assert_eq!(
+        handled_counter.load(Ordering::Relaxed),
+        std::cmp::min(WINDOW_SIZE + 2, 4) as u64
+    );
+    assert_eq!(receiver.stats().datagrams_accepted, 3);
+
+    receiver.handle_datagram(1150, &mut datagram_2.clone());
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 0);
+    receiver.handle_datagram(1200, &mut datagram_2.clone());
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 1);
+
+    // Keys replaced before the Sender switches over are all kept, since it may still be using
+    // any of them.
+    let accepted = receiver.stats().datagrams_accepted;
+    let datagram_4 = Box::<[u8]>::from(sender.poll_datagram(1200).unwrap());
+    receiver.rekey(2, key);
+    receiver.rekey(3, next_key ^ key);
+    receiver.handle_datagram(1200, &mut datagram_4.clone());
+    assert_eq!(receiver.stats().datagrams_accepted, accepted + 1);
+
+    sender.announce_key(2, key);
+    sender.announce_key(3, next_key ^ key);
+    sender.rekey(3, next_key ^ key);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), None);
+    let datagram_5 = Box::<[u8]>::from(sender.poll_datagram(1250).unwrap());
+    let datagram_6 = Box::<[u8]>::from(sender.poll_datagram(1300).unwrap());
+    receiver.handle_datagram(1250, &mut datagram_5.clone());
+    assert_eq!(receiver.stats().datagrams_accepted, accepted + 2);
+
+    // Timestamps that go back on themselves have been all the way around, well past the grace
+    // period.
+    receiver.poll(1300);
+    receiver.poll(1260);
+    sender.rekey(1, next_key);
+    let datagram_7 = Box::<[u8]>::from(sender.poll_datagram(1350).unwrap());
+    receiver.handle_datagram(1260, &mut datagram_7.clone());
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 2);
+
+    // Epochs the Receiver hasn't been told about are dropped.
+    sender.rekey(4, key);
+    let datagram_8 = Box::<[u8]>::from(sender.poll_datagram(1400).unwrap());
+    receiver.handle_datagram(1260, &mut datagram_8.clone());
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 3);
+
+    receiver.handle_datagram(1260, &mut datagram_6.clone());
+    assert_eq!(receiver.stats().datagrams_accepted, accepted + 3);
+    assert_eq!(receiver.stats().authentication_failures, 0);
+}
+
+fn versioned<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    let datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    assert_eq!(datagram[0], PROTOCOL_VERSION);
+
+    // Versions we don't know are turned away whatever follows them, even if it's too short to
+    // be anything else.
+    for version in [MIN_PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1, u8::MAX]
+    {
+        let mut unsupported = datagram.clone();
+        unsupported[0] = version;
+        receiver.handle_datagram(timestamp, &mut unsupported);
+        receiver.handle_datagram(timestamp, &mut [version]);
+    }
+    receiver.handle_datagram(timestamp, &mut []);
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 0);
+    assert_eq!(receiver.stats().datagrams_unsupported_version, 6);
+    assert_eq!(receiver.stats().datagrams_malformed, 1);
+
+    receiver.handle_datagram(timestamp, &mut datagram.clone());
+    assert_eq!(receiver.cycle(), 1);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 1);
+    assert_eq!(receiver.stats().datagrams_accepted, 1);
+}
+
+fn channels<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let other_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    )
+    .with_channel(1);
+    let mut other_sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: other_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    )
+    .with_channel(2);
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    )
+    .with_channel(1);
+
+    // Datagrams for another channel are turned away before anything else is looked at, even
+    // under the same key.
+    let mut datagram = Box::<[u8]>::from(other_sender.poll_datagram(timestamp).unwrap());
+    assert_eq!(datagram[1], 2);
+    receiver.handle_datagram(timestamp, &mut datagram);
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 0);
+    assert_eq!(receiver.stats().datagrams_unknown_channel, 1);
+    assert_eq!(receiver.stats().header_check_failures, 0);
+
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    assert_eq!(datagram[1], 1);
+    receiver.handle_datagram(timestamp, &mut datagram);
+    assert_eq!(receiver.cycle(), 1);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 1);
+    assert_eq!(receiver.stats().datagrams_accepted, 1);
+    assert_eq!(receiver.stats().datagrams_unknown_channel, 1);
+}
+
+fn garbage<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    // Garbage the size of a datagram, with a version, key epoch and lane we'd accept, between
+    // every real datagram.  Nearly all of it fails the header check, and what slips past by
+    // chance is still turned away before it can move cycles along or reach the sink.
+    let mut state = 0x2545F4914F6CDD1Du64;
+    for i in 0..256
+    {
+        for _ in 0..16
+        {
+            let mut garbage = vec![0; Constants::<SIZE, WINDOW_SIZE, CipherType>::DATAGRAM_SIZE];
+            for byte in garbage.iter_mut()
+            {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *byte = state as u8;
+            }
+            garbage[0] = PROTOCOL_VERSION;
+            garbage[1] = 0;
+            garbage[7] = 0;
+            garbage[8] = (state % 3) as u8;
+            receiver.handle_datagram(timestamp, &mut garbage);
+            assert_eq!(receiver.cycle(), i);
+            assert_eq!(handled_counter.load(Ordering::Relaxed), i as u64);
+        }
+        let stats = receiver.stats();
+        assert_eq!(stats.datagrams_accepted, i as u64);
+        assert_eq!(
+            stats.header_check_failures
+                + stats.datagrams_stale
+                + stats.datagrams_malformed
+                + stats.authentication_failures,
+            ((i as u64) + 1) * 16
+        );
+
+        receiver.handle_datagram(timestamp, &mut sender.poll_datagram(timestamp).unwrap().to_vec());
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), (i as u64) + 1);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+    assert_eq!(receiver.stats().datagrams_accepted, 256);
+    assert!(receiver.stats().header_check_failures >= (256 * 16) - 4);
+}
+
+fn tampered<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    assert_eq!(sender.cycle(), 0);
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(receiver.stats().authentication_failures, 0);
+
+    for i in 0..1024
+    {
+        let datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+
+        // Flip a bit somewhere past the header, walking over the whole window and tag.  A
+        // flipped key epoch, or anything else flipped in the header, is turned away before it
+        // gets to authentication.
+        let mut tampered = datagram.clone();
+        tampered[4 + (i % (tampered.len() - 4))] ^= 0x01;
+        receiver.handle_datagram(timestamp, &mut tampered);
+        let stats = receiver.stats();
+        assert_eq!(receiver.cycle(), i);
+        assert_eq!(
+            stats.authentication_failures + stats.datagrams_unknown_epoch + stats.header_check_failures,
+            (i as u64) + 1
+        );
+        assert_eq!(sink_counter.load(Ordering::Relaxed), i as u64);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), i as u64);
+
+        receiver.handle_datagram(timestamp, &mut datagram.clone());
+        let stats = receiver.stats();
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(
+            stats.authentication_failures + stats.datagrams_unknown_epoch + stats.header_check_failures,
+            (i as u64) + 1
+        );
+        assert_eq!(sink_counter.load(Ordering::Relaxed), (i as u64) + 1);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+
+    // A different key never gets past the header check.
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        !key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    receiver.handle_datagram(timestamp, &mut datagram);
+    assert_eq!(receiver.cycle(), 0);
+    assert_eq!(receiver.stats().header_check_failures, 1);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), 1024);
+}
+
+fn zeroed<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestZeroSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestZeroSource {
+            counter: source_counter.clone(),
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    assert_eq!(sender.cycle(), 0);
+    assert_eq!(receiver.cycle(), 0);
+
+    for i in 0..1024
+    {
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        receiver.handle_datagram(timestamp, &mut datagram);
+        assert_eq!(sender.cycle(), i + 1);
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(source_counter.load(Ordering::Relaxed), (i as u64) + 1);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), 0);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+}
+
+fn variable<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    // Alias constants so they're less painful to read.
+    #[allow(non_snake_case)]
+    let DATAGRAM_SIZE: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::DATAGRAM_SIZE;
+
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestVariableSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestVariableSource {
+            counter: source_counter.clone(),
+        },
+    );
+    let mut receiver: Receiver<TestVariableSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestVariableSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    assert_eq!(sender.cycle(), 0);
+    assert_eq!(receiver.cycle(), 0);
+
+    let mut total_length = 0;
+    for i in 0..1024
+    {
+        total_length += ((i as u64) + 1) % ((SIZE as u64) + 1);
+
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        assert!(datagram.len() <= DATAGRAM_SIZE);
+
+        receiver.handle_datagram(timestamp, &mut datagram);
+        assert_eq!(sender.cycle(), i + 1);
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(source_counter.load(Ordering::Relaxed), (i as u64) + 1);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), total_length);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+
+    // Truncated datagrams are dropped without touching the receiver.
+    let datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    receiver.handle_datagram(timestamp, &mut datagram[0..4].to_vec());
+    assert_eq!(receiver.cycle(), 1024);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), 1024);
+    assert_eq!(receiver.stats().slots_wrong_length, 0);
+
+    // Fixed size sinks only see input of exactly their size, and the rest is counted.
+    let mut sender: Sender<TestVariableSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestVariableSource {
+            counter: Arc::new(AtomicU64::new(0)),
+        },
+    );
+    let handled_counter = Arc::new(AtomicU64::new(0));
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: Arc::new(AtomicU64::new(0)),
+            handled: handled_counter.clone(),
+        },
+    );
+    let mut fixed = 0;
+    for i in 0..1024
+    {
+        if ((i as u64) + 1) % ((SIZE as u64) + 1) == (SIZE as u64)
+        {
+            fixed += 1;
+        }
+
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        receiver.handle_datagram(timestamp, &mut datagram);
+    }
+    assert_eq!(handled_counter.load(Ordering::Relaxed), fixed);
+    assert_eq!(receiver.stats().slots_wrong_length, 1024 - fixed);
+}
+
+fn budgeted<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    // The smallest budget only fits the newest slot, which still always makes it through.
+    let max_datagram_size = Constants::<SIZE, WINDOW_SIZE, CipherType>::min_datagram_size(Redundancy::Repetition);
+
+    let mut sender: Sender<TestVariableSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestVariableSource {
+            counter: source_counter.clone(),
+        },
+    )
+    .with_max_datagram_size(max_datagram_size);
+    let mut receiver: Receiver<TestVariableSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestVariableSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    let mut total_length = 0;
+    for i in 0..1024
+    {
+        total_length += ((i as u64) + 1) % ((SIZE as u64) + 1);
+
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        assert!(datagram.len() <= max_datagram_size);
+
+        receiver.handle_datagram(timestamp, &mut datagram);
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), total_length);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+}
+
+fn dynamic<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    for redundancy in [
+        Redundancy::Repetition,
+        Redundancy::Parity {
+            group: 2,
+            interleave: 1,
+        },
+    ]
+    {
+        let source_counters = [Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0))];
+        let sink_counters = [Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0))];
+        let handled_counters = [Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0))];
+
+        let timestamp = 0;
+
+        let mut sender: Sender<TestVariableSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+            key,
+            TestVariableSource {
+                counter: source_counters[0].clone(),
+            },
+        )
+        .with_encoding(Encoding::Delta)
+        .with_redundancy(redundancy);
+        let mut dyn_sender: DynSender<TestDynSource, CipherType> = DynSender::new(
+            key,
+            SIZE,
+            WINDOW_SIZE,
+            TestDynSource {
+                counter: source_counters[1].clone(),
+                size: SIZE,
+            },
+        )
+        .with_encoding(Encoding::Delta)
+        .with_redundancy(redundancy);
+        let mut receiver: Receiver<TestVariableSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+            key,
+            TestVariableSink {
+                counter: sink_counters[0].clone(),
+                handled: handled_counters[0].clone(),
+            },
+        );
+        let mut dyn_receiver: DynReceiver<TestDynSink, CipherType> = DynReceiver::new(
+            key,
+            SIZE,
+            WINDOW_SIZE,
+            TestDynSink {
+                counter: sink_counters[1].clone(),
+                handled: handled_counters[1].clone(),
+                size: SIZE,
+            },
+        );
+        assert_eq!(*dyn_sender.layout(), Constants::<SIZE, WINDOW_SIZE, CipherType>::LAYOUT);
+        assert_eq!(
+            *dyn_receiver.layout(),
+            Constants::<SIZE, WINDOW_SIZE, CipherType>::LAYOUT
+        );
+
+        // Both put the same bytes on the wire, and each takes the other's datagrams.
+        let mut total_length = 0;
+        for i in 0..1024
+        {
+            total_length += ((i as u64) + 1) % ((SIZE as u64) + 1);
+
+            let datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+            let dyn_datagram = Box::<[u8]>::from(dyn_sender.poll_datagram(timestamp).unwrap());
+            assert_eq!(datagram, dyn_datagram);
+            for mirroring in [Mirroring::Background, Mirroring::Voice]
+            {
+                assert_eq!(sender.mirror_datagram(mirroring), dyn_sender.mirror_datagram(mirroring));
+            }
+            receiver.handle_datagram(timestamp, &mut dyn_datagram.to_vec());
+            dyn_receiver.handle_datagram(timestamp, &mut datagram.to_vec());
+
+            let parity = sender.poll_parity(timestamp).map(Box::<[u8]>::from);
+            let dyn_parity = dyn_sender.poll_parity(timestamp).map(Box::<[u8]>::from);
+            assert_eq!(parity, dyn_parity);
+            if let (Some(parity), Some(dyn_parity)) = (parity, dyn_parity)
+            {
+                receiver.handle_datagram(timestamp, &mut dyn_parity.to_vec());
+                dyn_receiver.handle_datagram(timestamp, &mut parity.to_vec());
+            }
+
+            assert_eq!(receiver.cycle(), i + 1);
+            assert_eq!(dyn_receiver.cycle(), i + 1);
+            for (sink_counter, handled_counter) in sink_counters.iter().zip(handled_counters.iter())
+            {
+                assert_eq!(sink_counter.load(Ordering::Relaxed), total_length);
+                assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+            }
+        }
+        assert_eq!(receiver.stats(), dyn_receiver.stats());
+    }
+}
+
+fn delta<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+    [(); <Constants<SIZE, 16, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, 16, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let full_source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    )
+    .with_encoding(Encoding::Delta);
+    let mut full_sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: full_source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    // Delta encoded datagrams reconstruct the same input, and are never larger.
+    for i in 0..1024
+    {
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        let full_datagram = full_sender.poll_datagram(timestamp).unwrap();
+        assert!(datagram.len() <= full_datagram.len());
+        if WINDOW_SIZE > 1 && i >= WINDOW_SIZE && SIZE > 16
+        {
+            assert!(datagram.len() < full_datagram.len());
+        }
+
+        receiver.handle_datagram(timestamp, &mut datagram);
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), (i as u64) + 1);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+
+    // Variable length input reconstructs against neighbours of different lengths.
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let mut sender: Sender<TestVariableSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestVariableSource {
+            counter: source_counter.clone(),
+        },
+    )
+    .with_encoding(Encoding::Delta);
+    let mut receiver: Receiver<TestVariableSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestVariableSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    let mut total_length = 0;
+    for i in 0..1024
+    {
+        total_length += ((i as u64) + 1) % ((SIZE as u64) + 1);
+
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        receiver.handle_datagram(timestamp, &mut datagram);
+        assert_eq!(receiver.cycle(), i + 1);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), total_length);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+    }
+
+    // Windows too wide to send in full still carry every input when delta encoded.
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let mut sender: Sender<TestSource, SIZE, 16, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    )
+    .with_encoding(Encoding::Delta);
+    let mut receiver: Receiver<TestSink, SIZE, 16, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    for i in 0..1024
+    {
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        if i % 16 == 15
+        {
+            receiver.handle_datagram(timestamp, &mut datagram);
+            assert_eq!(receiver.cycle(), i + 1);
+            assert_eq!(sink_counter.load(Ordering::Relaxed), (i as u64) + 1);
+            assert_eq!(handled_counter.load(Ordering::Relaxed), (i as u64) + 1);
+        }
+    }
+
+    // Without delta encoding the same window may not fit, in which case the slots left out must
+    // not be taken for slots without input.
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let mut sender: Sender<TestSource, SIZE, 16, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, 16, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    for _ in 0..15
+    {
+        sender.poll_datagram(timestamp).unwrap();
+    }
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    receiver.handle_datagram(timestamp, &mut datagram);
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 16);
+    match handled_counter.load(Ordering::Relaxed) == 16
+    {
+        true => assert_eq!(receiver.cycle(), 16),
+        false => assert_eq!(receiver.cycle(), 0),
+    }
+}
+
+fn stats<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    // Alias constants so they're less painful to read.
+    #[allow(non_snake_case)]
+    let MAX_BUFFERED: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::MAX_BUFFERED;
+
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    assert_eq!(receiver.stats(), ReceiverStats::default());
+
+    // In order, with the second datagram repeating the first's slot when windowed.
+    let datagram_0 = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    let datagram_1 = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    receiver.handle_datagram(timestamp, &mut datagram_0.clone());
+    receiver.handle_datagram(timestamp, &mut datagram_1.clone());
+
+    // Already received and truncated.
+    receiver.handle_datagram(timestamp, &mut datagram_0.clone());
+    receiver.handle_datagram(timestamp, &mut datagram_1[0..4].to_vec());
+
+    // Gap past the buffer.
+    let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    for _ in 0..63
+    {
+        datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+    }
+    receiver.handle_datagram(timestamp, &mut datagram);
+    assert_eq!(receiver.cycle(), 66 - MAX_BUFFERED);
+
+    assert_eq!(
+        receiver.stats(),
+        ReceiverStats {
+            datagrams_accepted: 3,
+            datagrams_stale: 1,
+            datagrams_replayed: 0,
+            datagrams_malformed: 1,
+            datagrams_unsupported_version: 0,
+            datagrams_unknown_epoch: 0,
+            datagrams_unknown_channel: 0,
+            header_check_failures: 0,
+            authentication_failures: 0,
+
+            duplicate_slots: std::cmp::min(WINDOW_SIZE - 1, 1) as u64,
+            slots_wrong_length: 0,
+            slots_recovered: 0,
+            cycles_skipped: (64 - MAX_BUFFERED) as u64,
+            cycles_timed_out: 0,
+            soft_warnings: 1,
+            max_cycle_gap: 63,
+            jitter: 0,
+            playout_delay: 0,
+        }
+    );
+}
+
+fn metadata<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    // Alias constants so they're less painful to read.
+    #[allow(non_snake_case)]
+    let MAX_CYCLE: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::MAX_CYCLE;
+
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestMetadataSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestMetadataSink {
+            handled: handled.clone(),
+            skipped: Arc::new(Mutex::new(Vec::new())),
+        },
+    );
+
+    // Only deliver the last datagram of each window, so all but the newest input is recovered
+    // from older slots, and keep going past the cycle wrapping.
+    for i in 0..(MAX_CYCLE + (WINDOW_SIZE * 16))
+    {
+        let timestamp = (i % 1024) as u16;
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp + 100).unwrap());
+        if i % WINDOW_SIZE != WINDOW_SIZE - 1
+        {
+            continue;
+        }
+
+        receiver.handle_datagram(timestamp, &mut datagram);
+        let handled = std::mem::take(&mut *handled.lock().unwrap());
+        assert_eq!(handled.len(), WINDOW_SIZE);
+        for (j, (metadata, counter)) in handled.into_iter().enumerate()
+        {
+            let cycle = ((i + 1) - WINDOW_SIZE + j) as u64;
+            assert_eq!(
+                metadata,
+                SinkMetadata {
+                    cycle,
+                    timestamp: timestamp + 100,
+                    receive_timestamp: timestamp,
+                    age: (WINDOW_SIZE - 1) - j,
+                }
+            );
+            assert_eq!(counter, cycle + 1);
+        }
+    }
+}
+
+fn ordered<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let skipped = Arc::new(Mutex::new(Vec::new()));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestMetadataSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestMetadataSink {
+            handled: handled.clone(),
+            skipped: skipped.clone(),
+        },
+    )
+    .with_delivery(Delivery::Ordered { deadline: 10 });
+
+    let datagrams = (0..(WINDOW_SIZE * 4))
+        .map(|_| Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap()))
+        .collect::<Vec<_>>();
+    let handled_cycles = || {
+        std::mem::take(&mut *handled.lock().unwrap())
+            .into_iter()
+            .map(|(metadata, counter)| {
+                assert_eq!(counter, metadata.cycle + 1);
+                metadata.cycle
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Newer window first is held until the older window arrives, then both release in order.
+    receiver.handle_datagram(timestamp, &mut datagrams[(WINDOW_SIZE * 2) - 1].clone());
+    assert_eq!(receiver.cycle(), 0);
+    assert!(handled_cycles().is_empty());
+
+    receiver.handle_datagram(timestamp, &mut datagrams[WINDOW_SIZE - 1].clone());
+    assert_eq!(receiver.cycle(), WINDOW_SIZE * 2);
+    assert_eq!(handled_cycles(), (0..((WINDOW_SIZE as u64) * 2)).collect::<Vec<_>>());
+
+    // A window that never arrives is given up on once the deadline passes.
+    receiver.handle_datagram(timestamp, &mut datagrams[(WINDOW_SIZE * 4) - 1].clone());
+    receiver.poll(timestamp + 9);
+    assert_eq!(receiver.cycle(), WINDOW_SIZE * 2);
+    assert!(handled_cycles().is_empty());
+    assert!(skipped.lock().unwrap().is_empty());
+    assert_eq!(receiver.stats().cycles_timed_out, 0);
+
+    receiver.poll(timestamp + 10);
+    assert_eq!(receiver.cycle(), WINDOW_SIZE * 4);
+    assert_eq!(
+        handled_cycles(),
+        (((WINDOW_SIZE as u64) * 3)..((WINDOW_SIZE as u64) * 4)).collect::<Vec<_>>()
+    );
+    assert_eq!(receiver.stats().cycles_timed_out, WINDOW_SIZE as u64);
+    assert_eq!(
+        *skipped.lock().unwrap(),
+        (((WINDOW_SIZE as u64) * 2)..((WINDOW_SIZE as u64) * 3)).collect::<Vec<_>>()
+    );
+}
+
+fn skipped<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    // Alias constants so they're less painful to read.
+    #[allow(non_snake_case)]
+    let MAX_CYCLE: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::MAX_CYCLE;
+    #[allow(non_snake_case)]
+    let MAX_BUFFERED: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::MAX_BUFFERED;
+
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let skipped = Arc::new(Mutex::new(Vec::new()));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSkippedSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSkippedSink {
+            handled: handled.clone(),
+            skipped: skipped.clone(),
+        },
+    );
+
+    // Catching up past lost datagrams reports every cycle that never arrived, including across
+    // the cycle wrapping.
+    let mut expected_skipped = Vec::new();
+    let mut local_cycle = 0;
+    let mut previously_received = 0..0;
+    for i in 0..(MAX_CYCLE + 64)
+    {
+        let mut datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap());
+        if i % 64 != 63
+        {
+            continue;
+        }
+
+        receiver.handle_datagram(timestamp, &mut datagram);
+        let received = ((i + 1) - WINDOW_SIZE)..(i + 1);
+        let caught_up = (i + 1) - MAX_BUFFERED;
+        expected_skipped.extend(
+            (local_cycle..caught_up)
+                .filter(|cycle| !previously_received.contains(cycle))
+                .map(|cycle| cycle as u64),
+        );
+        local_cycle = caught_up;
+
+        assert_eq!(*skipped.lock().unwrap(), expected_skipped);
+        assert_eq!(
+            std::mem::take(&mut *handled.lock().unwrap()),
+            received.clone().map(|cycle| (cycle as u64) + 1).collect::<Vec<_>>()
+        );
+        previously_received = received;
+    }
+    assert_eq!(receiver.stats().cycles_skipped, expected_skipped.len() as u64);
+}
+
+fn keepalive<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    for keepalive_period in [None, Some(10)]
+    {
+        let source_counter = Arc::new(AtomicU64::new(0));
+        let sink_counter = Arc::new(AtomicU64::new(0));
+        let handled_counter = Arc::new(AtomicU64::new(0));
+
+        let sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+            key,
+            TestSource {
+                counter: source_counter.clone(),
+                accumulator: 0,
+                period: 50,
+            },
+        );
+        let mut sender = match keepalive_period
+        {
+            Some(keepalive_period) => sender.with_keepalive(keepalive_period),
+            None => sender,
+        };
+        let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+            key,
+            TestSink {
+                counter: sink_counter.clone(),
+                handled: handled_counter.clone(),
+            },
+        );
+
+        // Idle stretches between input go quiet, unless kept alive.
+        let mut last_transmit = 0;
+        let mut max_gap = 0;
+        for timestamp in 0..1000
+        {
+            if let Some(datagram) = sender.poll_datagram(timestamp)
+            {
+                receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+                max_gap = std::cmp::max(max_gap, timestamp - last_transmit);
+                last_transmit = timestamp;
+            }
+            assert_eq!(receiver.cycle(), sender.cycle());
+        }
+        match keepalive_period
+        {
+            Some(keepalive_period) => assert_eq!(max_gap, keepalive_period),
+            None => assert!(max_gap > 40),
+        }
+
+        // Keepalives carry no input.
+        assert_eq!(source_counter.load(Ordering::Relaxed), 20);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), 20);
+        assert_eq!(handled_counter.load(Ordering::Relaxed), 20);
+    }
+}
+
+fn acknowledged<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let timestamp = 0;
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    // Alias constants so they're less painful to read.
+    #[allow(non_snake_case)]
+    let HEADER_SIZE: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::HEADER_SIZE;
+    #[allow(non_snake_case)]
+    let LENGTH_SIZE: usize = Constants::<SIZE, WINDOW_SIZE, CipherType>::LENGTH_SIZE;
+    let single_size =
+        HEADER_SIZE + (LENGTH_SIZE + SIZE).next_multiple_of(CipherType::BLOCK_SIZE) + CipherType::TAG_SIZE;
+
+    // Acknowledged every cycle, only the newest slot is sent.
+    for _ in 0..100
+    {
+        let datagram = sender.poll_datagram(timestamp).unwrap();
+        assert_eq!(datagram.len(), single_size);
+        receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+        sender.acknowledge(receiver.acknowledgement());
+        assert_eq!(receiver.cycle(), sender.cycle());
+    }
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 100);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), 100);
+
+    // Without an acknowledgement for a lost datagram, redundancy comes back to cover it.
+    sender.poll_datagram(timestamp).unwrap();
+    let datagram = sender.poll_datagram(timestamp).unwrap();
+    match WINDOW_SIZE > 1
+    {
+        true => assert!(datagram.len() > single_size),
+        false => assert_eq!(datagram.len(), single_size),
+    }
+    receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+    sender.acknowledge(receiver.acknowledgement());
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 102);
+    assert_eq!(
+        handled_counter.load(Ordering::Relaxed),
+        std::cmp::min(WINDOW_SIZE, 2) as u64 + 100
+    );
+
+    // Acknowledgements past what's been sent are held to it, and the newest slot still goes out.
+    sender.acknowledge(u64::MAX);
+    let datagram = sender.poll_datagram(timestamp).unwrap();
+    assert_eq!(datagram.len(), single_size);
+    receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+    assert_eq!(sink_counter.load(Ordering::Relaxed), 103);
+    assert_eq!(
+        handled_counter.load(Ordering::Relaxed),
+        std::cmp::min(WINDOW_SIZE, 2) as u64 + 101
+    );
+
+    // Acknowledgements sent over the wire only count for the Session they name, and only once
+    // they check out under the key they were sent under.
+    let mut acknowledgement = receiver.acknowledgement_datagram(1, Mirroring::Voice);
+    assert_eq!(
+        sender.check_acknowledgement(1, &acknowledgement),
+        Some(receiver.acknowledgement())
+    );
+    assert_eq!(sender.check_acknowledgement(2, &acknowledgement), None);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement[1..]), None);
+    acknowledgement[10] ^= 1;
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), None);
+
+    let forger: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        0xBEEFDEADBEEFDEAD,
+        TestSink {
+            counter: Arc::new(AtomicU64::new(0)),
+            handled: Arc::new(AtomicU64::new(0)),
+        },
+    );
+    assert_eq!(
+        sender.check_acknowledgement(1, &forger.acknowledgement_datagram(1, Mirroring::Voice)),
+        None
+    );
+
+    // Heartbeats likewise only move the Session they name, once they check out.
+    let mut heartbeat = sender.heartbeat_datagram(1, Mirroring::Voice);
+    assert!(matches!(
+        receiver.check_heartbeat(1, &heartbeat),
+        Some(Mirroring::Voice)
+    ));
+    assert!(receiver.check_heartbeat(2, &heartbeat).is_none());
+    assert!(receiver.check_heartbeat(1, &heartbeat[1..]).is_none());
+    heartbeat[11] = Mirroring::AudioVideo as u8;
+    assert!(receiver.check_heartbeat(1, &heartbeat).is_none());
+    assert!(forger
+        .check_heartbeat(1, &sender.heartbeat_datagram(1, Mirroring::Voice))
+        .is_none());
+
+    let acknowledgement = receiver.acknowledgement_datagram(1, Mirroring::Voice);
+    sender.rekey(1, key);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), None);
+    assert!(receiver
+        .check_heartbeat(1, &sender.heartbeat_datagram(1, Mirroring::Voice))
+        .is_none());
+}
+
+fn parity<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    // Both modes through the same lossy channel, which drops a burst of two datagrams every
+    // twelve cycles.
+    let mut sent = [0; 2];
+    for (mode, redundancy) in [
+        Redundancy::Repetition,
+        Redundancy::Parity {
+            group: 3,
+            interleave: 2,
+        },
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let source_counter = Arc::new(AtomicU64::new(0));
+        let sink_counter = Arc::new(AtomicU64::new(0));
+        let handled_counter = Arc::new(AtomicU64::new(0));
+
+        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+            key,
+            TestSource {
+                counter: source_counter.clone(),
+                accumulator: 0,
+                period: 1,
+            },
+        )
+        .with_redundancy(redundancy);
+        let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+            key,
+            TestSink {
+                counter: sink_counter.clone(),
+                handled: handled_counter.clone(),
+            },
+        );
+
+        for timestamp in 0..600
+        {
+            let lost = sender.cycle() % 12 < 2;
+            if let Some(datagram) = sender.poll_datagram(timestamp)
+            {
+                sent[mode] += datagram.len();
+                if !lost
+                {
+                    receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+                }
+            }
+            if let Some(datagram) = sender.poll_parity(timestamp)
+            {
+                sent[mode] += datagram.len();
+                receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+            }
+        }
+
+        // Repetition only covers bursts its window spans, parity covers any burst up to its
+        // interleave.
+        let recovered = match redundancy
+        {
+            Redundancy::Repetition => WINDOW_SIZE > 2,
+            Redundancy::Parity { .. } => true,
+        };
+        assert_eq!(source_counter.load(Ordering::Relaxed), 600);
+        assert_eq!(sink_counter.load(Ordering::Relaxed), 600);
+        match recovered
+        {
+            true =>
+            {
+                assert_eq!(handled_counter.load(Ordering::Relaxed), 600);
+                assert_eq!(receiver.cycle(), sender.cycle());
+            }
+            false => assert_eq!(handled_counter.load(Ordering::Relaxed), 500),
+        }
+        assert_eq!(
+            receiver.stats().slots_recovered,
+            match redundancy
+            {
+                Redundancy::Repetition => 0,
+                Redundancy::Parity { .. } => 100,
+            }
+        );
+    }
+
+    // And does so with fewer bytes wherever repetition would cover the burst.
+    if WINDOW_SIZE > 2
+    {
+        assert!(sent[1] < sent[0], "{} >= {}", sent[1], sent[0]);
+    }
+}
+
+fn parity_idle<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    // Input only every other poll, so each cycle is polled once without transmitting before it
+    // goes out, including the last of each group.
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 2,
+        },
+    )
+    .with_redundancy(Redundancy::Parity {
+        group: 2,
+        interleave: 1,
+    });
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    // The first member of every group is lost, and only comes back through parity.
+    let mut parity = 0;
+    for timestamp in 0..400
+    {
+        let lost = sender.cycle() % 2 == 0;
+        if let Some(datagram) = sender.poll_datagram(timestamp)
+            && !lost
+        {
+            receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+        }
+        if let Some(datagram) = sender.poll_parity(timestamp)
+        {
+            parity += 1;
+            receiver.handle_datagram(timestamp, &mut Box::<[u8]>::from(datagram));
+        }
+    }
+
+    // One parity datagram per group, each sealed once, covering both members.
+    assert_eq!(sender.cycle(), 200);
+    assert_eq!(parity, 100);
+    assert_eq!(source_counter.load(Ordering::Relaxed), 200);
+    assert_eq!(handled_counter.load(Ordering::Relaxed), 200);
+    assert_eq!(receiver.stats().slots_recovered, 100);
+    assert_eq!(receiver.stats().datagrams_replayed, 0);
+    assert_eq!(receiver.stats().authentication_failures, 0);
+}
+
+fn unsynchronized<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let source_counter = Arc::new(AtomicU64::new(0));
+    let sink_counter = Arc::new(AtomicU64::new(0));
+    let handled_counter = Arc::new(AtomicU64::new(0));
+
+    let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+        key,
+        TestSource {
+            counter: source_counter.clone(),
+            accumulator: 0,
+            period: 1,
+        },
+    );
+    let mut receiver: Receiver<TestSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+        key,
+        TestSink {
+            counter: sink_counter.clone(),
+            handled: handled_counter.clone(),
+        },
+    );
+
+    // The sender's clock is half a wrap ahead of the receiver's, which doesn't matter as long as
+    // datagrams take about as long as each other to arrive.
+    for tick in 0..10
+    {
+        let now = Instant::from_millis(tick * 40);
+        let datagram = sender.poll_datagram((now + 30000).timestamp()).unwrap();
+        receiver.handle_datagram((now + 5).timestamp(), &mut datagram.to_vec());
+    }
+    assert_eq!(receiver.stats().datagrams_accepted, 10);
+    assert_eq!(receiver.stats().datagrams_stale, 0);
+
+    // Held up for three seconds on the way, the next cycle is stale all the same.
+    let now = Instant::from_millis(400);
+    let datagram = sender.poll_datagram((now + 30000).timestamp()).unwrap();
+    receiver.handle_datagram((now + 3005).timestamp(), &mut datagram.to_vec());
+    assert_eq!(receiver.stats().datagrams_accepted, 10);
+    assert_eq!(receiver.stats().datagrams_stale, 1);
+
+    // Anything on time afterwards is taken as usual.
+    let now = Instant::from_millis(3400);
+    let datagram = sender.poll_datagram((now + 30000).timestamp()).unwrap();
+    receiver.handle_datagram((now + 5).timestamp(), &mut datagram.to_vec());
+    assert_eq!(receiver.stats().datagrams_accepted, 11);
+    assert_eq!(receiver.stats().datagrams_stale, 1);
+}
+
+fn buffered<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    // Steady transit, then transit varying by up to 8 on top of it.
+    for extra_transits in [[0; 5], [0, 6, 2, 8, 4]]
+    {
+        let source_counter = Arc::new(AtomicU64::new(0));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+
+        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+            key,
+            TestSource {
+                counter: source_counter.clone(),
+                accumulator: 0,
+                period: 1,
+            },
+        );
+        let mut receiver: Receiver<TestMetadataSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+            key,
+            TestMetadataSink {
+                handled: handled.clone(),
+                skipped: skipped.clone(),
+            },
+        )
+        .with_delivery(Delivery::Buffered {
+            min_delay: 5,
+            max_delay: 40,
+        });
+
+        // Send every 10 ticks, by a clock ahead of the receiver's, and release on every tick.
+        let mut in_flight = Vec::new();
+        let mut released = Vec::new();
+        for timestamp in 0u16..2100
+        {
+            if timestamp % 10 == 0
+            {
+                let cycle = sender.cycle();
+                let datagram = Box::<[u8]>::from(sender.poll_datagram(timestamp + 1000).unwrap());
+                in_flight.push((timestamp + 5 + extra_transits[cycle % 5], datagram));
+            }
+            in_flight.sort_by_key(|(arrival, _)| *arrival);
+            while let Some((arrival, _)) = in_flight.first()
+                && *arrival == timestamp
+            {
+                let (_, mut datagram) = in_flight.remove(0);
+                receiver.handle_datagram(timestamp, &mut datagram);
+            }
+
+            receiver.poll(timestamp);
+            for (metadata, counter) in std::mem::take(&mut *handled.lock().unwrap())
+            {
+                assert_eq!(counter, metadata.cycle + 1);
+                released.push((metadata.cycle, timestamp));
+            }
+        }
+
+        // Everything is released in order, none of it given up on.
+        assert!(skipped.lock().unwrap().is_empty());
+        assert!(released.len() >= 200);
+        assert!(released
+            .iter()
+            .enumerate()
+            .all(|(index, (cycle, _))| *cycle == index as u64));
+
+        // Once settled, input is released at a steady lag after it was sent, past when even the
+        // slowest of it arrives.
+        let lags = released[100..]
+            .iter()
+            .map(|(cycle, timestamp)| timestamp - ((*cycle as u16) * 10))
+            .collect::<Vec<_>>();
+        let min_lag = *lags.iter().min().unwrap();
+        let max_lag = *lags.iter().max().unwrap();
+        assert!(min_lag >= 5 + extra_transits.iter().max().unwrap());
+        match extra_transits == [0; 5]
+        {
+            true =>
+            {
+                assert_eq!(max_lag, min_lag);
+                assert_eq!(receiver.stats().jitter, 0);
+                assert_eq!(receiver.stats().playout_delay, 5);
+            }
+            false =>
+            {
+                assert!(max_lag - min_lag <= 2, "{} - {}", max_lag, min_lag);
+                assert!(receiver.stats().jitter > 0);
+                assert!((6..40).contains(&receiver.stats().playout_delay));
+            }
+        }
+    }
+}
+
+fn wraparound<CipherType, const SIZE: usize, const WINDOW_SIZE: usize>()
+where
+    CipherType: Cipher,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::DATAGRAM_SIZE]:,
+    [(); <Constants<SIZE, WINDOW_SIZE, CipherType>>::MAX_BUFFERED]:,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+
+    for delivery in [
+        Delivery::Ordered { deadline: 100 },
+        Delivery::Buffered {
+            min_delay: 5,
+            max_delay: 40,
+        },
+    ]
+    {
+        let source_counter = Arc::new(AtomicU64::new(0));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+
+        let mut sender: Sender<TestSource, SIZE, WINDOW_SIZE, CipherType> = Sender::new(
+            key,
+            TestSource {
+                counter: source_counter.clone(),
+                accumulator: 0,
+                period: 1,
+            },
+        );
+        let mut receiver: Receiver<TestMetadataSink, SIZE, WINDOW_SIZE, CipherType> = Receiver::new(
+            key,
+            TestMetadataSink {
+                handled: handled.clone(),
+                skipped: skipped.clone(),
+            },
+        )
+        .with_delivery(delivery);
+
+        // Send every 40 ticks, by a clock ahead of the receiver's, for a little over three wraps
+        // of the wire timestamps.  Each datagram arrives 5 ticks later.
+        let mut released = 0;
+        for tick in 0..5000
+        {
+            let now = Instant::from_millis(tick * 40);
+            let datagram = sender.poll_datagram((now + 1000).timestamp()).unwrap();
+            receiver.handle_datagram((now + 5).timestamp(), &mut datagram.to_vec());
+            receiver.poll((now + 5).timestamp());
+            receiver.poll((now + 20).timestamp());
+
+            for (metadata, counter) in std::mem::take(&mut *handled.lock().unwrap())
+            {
+                assert_eq!(metadata.cycle, released);
+                assert_eq!(counter, metadata.cycle + 1);
+                released += 1;
+            }
+        }
+
+        // Everything is released in order, none of it given up on.
+        assert!(skipped.lock().unwrap().is_empty());
+        assert!(released >= 4999, "{}", released);
+        assert_eq!(receiver.stats().cycles_timed_out, 0);
+        assert_eq!(receiver.stats().datagrams_stale, 0);
+    }
+}
+
+fn snapshots<CipherType>()
+where
+    CipherType: Cipher,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let max_snapshot_size = 4096;
+    let max_datagram_size = 128;
+
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let snapshot = Arc::new(Mutex::new(Vec::new()));
+
+    let timestamp = 0;
+
+    let mut sender: SnapshotSender<TestSnapshotSource, CipherType> = SnapshotSender::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSource {
+            snapshots: snapshots.clone(),
+        },
+    )
+    .with_channel(5)
+    .with_max_datagram_size(max_datagram_size);
+    let mut receiver: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: handled.clone(),
+            snapshot: snapshot.clone(),
+        },
+    )
+    .with_channel(5)
+    .with_max_datagram_size(max_datagram_size);
+    let layout = *sender.layout();
+    assert_eq!(layout, *receiver.layout());
+    assert!(layout.datagram_size <= max_datagram_size);
+    assert!(layout.max_fragments > 2);
+    assert_eq!(receiver.acknowledgement(), 0);
+
+    // Nothing goes out until the Source has something.
+    assert!(!sender.poll_snapshot());
+    assert!(sender.poll_datagram(timestamp).is_none());
+
+    // Snapshots come back whole whatever order their fragments arrive in, whether they take the
+    // most fragments there can be, none at all, a single one or just over it.  Mirrored copies
+    // are only duplicates, or stale once the snapshot is delivered, and copies on the same lane
+    // are replays.
+    let lengths = [max_snapshot_size, 0, 1, layout.fragment_size, layout.fragment_size + 1];
+    let mut duplicates = 0;
+    let mut replayed = 0;
+    let mut stale = 0;
+    let mut accepted = 0;
+    for (sequence, length) in lengths.iter().copied().enumerate()
+    {
+        let sequence = sequence as u64;
+        snapshots.lock().unwrap().push(test_snapshot(sequence, length));
+        assert!(sender.poll_snapshot());
+        assert_eq!(sender.sequence(), sequence);
+
+        let mut datagrams = Vec::new();
+        while let Some(datagram) = sender.poll_datagram(timestamp)
+        {
+            assert!(datagram.len() <= layout.datagram_size);
+            let datagram = Box::<[u8]>::from(datagram);
+            datagrams.push((datagram, Box::<[u8]>::from(sender.mirror_datagram(Mirroring::Voice))));
+        }
+        assert_eq!(datagrams.len(), layout.fragments(length));
+
+        for (i, (datagram, mirrored)) in datagrams.iter().rev().enumerate()
+        {
+            receiver.handle_datagram(timestamp, &mut datagram.clone());
+            receiver.handle_datagram(timestamp, &mut mirrored.clone());
+            accepted += 1;
+            match i + 1 == datagrams.len()
+            {
+                true => stale += 1,
+                false =>
+                {
+                    duplicates += 1;
+                    receiver.handle_datagram(timestamp, &mut datagram.clone());
+                    receiver.handle_datagram(timestamp, &mut mirrored.clone());
+                    replayed += 2;
+                    assert_eq!(handled.lock().unwrap().len(), sequence as usize);
+                }
+            }
+        }
+        assert_eq!(handled.lock().unwrap().last(), Some(&sequence));
+        assert_eq!(*snapshot.lock().unwrap(), test_snapshot(sequence, length));
+        assert_eq!(receiver.acknowledgement(), sequence + 1);
+    }
+
+    // A newer snapshot gives up on whatever's left of an older one, and the rest of the older one
+    // is stale once it arrives.
+    let superseded_sequence = lengths.len() as u64;
+    let sequence = superseded_sequence + 1;
+    snapshots
+        .lock()
+        .unwrap()
+        .push(test_snapshot(superseded_sequence, max_snapshot_size));
+    snapshots
+        .lock()
+        .unwrap()
+        .push(test_snapshot(sequence, max_snapshot_size));
+    assert!(sender.poll_snapshot());
+    let superseded_datagrams =
+        std::iter::from_fn(|| sender.poll_datagram(timestamp).map(Box::<[u8]>::from)).collect::<Vec<_>>();
+    assert!(sender.poll_snapshot());
+    let datagrams = std::iter::from_fn(|| sender.poll_datagram(timestamp).map(Box::<[u8]>::from)).collect::<Vec<_>>();
+
+    let (early, late) = superseded_datagrams.split_at(superseded_datagrams.len() / 2);
+    for datagram in early.iter()
+    {
+        receiver.handle_datagram(timestamp, &mut datagram.clone());
+        accepted += 1;
+    }
+    for datagram in datagrams.iter()
+    {
+        receiver.handle_datagram(timestamp, &mut datagram.clone());
+        accepted += 1;
+    }
+    for datagram in late.iter()
+    {
+        receiver.handle_datagram(timestamp, &mut datagram.clone());
+        stale += 1;
+    }
+    assert_eq!(handled.lock().unwrap().last(), Some(&sequence));
+    assert_eq!(*snapshot.lock().unwrap(), test_snapshot(sequence, max_snapshot_size));
+    assert_eq!(handled.lock().unwrap().len(), lengths.len() + 1);
+    assert_eq!(receiver.acknowledgement(), sequence + 1);
+
+    // Snapshots over the maximum never go out, and leave the sequence where it was.
+    snapshots
+        .lock()
+        .unwrap()
+        .push(test_snapshot(sequence + 1, max_snapshot_size + 1));
+    assert!(!sender.poll_snapshot());
+    assert!(sender.poll_datagram(timestamp).is_none());
+    assert_eq!(sender.sequence(), sequence);
+    assert_eq!(
+        sender.stats(),
+        SnapshotSenderStats {
+            snapshots_polled: sequence + 1,
+            snapshots_oversized: 1,
+        }
+    );
+
+    // Datagrams for another channel are turned away before anything else is looked at.
+    let mut other_receiver: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: handled.clone(),
+            snapshot: snapshot.clone(),
+        },
+    )
+    .with_channel(6)
+    .with_max_datagram_size(max_datagram_size);
+    other_receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+    assert_eq!(other_receiver.stats().datagrams_unknown_channel, 1);
+    assert_eq!(other_receiver.acknowledgement(), 0);
+
+    assert_eq!(
+        receiver.stats(),
+        SnapshotStats {
+            datagrams_accepted: accepted,
+            datagrams_stale: stale,
+            datagrams_replayed: replayed,
+            duplicate_fragments: duplicates,
+            snapshots_delivered: (lengths.len() + 1) as u64,
+            snapshots_superseded: 1,
+            ..Default::default()
+        }
+    );
+}
+
+fn snapshot_deltas<CipherType>()
+where
+    CipherType: Cipher,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let max_snapshot_size = 4096;
+    let max_datagram_size = 128;
+
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let snapshot = Arc::new(Mutex::new(Vec::new()));
+
+    let timestamp = 0;
+
+    let mut sender: SnapshotSender<TestSnapshotSource, CipherType> = SnapshotSender::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSource {
+            snapshots: snapshots.clone(),
+        },
+    )
+    .with_max_datagram_size(max_datagram_size)
+    .with_baselines(2);
+    let mut receiver: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: handled.clone(),
+            snapshot: snapshot.clone(),
+        },
+    )
+    .with_max_datagram_size(max_datagram_size)
+    .with_baselines(2);
+    let layout = *sender.layout();
+
+    let send = |sender: &mut SnapshotSender<TestSnapshotSource, CipherType>, next: &[u8]| {
+        snapshots.lock().unwrap().push(next.to_vec());
+        assert!(sender.poll_snapshot());
+        std::iter::from_fn(|| sender.poll_datagram(timestamp).map(Box::<[u8]>::from)).collect::<Vec<_>>()
+    };
+
+    // Nothing is acknowledged yet, so the first snapshot goes out whole.
+    let mut expected = test_snapshot(0, max_snapshot_size);
+    let datagrams = send(&mut sender, &expected);
+    assert_eq!(sender.encoding(), Encoding::Full);
+    assert_eq!(datagrams.len(), layout.max_fragments);
+    assert!(datagrams.iter().all(|datagram| datagram[6] == 0x00));
+    for datagram in datagrams.iter()
+    {
+        receiver.handle_datagram(timestamp, &mut datagram.clone());
+    }
+    assert_eq!(*snapshot.lock().unwrap(), expected);
+    assert_eq!(receiver.acknowledgement(), 1);
+
+    // Acknowledgements sent over the wire only count for the Session they name, and only once
+    // they check out under the key they were sent under.
+    let mut acknowledgement = receiver.acknowledgement_datagram(1, Mirroring::Voice);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), Some(1));
+    assert_eq!(sender.check_acknowledgement(2, &acknowledgement), None);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement[1..]), None);
+    let forger: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        0xBEEFDEADBEEFDEAD,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: Arc::new(Mutex::new(Vec::new())),
+            snapshot: Arc::new(Mutex::new(Vec::new())),
+        },
+    )
+    .with_max_datagram_size(max_datagram_size);
+    assert_eq!(
+        sender.check_acknowledgement(1, &forger.acknowledgement_datagram(1, Mirroring::Voice)),
+        None
+    );
+    acknowledgement[10] ^= 1;
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), None);
+    acknowledgement[10] ^= 1;
+
+    // Acknowledged, the next ones only carry what changed from it, however far back it is, and
+    // are rebuilt whole, whether they grow or shrink.
+    sender.acknowledge(sender.check_acknowledgement(1, &acknowledgement).unwrap());
+    for (sequence, length) in [(1, max_snapshot_size - 3), (2, max_snapshot_size)]
+    {
+        expected.truncate(length);
+        expected.resize(length, 0);
+        expected[100] ^= 0xFF;
+        let datagrams = send(&mut sender, &expected);
+        assert_eq!(sender.encoding(), Encoding::Delta);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0][6], 0x01);
+        receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+        assert_eq!(handled.lock().unwrap().last(), Some(&sequence));
+        assert_eq!(*snapshot.lock().unwrap(), expected);
+        assert_eq!(receiver.acknowledgement(), sequence + 1);
+    }
+
+    // Past the baselines kept, snapshots go out whole again until the next acknowledgement.
+    expected[0] ^= 0xFF;
+    let datagrams = send(&mut sender, &expected);
+    assert_eq!(sender.encoding(), Encoding::Full);
+    for datagram in datagrams.iter()
+    {
+        receiver.handle_datagram(timestamp, &mut datagram.clone());
+    }
+    assert_eq!(handled.lock().unwrap().last(), Some(&3));
+    assert_eq!(*snapshot.lock().unwrap(), expected);
+
+    // Acknowledgements only move forward, and never past what's been sent.
+    sender.acknowledge(receiver.acknowledgement());
+    sender.acknowledge(1);
+    sender.acknowledge(1000);
+    expected[1] ^= 0xFF;
+    let datagrams = send(&mut sender, &expected);
+    assert_eq!(sender.encoding(), Encoding::Delta);
+    receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+    assert_eq!(handled.lock().unwrap().last(), Some(&4));
+    assert_eq!(*snapshot.lock().unwrap(), expected);
+
+    // A Receiver that never had the baseline can't rebuild the snapshot, and leaves it be.
+    let mut other_receiver: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: handled.clone(),
+            snapshot: snapshot.clone(),
+        },
+    )
+    .with_max_datagram_size(max_datagram_size)
+    .with_baselines(2);
+    sender.acknowledge(receiver.acknowledgement());
+    expected[2] ^= 0xFF;
+    let datagrams = send(&mut sender, &expected);
+    assert_eq!(sender.encoding(), Encoding::Delta);
+    other_receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+    other_receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+    assert_eq!(other_receiver.acknowledgement(), 0);
+    assert_eq!(other_receiver.stats().snapshots_missing_baseline, 1);
+    assert_eq!(other_receiver.stats().datagrams_stale, 1);
+
+    receiver.handle_datagram(timestamp, &mut datagrams[0].clone());
+    assert_eq!(handled.lock().unwrap().last(), Some(&5));
+    assert_eq!(*snapshot.lock().unwrap(), expected);
+    assert_eq!(receiver.stats().snapshots_missing_baseline, 0);
+    assert_eq!(receiver.stats().snapshots_delivered, 6);
+}
+
+fn snapshot_outage<CipherType>()
+where
+    CipherType: Cipher,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let max_snapshot_size = 64;
+
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let snapshot = Arc::new(Mutex::new(Vec::new()));
+
+    let timestamp = 0;
+
+    let mut sender: SnapshotSender<TestSnapshotSource, CipherType> = SnapshotSender::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSource {
+            snapshots: snapshots.clone(),
+        },
+    );
+    let mut receiver: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: handled.clone(),
+            snapshot: snapshot.clone(),
+        },
+    );
+    let layout = *sender.layout();
+
+    let send = |sender: &mut SnapshotSender<TestSnapshotSource, CipherType>, sequence: u64| {
+        snapshots
+            .lock()
+            .unwrap()
+            .push(test_snapshot(sequence, max_snapshot_size));
+        assert!(sender.poll_snapshot());
+        assert_eq!(sender.sequence(), sequence);
+        Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap())
+    };
+
+    let first = send(&mut sender, 0);
+    receiver.handle_datagram(timestamp, &mut first.clone());
+    assert_eq!(handled.lock().unwrap().last(), Some(&0));
+
+    // An outage long enough for the sequence on the wire to come back around more than once
+    // doesn't leave the Receiver lost once snapshots get through again.
+    for sequence in 1..150_000
+    {
+        send(&mut sender, sequence);
+    }
+    let datagram = send(&mut sender, 150_000);
+    receiver.handle_datagram(timestamp, &mut datagram.clone());
+    assert_eq!(handled.lock().unwrap().last(), Some(&150_000));
+    assert_eq!(*snapshot.lock().unwrap(), test_snapshot(150_000, max_snapshot_size));
+    assert_eq!(receiver.acknowledgement(), 150_001);
+
+    // Whatever was sent before it is stale, and the rest of the sequence is checked along with
+    // the header.
+    receiver.handle_datagram(timestamp, &mut first.clone());
+    let mut datagram = send(&mut sender, 150_001);
+    datagram[layout.header_size - 4] ^= 1;
+    receiver.handle_datagram(timestamp, &mut datagram);
+
+    assert_eq!(
+        receiver.stats(),
+        SnapshotStats {
+            datagrams_accepted: 2,
+            datagrams_stale: 1,
+            header_check_failures: 1,
+            snapshots_delivered: 2,
+            ..Default::default()
+        }
+    );
+}
+
+fn snapshot_rekeyed<CipherType>()
+where
+    CipherType: Cipher,
+{
+    let key = 0xDEADBEEFDEADBEEF;
+    let next_key = 0xBEEFDEADBEEFDEAD;
+    let max_snapshot_size = 64;
+
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let snapshot = Arc::new(Mutex::new(Vec::new()));
+
+    let mut sender: SnapshotSender<TestSnapshotSource, CipherType> = SnapshotSender::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSource {
+            snapshots: snapshots.clone(),
+        },
+    );
+    let mut receiver: SnapshotReceiver<TestSnapshotSink, CipherType> = SnapshotReceiver::new(
+        key,
+        max_snapshot_size,
+        TestSnapshotSink {
+            handled: handled.clone(),
+            snapshot: snapshot.clone(),
+        },
+    )
+    .with_key_grace_period(100);
+
+    let mut sequence = 0;
+    let mut send = |sender: &mut SnapshotSender<TestSnapshotSource, CipherType>, timestamp: u16| {
+        snapshots
+            .lock()
+            .unwrap()
+            .push(test_snapshot(sequence, max_snapshot_size));
+        sequence += 1;
+        assert!(sender.poll_snapshot());
+        Box::<[u8]>::from(sender.poll_datagram(timestamp).unwrap())
+    };
+
+    // Sealed under the old key before the Sender hears about the new one.
+    let datagram_0 = send(&mut sender, 0);
+    let datagram_1 = send(&mut sender, 1000);
+    let datagram_2 = send(&mut sender, 1050);
+    receiver.handle_datagram(0, &mut datagram_0.clone());
+    receiver.rekey(1, next_key);
+
+    // The old key holds for as long as the Sender hasn't been heard from under the new one.
+    receiver.poll(1000);
+    receiver.handle_datagram(1000, &mut datagram_1.clone());
+    assert_eq!(*handled.lock().unwrap(), [0, 1]);
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 0);
+
+    // Acknowledgements are only taken under the key the Sender has now, or one announced to it.
+    let acknowledgement = receiver.acknowledgement_datagram(1, Mirroring::AudioVideo);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), None);
+    sender.announce_key(1, next_key);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), Some(2));
+
+    // The new key picks up where the old one left off, and from then on the old one only holds
+    // for the grace period.
+    sender.rekey(1, next_key);
+    assert_eq!(sender.check_acknowledgement(1, &acknowledgement), Some(2));
+    let datagram_3 = send(&mut sender, 1100);
+    receiver.handle_datagram(1100, &mut datagram_3.clone());
+    assert_eq!(*handled.lock().unwrap(), [0, 1, 3]);
+    assert_eq!(*snapshot.lock().unwrap(), test_snapshot(3, max_snapshot_size));
+    assert_eq!(receiver.stats().datagrams_accepted, 3);
+
+    receiver.handle_datagram(1150, &mut datagram_2.clone());
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 0);
+    assert_eq!(receiver.stats().datagrams_stale, 1);
+    receiver.handle_datagram(1200, &mut datagram_2.clone());
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 1);
+
+    // Epochs the Receiver hasn't been told about are dropped.
+    sender.rekey(2, key);
+    let datagram_4 = send(&mut sender, 1200);
+    receiver.handle_datagram(1200, &mut datagram_4.clone());
+    assert_eq!(*handled.lock().unwrap(), [0, 1, 3]);
+    assert_eq!(receiver.stats().datagrams_unknown_epoch, 2);
+    assert_eq!(receiver.stats().authentication_failures, 0);
+}